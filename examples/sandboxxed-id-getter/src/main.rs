@@ -1,3 +1,30 @@
+use audio_plugin_host::discovery;
+
 fn main() {
-    sandboxxed_id_getter::main();
+    // Scans a plugin and exits when started by `scan_out_of_process`.
+    discovery::scan_helper_main();
+
+    let options = discovery::ScanOptions {
+        extra_paths: std::env::args().skip(1).map(Into::into).collect(),
+        ..Default::default()
+    };
+
+    // This executable is its own helper.
+    let helper = discovery::OutOfProcessOptions {
+        helper: std::env::current_exe().expect("No current executable"),
+        ..Default::default()
+    };
+
+    discovery::scan_out_of_process(&options, &helper, |progress| {
+        print!("[{}/{}] {}", progress.scanned, progress.total, progress.path.display());
+
+        match progress.error {
+            Some(error) => println!(": {}", error),
+            None => println!(),
+        }
+
+        for descriptor in progress.descriptors {
+            println!("    {} ({}) by {}", descriptor.name, descriptor.id, descriptor.vendor);
+        }
+    });
 }
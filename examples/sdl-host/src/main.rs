@@ -1,11 +1,10 @@
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
 use audio_bus::AudioBus;
 use audio_plugin_host::*;
 use event::PluginIssuedEvent;
-use plugin::PluginInstance;
+use plugin::AudioHandle;
 use sdl2::audio::{AudioCallback, AudioDevice};
 use sdl2::{Sdl, VideoSubsystem};
 
@@ -25,27 +24,26 @@ fn main() {
     let descriptors = discovery::get_descriptor_from_file(&plugin_path);
     println!("Plugins in file: {:?}", descriptors);
 
-    let mut plugin = plugin::load(
+    let (mut plugin, audio) = plugin::load(
         &plugin_path,
         &descriptors.first().expect("No plugins in file").id,
         &host,
     )
-    .unwrap();
+    .unwrap()
+    .split();
 
     println!("IO configuration: {:?}", plugin.get_io_configuration());
 
-    let plugin = Arc::new(Mutex::new(plugin));
-
     let (sdl, video) = sdl();
-    let audio_device = SDLAudioDevice::new(&sdl, plugin.clone());
+    let audio_device = SDLAudioDevice::new(&sdl, audio);
     audio_device.device.resume();
     let mut window = video
-        .window(&plugin.lock().unwrap().descriptor.name, 1024, 769)
+        .window(&plugin.descriptor.name, 1024, 769)
         .build()
         .unwrap();
     let window_id = get_window_id(&window);
 
-    let (width, height) = plugin.lock().unwrap().show_editor(window_id, WindowIDType::this_platform()).unwrap();
+    let (width, height) = plugin.show_editor(window_id, WindowIDType::this_platform()).unwrap();
     window.set_size(width as u32, height as u32).unwrap();
 
     loop {
@@ -56,7 +54,7 @@ fn main() {
             }
         }
 
-        let events = plugin.lock().unwrap().get_events();
+        let events = plugin.get_events();
 
         if !events.is_empty() {
             println!("Received events: {:?}", events);
@@ -68,7 +66,7 @@ fn main() {
                     window.set_size(width as u32, height as u32).unwrap();
                 }
                 PluginIssuedEvent::Parameter(param) => {
-                    let param = plugin.lock().unwrap().get_parameter(param.parameter_id);
+                    let param = plugin.get_parameter(param.parameter_id);
                     println!("Parameter updated {:?}", param);
                 }
                 _ => {}
@@ -92,7 +90,7 @@ impl AudioCallback for SDLAudioDeviceCallback {
 
         // This does not support all IO configurations and is not real-time safe. This is just
         // for demonstration purposes.
-        let io = self.plugin.lock().unwrap().get_io_configuration();
+        let io = self.plugin.io_configuration();
         let mut input_buses = vec![];
         let mut output_buses = vec![];
         for bus in io.audio_inputs.iter() {
@@ -118,10 +116,10 @@ impl AudioCallback for SDLAudioDeviceCallback {
             ..Default::default()
         };
 
-        self.plugin.lock().unwrap().process(
+        self.plugin.process(
             &input_buses,
             &mut output_buses,
-            vec![],
+            &[],
             &process_details,
         );
 
@@ -203,7 +201,7 @@ const SAMPLE_RATE: u32 = 44100;
 pub struct SDLAudioDeviceCallback {
     pub block_size: BlockSize,
     pub sample_rate: SampleRate,
-    pub plugin: Arc<Mutex<PluginInstance>>,
+    pub plugin: AudioHandle,
 }
 
 pub struct SDLAudioDevice {
@@ -211,7 +209,7 @@ pub struct SDLAudioDevice {
 }
 
 impl SDLAudioDevice {
-    pub fn new(sdl_context: &Sdl, plugin: Arc<Mutex<PluginInstance>>) -> Self {
+    pub fn new(sdl_context: &Sdl, plugin: AudioHandle) -> Self {
         let audio_subsystem = sdl_context.audio().unwrap();
 
         let desired_spec = sdl2::audio::AudioSpecDesired {
@@ -221,10 +219,10 @@ impl SDLAudioDevice {
         };
 
         let device = audio_subsystem
-            .open_playback(None, &desired_spec, |spec| SDLAudioDeviceCallback {
+            .open_playback(None, &desired_spec, move |spec| SDLAudioDeviceCallback {
                 block_size: spec.samples as BlockSize,
                 sample_rate: spec.freq as SampleRate,
-                plugin: plugin.clone(),
+                plugin,
             })
             .unwrap();
 
@@ -0,0 +1,95 @@
+//! Catches heap allocations on the audio thread. Enabled with the `assert_no_alloc` feature.
+//!
+//! Register `AllocChecker` as the global allocator in your application:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static ALLOCATOR: audio_plugin_host::alloc_check::AllocChecker =
+//!     audio_plugin_host::alloc_check::AllocChecker;
+//! ```
+//!
+//! In debug builds any allocation or deallocation made while inside `AudioHandle::process` or
+//! `AudioHandle::process_f64` is logged, or aborts the process if `abort_on_alloc(true)` was
+//! called. Release builds only forward to the system allocator.
+//!
+//! Plugins are separate binaries with their own allocators so allocations made by the plugin
+//! itself are not seen.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+thread_local!(static FORBID_DEPTH: Cell<u32> = const { Cell::new(0) });
+
+static ABORT_ON_ALLOC: AtomicBool = AtomicBool::new(false);
+
+/// Global allocator that reports allocations made inside `forbid`.
+pub struct AllocChecker;
+
+unsafe impl GlobalAlloc for AllocChecker {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        check("alloc");
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        check("alloc_zeroed");
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        check("realloc");
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        check("dealloc");
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Abort instead of logging when an allocation is caught. Off by default.
+pub fn abort_on_alloc(abort: bool) {
+    ABORT_ON_ALLOC.store(abort, Ordering::Relaxed);
+}
+
+/// Runs `f` with allocation checking enabled on the current thread.
+pub(crate) fn forbid<T>(f: impl FnOnce() -> T) -> T {
+    if !cfg!(debug_assertions) {
+        return f();
+    }
+
+    // Decrements on drop so a panic inside `f` doesn't leave the thread marked.
+    struct Guard;
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            let _ = FORBID_DEPTH.try_with(|depth| depth.set(depth.get() - 1));
+        }
+    }
+
+    let _ = FORBID_DEPTH.try_with(|depth| depth.set(depth.get() + 1));
+    let _guard = Guard;
+
+    f()
+}
+
+fn check(operation: &'static str) {
+    let forbidden = FORBID_DEPTH.try_with(|depth| depth.get() > 0).unwrap_or(false);
+    if !forbidden {
+        return;
+    }
+
+    // Allow the report itself to allocate.
+    let saved_depth = FORBID_DEPTH.with(|depth| depth.replace(0));
+
+    eprintln!("[assert_no_alloc] Memory {} on the audio thread", operation);
+
+    if ABORT_ON_ALLOC.load(Ordering::Relaxed) {
+        std::process::abort();
+    }
+
+    FORBID_DEPTH.with(|depth| depth.set(saved_depth));
+}
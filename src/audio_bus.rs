@@ -1,7 +1,13 @@
-use crate::{error::{err, Error}, heapless_vec::HeaplessVec};
+use crate::{error::Error, host::Limits};
 
 pub struct AudioBus<'a, T> {
     pub data: &'a mut Vec<Vec<T>>,
+    /// Bit N is set if channel N holds the same value for the whole block. Set it on inputs so
+    /// plugins can skip work. CLAP plugins report it back on outputs after processing, other
+    /// formats leave it untouched.
+    pub constant_mask: u64,
+    /// Latency in samples from or to the audio interface. Only passed to CLAP plugins.
+    pub latency: u32,
     /// For `new_alloced`. If non-null this is freed on drop.
     owned_data: *mut Vec<Vec<T>>,
 }
@@ -13,6 +19,8 @@ impl<'a, T> AudioBus<'a, T> {
     pub fn new(data: &'a mut Vec<Vec<T>>) -> Self {
         AudioBus {
             data,
+            constant_mask: 0,
+            latency: 0,
             owned_data: std::ptr::null_mut(),
         }
     }
@@ -22,29 +30,56 @@ impl<'a, T> AudioBus<'a, T> {
     }
 }
 
-#[derive(Clone, Debug)]
-#[repr(C)]
+#[derive(Clone, Debug, Default)]
 /// Input and output configuration for the plugin.
 pub struct IOConfigutaion {
-    pub audio_inputs: HeaplessVec<AudioBusDescriptor, 16>,
-    pub audio_outputs: HeaplessVec<AudioBusDescriptor, 16>,
+    pub audio_inputs: Vec<AudioBusDescriptor>,
+    pub audio_outputs: Vec<AudioBusDescriptor>,
     pub event_inputs_count: i32,
 }
 
 impl IOConfigutaion {
+    /// Whether the buffers allocated for `limits` are big enough for this configuration.
+    pub fn within_limits(&self, limits: &Limits) -> bool {
+        let input_channels: usize = self.audio_inputs.iter().map(|b| b.channels).sum();
+        let output_channels: usize = self.audio_outputs.iter().map(|b| b.channels).sum();
+
+        self.audio_inputs.len() <= limits.max_buses
+            && self.audio_outputs.len() <= limits.max_buses
+            && input_channels <= limits.max_channels
+            && output_channels <= limits.max_channels
+    }
+
+    /// Same check as `matches` without building an error message so it's safe to call from the
+    /// audio thread.
+    pub fn fits<T>(&self, inputs: &[AudioBus<T>], outputs: &[AudioBus<T>]) -> bool {
+        self.audio_inputs.len() == inputs.len()
+            && self.audio_outputs.len() == outputs.len()
+            && self
+                .audio_inputs
+                .iter()
+                .zip(inputs)
+                .all(|(descriptor, bus)| descriptor.channels == bus.channels())
+            && self
+                .audio_outputs
+                .iter()
+                .zip(outputs)
+                .all(|(descriptor, bus)| descriptor.channels == bus.channels())
+    }
+
     pub fn matches<'a, T>(
         &self,
         inputs: &[AudioBus<'a, T>],
         outputs: &[AudioBus<'a, T>],
     ) -> Result<(), Error> {
         if self.audio_inputs.len() != inputs.len() || self.audio_outputs.len() != outputs.len() {
-            return err(&format!(
+            return Err(Error::IoMismatch(format!(
                 "Input/output count mismatch: expected {} inputs and {} outputs, got {} inputs and {} outputs",
                 self.audio_inputs.len(),
                 self.audio_outputs.len(),
                 inputs.len(),
                 outputs.len()
-            ));
+            )));
         }
 
         #[allow(clippy::needless_range_loop)]
@@ -52,11 +87,11 @@ impl IOConfigutaion {
             let channels = inputs[i].channels();
             let needed_channels = self.audio_inputs[i].channels;
             if channels != needed_channels {
-                return err(&format!(
+                return Err(Error::IoMismatch(format!(
                     "Input channel count mismatch: expected {}, got {}",
                     needed_channels,
                     channels
-                ));
+                )));
             }
         }
 
@@ -65,11 +100,11 @@ impl IOConfigutaion {
             let channels = outputs[i].channels();
             let needed_channels = self.audio_outputs[i].channels;
             if channels != needed_channels {
-                return err(&format!(
+                return Err(Error::IoMismatch(format!(
                     "Output channel count mismatch: expected {}, got {}",
                     needed_channels,
                     channels
-                ));
+                )));
             }
         }
 
@@ -83,6 +118,35 @@ pub struct AudioBusDescriptor {
     pub channels: usize,
 }
 
+/// For building an `IOConfigutaion` from C++.
+///
+/// # Safety
+/// `io_config` must be the pointer the C++ side was given.
+#[no_mangle]
+pub unsafe extern "C" fn push_audio_bus_to_io_config(
+    io_config: *mut IOConfigutaion,
+    is_input: bool,
+    channels: usize,
+) {
+    let io_config = &mut *io_config;
+    let bus = AudioBusDescriptor { channels };
+
+    if is_input {
+        io_config.audio_inputs.push(bus);
+    } else {
+        io_config.audio_outputs.push(bus);
+    }
+}
+
+/// For building an `IOConfigutaion` from C++.
+///
+/// # Safety
+/// `io_config` must be the pointer the C++ side was given.
+#[no_mangle]
+pub unsafe extern "C" fn set_event_inputs_in_io_config(io_config: *mut IOConfigutaion, count: i32) {
+    (*io_config).event_inputs_count = count;
+}
+
 impl<T> AudioBus<'_, T>
 where
     T: Default + Clone,
@@ -92,6 +156,8 @@ where
         let ptr = Box::into_raw(Box::new(buffer));
         AudioBus {
             data: unsafe { &mut *ptr },
+            constant_mask: 0,
+            latency: 0,
             owned_data: ptr,
         }
     }
@@ -0,0 +1,8 @@
+//! Helper executable for `discovery::scan_out_of_process`. Ship it next to the host executable.
+
+fn main() {
+    audio_plugin_host::discovery::scan_helper_main();
+
+    eprintln!("This is a helper for `discovery::scan_out_of_process` and isn't meant to be run directly.");
+    std::process::exit(1);
+}
@@ -0,0 +1,436 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "out_of_process_scan")]
+use super::{scan_file_out_of_process, OutOfProcessOptions, ScanResult};
+use super::{
+    find_plugin_files, get_descriptor_from_file, PluginDescriptor, ScanError, ScanOptions,
+    ScanProgress,
+};
+
+/// Bumped whenever the file format changes. Files with another version load as an empty cache.
+const CACHE_VERSION: u32 = 4;
+
+/// Scan results from previous runs so only new and changed plugin files are loaded again.
+#[derive(Clone, Debug, Default)]
+pub struct ScanCache {
+    files: BTreeMap<PathBuf, CachedFile>,
+}
+
+/// A plugin file or bundle in a `ScanCache`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CachedFile {
+    pub path: PathBuf,
+    /// Total size of the file, or of every file in the bundle.
+    pub size: u64,
+    /// Latest modification time of the file or any file in the bundle.
+    pub modified: SystemTime,
+    /// Hash of the contents, only computed when the size or modification time changed so a
+    /// file that was touched or copied without changing isn't scanned again.
+    pub hash: u64,
+    pub result: Result<Vec<PluginDescriptor>, ScanError>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    files: Vec<CachedFile>,
+}
+
+impl ScanCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a cache saved with `save`. Use `unwrap_or_default` to start with an empty cache when
+    /// the file doesn't exist yet.
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file: CacheFile = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if file.version != CACHE_VERSION {
+            return Ok(Self::default());
+        }
+
+        Ok(Self {
+            files: file
+                .files
+                .into_iter()
+                .map(|file| (file.path.clone(), file))
+                .collect(),
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let file = CacheFile {
+            version: CACHE_VERSION,
+            files: self.files.values().cloned().collect(),
+        };
+
+        // Written next to the destination first so a crash while saving can't corrupt the cache.
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        serde_json::to_writer(&mut writer, &file)?;
+        // The rename must not land before the data does.
+        writer.into_inner()?.sync_all()?;
+        std::fs::rename(&temp, path)
+    }
+
+    /// Same as `discovery::scan` but only files that are new or changed since they were cached
+    /// are loaded. Files that no longer exist are removed from the cache. Files without any
+    /// plugins are cached as `ScanError::NoPlugins` so `retry_failed` tries them again.
+    pub fn scan(
+        &mut self,
+        options: &ScanOptions,
+        progress: impl FnMut(ScanProgress<'_>),
+    ) -> Vec<PluginDescriptor> {
+        self.update(options, progress, |path| {
+            let descriptors = get_descriptor_from_file(path);
+            if descriptors.is_empty() {
+                return Err(ScanError::NoPlugins);
+            }
+
+            Ok(descriptors)
+        })
+            .into_iter()
+            .filter_map(|(_, result)| result.ok())
+            .flatten()
+            .collect()
+    }
+
+    /// Same as `discovery::scan_out_of_process` but only files that are new or changed since
+    /// they were cached are scanned. Failed files are remembered and only retried once they
+    /// change or after `retry_failed`.
+    #[cfg(feature = "out_of_process_scan")]
+    pub fn scan_out_of_process(
+        &mut self,
+        options: &ScanOptions,
+        helper: &OutOfProcessOptions,
+        progress: impl FnMut(ScanProgress<'_>),
+    ) -> Vec<ScanResult> {
+        self.update(options, progress, |path| scan_file_out_of_process(path, helper))
+            .into_iter()
+            .map(|(path, result)| ScanResult { path, result })
+            .collect()
+    }
+
+    /// Every plugin in the cache.
+    pub fn descriptors(&self) -> impl Iterator<Item = &PluginDescriptor> {
+        self.files
+            .values()
+            .filter_map(|file| file.result.as_ref().ok())
+            .flatten()
+    }
+
+    /// Files that failed to scan or are blacklisted.
+    pub fn failed(&self) -> impl Iterator<Item = (&Path, &ScanError)> {
+        self.files
+            .values()
+            .filter_map(|file| Some((file.path.as_path(), file.result.as_ref().err()?)))
+    }
+
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<&CachedFile> {
+        self.files.get(path.as_ref())
+    }
+
+    /// Removes a file so it's scanned again, also undoing `blacklist`.
+    pub fn remove(&mut self, path: impl AsRef<Path>) -> Option<CachedFile> {
+        self.files.remove(path.as_ref())
+    }
+
+    /// Never scans this file again, even if it changes, until it's `remove`d.
+    pub fn blacklist(&mut self, path: impl Into<PathBuf>, reason: impl Into<String>) {
+        let path = path.into();
+        let (size, modified) = file_stats(&path).unwrap_or((0, SystemTime::UNIX_EPOCH));
+        let hash = self.files.get(&path).map(|file| file.hash).unwrap_or(0);
+
+        self.files.insert(
+            path.clone(),
+            CachedFile {
+                path,
+                size,
+                modified,
+                hash,
+                result: Err(ScanError::Blacklisted(reason.into())),
+            },
+        );
+    }
+
+    /// Removes the files that failed to scan so the next scan tries them again. Blacklisted files
+    /// are kept.
+    pub fn retry_failed(&mut self) {
+        self.files.retain(|_, file| {
+            matches!(file.result, Ok(_) | Err(ScanError::Blacklisted(_)))
+        });
+    }
+
+    fn update(
+        &mut self,
+        options: &ScanOptions,
+        mut progress: impl FnMut(ScanProgress<'_>),
+        mut scan_file: impl FnMut(&Path) -> Result<Vec<PluginDescriptor>, ScanError>,
+    ) -> Vec<(PathBuf, Result<Vec<PluginDescriptor>, ScanError>)> {
+        self.files.retain(|path, file| {
+            path.exists() || matches!(file.result, Err(ScanError::Blacklisted(_)))
+        });
+
+        let files = find_plugin_files(options);
+        let mut results = Vec::with_capacity(files.len());
+
+        for (i, path) in files.iter().enumerate() {
+            let cached = self.check(path);
+            if !cached {
+                let (size, modified) = file_stats(path).unwrap_or((0, SystemTime::UNIX_EPOCH));
+                let hash = content_hash(path).unwrap_or(0);
+                let result = scan_file(path);
+
+                self.files.insert(
+                    path.clone(),
+                    CachedFile {
+                        path: path.clone(),
+                        size,
+                        modified,
+                        hash,
+                        result,
+                    },
+                );
+            }
+
+            let result = &self.files[path].result;
+            progress(ScanProgress {
+                path,
+                scanned: i + 1,
+                total: files.len(),
+                descriptors: result.as_deref().unwrap_or_default(),
+                error: result.as_ref().err(),
+                cached,
+            });
+
+            results.push((path.clone(), result.clone()));
+        }
+
+        results
+    }
+
+    /// Whether the cached result for `path` is still valid, updating its modification time when
+    /// only that changed.
+    fn check(&mut self, path: &Path) -> bool {
+        let Some(file) = self.files.get_mut(path) else {
+            return false;
+        };
+
+        if matches!(file.result, Err(ScanError::Blacklisted(_))) {
+            return true;
+        }
+
+        let Some((size, modified)) = file_stats(path) else {
+            return false;
+        };
+
+        if size == file.size && modified == file.modified {
+            return true;
+        }
+
+        if size == file.size && content_hash(path) == Some(file.hash) {
+            file.modified = modified;
+            return true;
+        }
+
+        false
+    }
+}
+
+/// Total size and latest modification time of a file or every file in a bundle.
+fn file_stats(path: &Path) -> Option<(u64, SystemTime)> {
+    let mut size = 0;
+    let mut modified = SystemTime::UNIX_EPOCH;
+
+    for file in bundle_files(path)? {
+        let metadata = std::fs::metadata(&file).ok()?;
+        size += metadata.len();
+        modified = modified.max(metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH));
+    }
+
+    Some((size, modified))
+}
+
+/// FNV-1a hash of a file, or of the relative paths and contents of every file in a bundle.
+/// Written out rather than using `DefaultHasher` as the hash has to stay the same between Rust
+/// versions.
+fn content_hash(path: &Path) -> Option<u64> {
+    const PRIME: u64 = 0x100000001b3;
+
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut write = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(PRIME);
+        }
+    };
+
+    let mut buffer = vec![0; 64 * 1024];
+    for file in bundle_files(path)? {
+        if let Ok(relative) = file.strip_prefix(path) {
+            write(relative.to_string_lossy().as_bytes());
+        }
+
+        let mut reader = File::open(&file).ok()?;
+        loop {
+            let len = reader.read(&mut buffer).ok()?;
+            if len == 0 {
+                break;
+            }
+            write(&buffer[..len]);
+        }
+    }
+
+    Some(hash)
+}
+
+/// The file itself, or every file in a bundle sorted by path. Symlinked directories inside
+/// bundles aren't followed.
+fn bundle_files(path: &Path) -> Option<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return path.is_file().then(|| vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir).ok()?.filter_map(|e| e.ok()) {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else if entry.path().is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+
+    files.sort();
+    Some(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// A file in its own temporary directory, removed on drop.
+    struct TempFile {
+        dir: PathBuf,
+        path: PathBuf,
+    }
+
+    impl TempFile {
+        fn new(name: &str, contents: &[u8]) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "audio-plugin-host-cache-{}-{}",
+                std::process::id(),
+                name
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+
+            let path = dir.join("plugin.clap");
+            std::fs::write(&path, contents).unwrap();
+
+            Self { dir, path }
+        }
+
+        fn write(&self, contents: &[u8]) {
+            std::fs::write(&self.path, contents).unwrap();
+        }
+
+        /// Moves the modification time so it differs from the cached one regardless of the file
+        /// system's timestamp resolution.
+        fn touch(&self, seconds: u64) {
+            let file = File::options().write(true).open(&self.path).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+                .unwrap();
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn cached(path: &Path, result: Result<Vec<PluginDescriptor>, ScanError>) -> ScanCache {
+        let (size, modified) = file_stats(path).unwrap();
+        let mut cache = ScanCache::new();
+        cache.files.insert(
+            path.to_path_buf(),
+            CachedFile {
+                path: path.to_path_buf(),
+                size,
+                modified,
+                hash: content_hash(path).unwrap(),
+                result,
+            },
+        );
+
+        cache
+    }
+
+    #[test]
+    fn unchanged_file_is_cached() {
+        let file = TempFile::new("unchanged", b"plugin");
+        let mut cache = cached(&file.path, Ok(Vec::new()));
+
+        assert!(cache.check(&file.path));
+        assert!(!ScanCache::new().check(&file.path));
+    }
+
+    #[test]
+    fn size_change_invalidates() {
+        let file = TempFile::new("size", b"plugin");
+        let mut cache = cached(&file.path, Ok(Vec::new()));
+
+        file.write(b"plugin v2");
+        assert!(!cache.check(&file.path));
+    }
+
+    #[test]
+    fn touched_file_with_same_contents_is_cached() {
+        let file = TempFile::new("touched", b"plugin");
+        let mut cache = cached(&file.path, Ok(Vec::new()));
+
+        file.touch(1_000_000);
+        assert!(cache.check(&file.path));
+
+        // The new modification time is remembered so the next check doesn't hash again.
+        let (_, modified) = file_stats(&file.path).unwrap();
+        assert_eq!(cache.get(&file.path).unwrap().modified, modified);
+    }
+
+    #[test]
+    fn content_change_with_same_size_invalidates() {
+        let file = TempFile::new("contents", b"plugin");
+        let mut cache = cached(&file.path, Ok(Vec::new()));
+
+        file.write(b"PLUGIN");
+        file.touch(1_000_000);
+        assert!(!cache.check(&file.path));
+    }
+
+    #[test]
+    fn blacklisted_file_is_never_rescanned() {
+        let file = TempFile::new("blacklisted", b"plugin");
+        let mut cache = cached(&file.path, Err(ScanError::Blacklisted("crashes".into())));
+
+        file.write(b"plugin v2");
+        assert!(cache.check(&file.path));
+    }
+}
@@ -0,0 +1,179 @@
+use std::path::{Path, PathBuf};
+
+use crate::{error::Error, host::Host, load, plugin::PluginInstance, Samples};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "scan_cache")]
+mod cache;
+/// cbindgen:ignore
+#[cfg(feature = "out_of_process_scan")]
+mod out_of_process;
+mod scan;
+
+#[cfg(feature = "out_of_process_scan")]
+pub use out_of_process::{
+    scan_file_out_of_process, scan_helper_main, scan_out_of_process, OutOfProcessOptions,
+    ScanResult, SCAN_PROTOCOL_VERSION,
+};
+#[cfg(feature = "scan_cache")]
+pub use cache::{CachedFile, ScanCache};
+pub use scan::{find_plugin_files, scan, standard_paths, ScanError, ScanOptions, ScanProgress};
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PluginDescriptor {
+    pub name: String,
+    pub id: String,
+    pub path: PathBuf,
+    pub version: String,
+    pub vendor: String,
+    pub format: Format,
+    pub initial_latency: Samples,
+    /// Normalised from `features` so plugins of every format can be filtered the same way.
+    pub category: PluginCategory,
+    /// The format's own tags: CLAP features, VST3 subcategories or the VST2 category.
+    pub features: Vec<String>,
+    /// Empty if the plugin doesn't provide them. Only CLAP has a description and manual URL.
+    pub description: String,
+    pub url: String,
+    pub manual_url: String,
+    pub support_url: String,
+    /// The VST3 SDK version the plugin was built with. Empty for other formats.
+    pub sdk_version: String,
+    /// IDs of older plugins this one can be loaded in place of, e.g. when opening projects
+    /// saved with them. Only VST3 plugins scanned from a `moduleinfo.json` list them, see the
+    /// `vst3_moduleinfo` feature.
+    pub replaces: Vec<String>,
+    /// `None` if the plugin couldn't be created while scanning or wasn't loaded at all, as for
+    /// VST3 plugins with a `moduleinfo.json`.
+    pub has_editor: Option<bool>,
+    /// The plugin's default buses, `None` in the same cases as `has_editor`.
+    pub io: Option<IoSummary>,
+}
+
+impl PluginDescriptor {
+    pub fn load(&self, host: &Host) -> Result<PluginInstance, Error> {
+        load(&self.path, &self.id, host)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Format {
+    #[default]
+    Vst2,
+    Vst3,
+    Clap,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PluginCategory {
+    #[default]
+    Unknown,
+    Instrument,
+    Effect,
+    Analyzer,
+    /// Processes notes rather than audio, e.g. arpeggiators.
+    NoteEffect,
+    /// Makes sound without notes, e.g. test tones.
+    Generator,
+}
+
+impl PluginCategory {
+    /// Normalises CLAP features or VST3 subcategories, which are matched case-insensitively.
+    pub(crate) fn from_features(features: &[String]) -> Self {
+        let has = |tags: &[&str]| {
+            features
+                .iter()
+                .any(|f| tags.iter().any(|tag| f.eq_ignore_ascii_case(tag)))
+        };
+
+        // Most specific first, e.g. VST3 analyzers are `Fx|Analyzer`.
+        if has(&["instrument", "synthesizer", "synth", "sampler", "drum"]) {
+            PluginCategory::Instrument
+        } else if has(&["note-effect"]) {
+            PluginCategory::NoteEffect
+        } else if has(&["generator"]) {
+            PluginCategory::Generator
+        } else if has(&["analyzer"]) {
+            PluginCategory::Analyzer
+        } else if has(&["audio-effect", "fx"]) {
+            PluginCategory::Effect
+        } else {
+            PluginCategory::Unknown
+        }
+    }
+}
+
+/// Bus counts of a plugin as it's created, before the host changes its configuration.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IoSummary {
+    /// Number of audio input buses, including the main one.
+    pub audio_inputs: usize,
+    pub audio_outputs: usize,
+    /// Channels of the first audio input bus, 0 if there are no inputs.
+    pub main_input_channels: usize,
+    pub main_output_channels: usize,
+    /// Number of note and MIDI buses.
+    pub event_inputs: usize,
+    pub event_outputs: usize,
+}
+
+/// Returns any plugin descriptors of plugins in a file. Note that formats such as VST3 allow
+/// multiple plugins to be defined in the same file.
+pub fn get_descriptor_from_file(path: impl AsRef<Path>) -> Vec<PluginDescriptor> {
+    if is_vst2(path.as_ref(), false) {
+        let Some(desc) = crate::formats::vst2::get_descriptor(path.as_ref()) else {
+            return vec![];
+        };
+
+        vec![desc]
+    } else if is_vst3(path.as_ref()) {
+        crate::formats::vst3::get_descriptor(path.as_ref())
+    } else if is_clap(path.as_ref()) {
+        crate::formats::clap::get_descriptor(path.as_ref())
+    } else {
+        vec![]
+    }
+}
+
+pub fn is_vst2(path: &Path, check_contents: bool) -> bool {
+    if !path.exists() {
+        return false;
+    }
+
+    let ext = path.extension().unwrap_or_default();
+    if ext != "dll" && ext != "so" && ext != "vst" {
+        return false;
+    }
+
+    // if !check_contents {
+        return true;
+    // }
+
+    #[cfg(target_os = "macos")]
+    if path.is_dir() {
+        return true;
+    }
+
+    let data = std::fs::read(path).expect("Failed to read file");
+    match goblin::Object::parse(&data) {
+        Ok(goblin::Object::Elf(elf)) => elf.dynstrtab.to_vec().map(|t| t.contains(&"VSTPluginMain")).unwrap_or(true),
+        Ok(goblin::Object::PE(pe)) => pe.exports.iter().any(|e| e.name == Some("VSTPluginMain")),
+        _ => true, // Can't check so just return true
+    }
+}
+
+pub fn is_vst3(path: &Path) -> bool {
+    let path = path.to_string_lossy().to_lowercase();
+    path.ends_with(".vst3")
+}
+
+pub fn is_clap(path: &Path) -> bool {
+    let path = path.to_string_lossy().to_lowercase();
+    path.ends_with(".clap")
+}
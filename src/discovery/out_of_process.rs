@@ -0,0 +1,201 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    find_plugin_files, get_descriptor_from_file, PluginDescriptor, ScanError, ScanOptions,
+    ScanProgress,
+};
+
+/// Version of the protocol between `scan_out_of_process` and the helper. Bumped whenever
+/// `ScanResponse` changes so a stale helper is reported instead of misread.
+pub const SCAN_PROTOCOL_VERSION: u32 = 3;
+
+/// First argument given to the helper, see `scan_helper_main`.
+const HELPER_ARG: &str = "--audio-plugin-host-scan";
+
+/// The response is the last stdout line starting with this. Plugins print to stdout too.
+const RESPONSE_PREFIX: &str = "audio-plugin-host-scan-response:";
+
+/// How long to wait for the helper's output after it exits.
+const OUTPUT_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize)]
+struct ScanResponse {
+    version: u32,
+    descriptors: Vec<PluginDescriptor>,
+}
+
+/// Read before the rest of the response so a helper using a different version is reported as
+/// such rather than as an invalid response.
+#[derive(Deserialize)]
+struct ResponseHeader {
+    version: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct OutOfProcessOptions {
+    /// The helper executable. Either the `audio-plugin-scanner` binary built with the
+    /// `out_of_process_scan` feature or any executable that calls `scan_helper_main`, including
+    /// the host itself. Defaults to `audio-plugin-scanner` next to the current executable.
+    pub helper: PathBuf,
+    /// How long a plugin file may take to scan before the helper is killed.
+    pub timeout: Duration,
+}
+
+impl Default for OutOfProcessOptions {
+    fn default() -> Self {
+        let helper = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf))
+            .unwrap_or_default()
+            .join(format!("audio-plugin-scanner{}", std::env::consts::EXE_SUFFIX));
+
+        Self {
+            helper,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Result of scanning one plugin file with `scan_out_of_process`.
+#[derive(Clone, Debug)]
+pub struct ScanResult {
+    pub path: PathBuf,
+    pub result: Result<Vec<PluginDescriptor>, ScanError>,
+}
+
+/// Same as `scan` but each plugin file is loaded in a new helper process, so a plugin that
+/// crashes or hangs is reported as a `ScanError` instead of taking the host down with it.
+pub fn scan_out_of_process(
+    options: &ScanOptions,
+    helper: &OutOfProcessOptions,
+    mut progress: impl FnMut(ScanProgress<'_>),
+) -> Vec<ScanResult> {
+    let files = find_plugin_files(options);
+    let mut results = Vec::with_capacity(files.len());
+
+    for (i, path) in files.iter().enumerate() {
+        let result = scan_file_out_of_process(path, helper);
+
+        progress(ScanProgress {
+            path,
+            scanned: i + 1,
+            total: files.len(),
+            descriptors: result.as_deref().unwrap_or_default(),
+            error: result.as_ref().err(),
+            cached: false,
+        });
+
+        results.push(ScanResult {
+            path: path.clone(),
+            result,
+        });
+    }
+
+    results
+}
+
+/// Gets the descriptors of the plugins in one file in a helper process.
+pub fn scan_file_out_of_process(
+    path: &Path,
+    helper: &OutOfProcessOptions,
+) -> Result<Vec<PluginDescriptor>, ScanError> {
+    let mut child = Command::new(&helper.helper)
+        .arg(HELPER_ARG)
+        .arg(SCAN_PROTOCOL_VERSION.to_string())
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| ScanError::HelperNotStarted(e.to_string()))?;
+
+    // Read on another thread so a plugin printing a lot can't fill the pipe and block the helper.
+    let mut stdout = child.stdout.take().unwrap();
+    let (output_sender, output_receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stdout.read_to_end(&mut output);
+        let _ = output_sender.send(output);
+    });
+
+    let deadline = Instant::now() + helper.timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(ScanError::TimedOut);
+            }
+            Err(e) => return Err(ScanError::InvalidResponse(e.to_string())),
+        }
+    };
+
+    // Processes started by the plugin can keep the pipe open after the helper exits.
+    let output = output_receiver
+        .recv_timeout(OUTPUT_TIMEOUT)
+        .unwrap_or_default();
+    let output = String::from_utf8_lossy(&output);
+
+    let Some(response) = output
+        .lines()
+        .rev()
+        .find_map(|line| line.strip_prefix(RESPONSE_PREFIX))
+    else {
+        return Err(ScanError::Crashed(status.code()));
+    };
+
+    let invalid = |e: serde_json::Error| ScanError::InvalidResponse(e.to_string());
+
+    let header: ResponseHeader = serde_json::from_str(response.trim()).map_err(invalid)?;
+    if header.version != SCAN_PROTOCOL_VERSION {
+        return Err(ScanError::VersionMismatch(header.version));
+    }
+
+    let response: ScanResponse = serde_json::from_str(response.trim()).map_err(invalid)?;
+
+    Ok(response.descriptors)
+}
+
+/// Entry point of the scan helper. Call this at the start of `main`: when the process was
+/// started by `scan_out_of_process` it scans the plugin, prints the response and exits,
+/// otherwise it returns straight away so the host's own executable can be used as the helper.
+pub fn scan_helper_main() {
+    let args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    if args.len() != 4 || args[1] != HELPER_ARG {
+        return;
+    }
+
+    let respond = |descriptors: Vec<PluginDescriptor>| {
+        let response = ScanResponse {
+            version: SCAN_PROTOCOL_VERSION,
+            descriptors,
+        };
+
+        // On its own line in case the plugin printed something without a newline.
+        println!();
+        println!(
+            "{}{}",
+            RESPONSE_PREFIX,
+            serde_json::to_string(&response).unwrap()
+        );
+    };
+
+    let version = args[2].to_string_lossy().parse::<u32>().unwrap_or(0);
+    if version != SCAN_PROTOCOL_VERSION {
+        respond(Vec::new());
+        std::process::exit(2);
+    }
+
+    respond(get_descriptor_from_file(Path::new(&args[3])));
+
+    // Don't carry on into the rest of `main` when the host itself is the helper.
+    std::process::exit(0);
+}
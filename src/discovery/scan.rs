@@ -0,0 +1,328 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::{get_descriptor_from_file, Format, PluginDescriptor};
+
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    /// Formats to look for.
+    pub formats: Vec<Format>,
+    /// Search the platform's standard plugin directories and the directories in `VST_PATH`,
+    /// `VST3_PATH` and `CLAP_PATH`. See `standard_paths`.
+    pub standard_paths: bool,
+    /// Searched as well as the standard directories. Can be directories or plugins.
+    pub extra_paths: Vec<PathBuf>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            formats: vec![Format::Vst2, Format::Vst3, Format::Clap],
+            standard_paths: true,
+            extra_paths: Vec::new(),
+        }
+    }
+}
+
+/// Passed to the `scan` callback after each plugin file.
+#[derive(Debug)]
+pub struct ScanProgress<'a> {
+    pub path: &'a Path,
+    /// Number of files scanned so far, including this one.
+    pub scanned: usize,
+    /// Number of files that will be scanned.
+    pub total: usize,
+    /// Plugins found in the file. Empty if there were none or it couldn't be loaded.
+    pub descriptors: &'a [PluginDescriptor],
+    /// Why the file couldn't be scanned. Never reported by `scan`, which can't tell a failed load
+    /// from a file without plugins.
+    pub error: Option<&'a ScanError>,
+    /// Whether the result came from a `ScanCache` instead of loading the file.
+    pub cached: bool,
+}
+
+/// Why a plugin file couldn't be scanned.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ScanError {
+    /// The helper executable couldn't be started. Contains the reason.
+    HelperNotStarted(String),
+    /// The helper exited without a response, most likely because the plugin crashed. Contains
+    /// the exit code, `None` if it was killed by a signal.
+    Crashed(Option<i32>),
+    /// The plugin took longer than `OutOfProcessOptions::timeout` so the helper was killed.
+    TimedOut,
+    /// The helper uses a different protocol version. Contains the helper's version.
+    VersionMismatch(u32),
+    /// The helper's response couldn't be parsed. Contains the reason.
+    InvalidResponse(String),
+    /// No plugins were found in the file. When scanning in-process this is also how a file that
+    /// failed to load shows up.
+    NoPlugins,
+    /// Skipped because of `ScanCache::blacklist`. Contains the reason given.
+    Blacklisted(String),
+}
+
+impl std::fmt::Display for ScanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanError::HelperNotStarted(reason) => {
+                write!(f, "Failed to start the scan helper: {}", reason)
+            }
+            ScanError::Crashed(Some(code)) => write!(f, "Scan helper exited with code {}", code),
+            ScanError::Crashed(None) => write!(f, "Scan helper crashed"),
+            ScanError::TimedOut => write!(f, "Scan timed out"),
+            ScanError::VersionMismatch(version) => {
+                write!(f, "Scan helper uses protocol version {}", version)
+            }
+            ScanError::InvalidResponse(reason) => {
+                write!(f, "Invalid response from the scan helper: {}", reason)
+            }
+            ScanError::NoPlugins => write!(f, "No plugins found"),
+            ScanError::Blacklisted(reason) => write!(f, "Blacklisted: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+/// Finds every plugin in the directories given by `options` and gets their descriptors, calling
+/// `progress` after each file.
+///
+/// Plugins are loaded into this process so a plugin that crashes while being scanned takes the
+/// host down with it. See `scan_out_of_process`.
+pub fn scan(
+    options: &ScanOptions,
+    mut progress: impl FnMut(ScanProgress<'_>),
+) -> Vec<PluginDescriptor> {
+    let files = find_plugin_files(options);
+    let mut descriptors = Vec::new();
+
+    for (i, path) in files.iter().enumerate() {
+        let found = get_descriptor_from_file(path);
+
+        progress(ScanProgress {
+            path,
+            scanned: i + 1,
+            total: files.len(),
+            descriptors: &found,
+            error: None,
+            cached: false,
+        });
+
+        descriptors.extend(found);
+    }
+
+    descriptors
+}
+
+/// Finds the plugin files and bundles in the directories given by `options` without loading
+/// them. Directories are searched recursively and plugins reached through more than one path
+/// (e.g. symlinks) are only returned once.
+pub fn find_plugin_files(options: &ScanOptions) -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+    if options.standard_paths {
+        for format in options.formats.iter() {
+            roots.extend(standard_paths(format));
+        }
+    }
+    roots.extend(options.extra_paths.iter().cloned());
+
+    let mut walker = Walker {
+        formats: &options.formats,
+        visited_dirs: HashSet::new(),
+        found: HashSet::new(),
+        files: Vec::new(),
+    };
+
+    for root in roots.iter() {
+        walker.visit(root);
+    }
+
+    walker.files
+}
+
+/// The directories searched for a format when `ScanOptions::standard_paths` is set, starting
+/// with the ones in the format's environment variable. Directories that don't exist are
+/// included.
+pub fn standard_paths(format: &Format) -> Vec<PathBuf> {
+    let variable = match format {
+        Format::Vst2 => "VST_PATH",
+        Format::Vst3 => "VST3_PATH",
+        Format::Clap => "CLAP_PATH",
+    };
+
+    let mut paths: Vec<PathBuf> = std::env::var_os(variable)
+        .map(|value| std::env::split_paths(&value).collect())
+        .unwrap_or_default();
+
+    let home = |path: &str| std::env::var_os("HOME").map(|home| Path::new(&home).join(path));
+    let env = |variable: &str, path: &str| {
+        std::env::var_os(variable).map(|dir| Path::new(&dir).join(path))
+    };
+
+    let platform_paths: Vec<Option<PathBuf>> = if cfg!(target_os = "macos") {
+        let dir = match format {
+            Format::Vst2 => "VST",
+            Format::Vst3 => "VST3",
+            Format::Clap => "CLAP",
+        };
+
+        vec![
+            home(&format!("Library/Audio/Plug-Ins/{}", dir)),
+            Some(Path::new("/Library/Audio/Plug-Ins").join(dir)),
+        ]
+    } else if cfg!(target_os = "windows") {
+        match format {
+            Format::Vst2 => vec![
+                env("ProgramFiles", "Steinberg\\VstPlugins"),
+                env("ProgramFiles", "VstPlugins"),
+                env("CommonProgramFiles", "VST2"),
+            ],
+            Format::Vst3 => vec![
+                env("LOCALAPPDATA", "Programs\\Common\\VST3"),
+                env("CommonProgramFiles", "VST3"),
+            ],
+            Format::Clap => vec![
+                env("LOCALAPPDATA", "Programs\\Common\\CLAP"),
+                env("CommonProgramFiles", "CLAP"),
+            ],
+        }
+    } else {
+        match format {
+            Format::Vst2 => vec![
+                home(".vst"),
+                home(".lxvst"),
+                Some(PathBuf::from("/usr/local/lib/vst")),
+                Some(PathBuf::from("/usr/lib/vst")),
+                Some(PathBuf::from("/usr/lib/lxvst")),
+            ],
+            Format::Vst3 => vec![
+                home(".vst3"),
+                Some(PathBuf::from("/usr/local/lib/vst3")),
+                Some(PathBuf::from("/usr/lib/vst3")),
+            ],
+            Format::Clap => vec![
+                home(".clap"),
+                Some(PathBuf::from("/usr/local/lib/clap")),
+                Some(PathBuf::from("/usr/lib/clap")),
+            ],
+        }
+    };
+
+    paths.extend(platform_paths.into_iter().flatten());
+    paths
+}
+
+struct Walker<'a> {
+    formats: &'a [Format],
+    /// Canonical paths, so symlink loops are only followed once.
+    visited_dirs: HashSet<PathBuf>,
+    /// Canonical paths of the plugins in `files`.
+    found: HashSet<PathBuf>,
+    files: Vec<PathBuf>,
+}
+
+impl Walker<'_> {
+    fn visit(&mut self, path: &Path) {
+        if let Some(format) = plugin_format(path) {
+            if self.formats.contains(&format) && is_complete(path, &format) {
+                let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+                if self.found.insert(canonical) {
+                    self.files.push(path.to_path_buf());
+                }
+            }
+
+            // Bundles aren't searched, the `.so` files in a VST3 bundle aren't VST2 plugins.
+            return;
+        }
+
+        if !path.is_dir() {
+            return;
+        }
+
+        let Ok(canonical) = path.canonicalize() else {
+            return;
+        };
+        if !self.visited_dirs.insert(canonical) {
+            return;
+        }
+
+        let Ok(entries) = std::fs::read_dir(path) else {
+            return;
+        };
+
+        // Sorted so scans always report plugins in the same order.
+        let mut entries: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        entries.sort();
+
+        for entry in entries.iter() {
+            self.visit(entry);
+        }
+    }
+}
+
+/// The format of a plugin file or bundle, by its extension.
+fn plugin_format(path: &Path) -> Option<Format> {
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+
+    match ext.as_str() {
+        "vst3" => Some(Format::Vst3),
+        "clap" => Some(Format::Clap),
+        "vst" if cfg!(target_os = "macos") => Some(Format::Vst2),
+        "so" if cfg!(target_os = "linux") && path.is_file() => Some(Format::Vst2),
+        "dll" if cfg!(target_os = "windows") && path.is_file() => Some(Format::Vst2),
+        _ => None,
+    }
+}
+
+/// Whether a bundle has a binary for this platform. Plugins that are a single file always do.
+fn is_complete(path: &Path, format: &Format) -> bool {
+    if !path.is_dir() {
+        return path.is_file();
+    }
+
+    let contents = path.join("Contents");
+
+    if cfg!(target_os = "macos") {
+        return contents.join("MacOS").is_dir();
+    }
+
+    if *format != Format::Vst3 {
+        return false;
+    }
+
+    // E.g. `Contents/x86_64-linux/Plugin.so` or `Contents/x86_64-win/Plugin.vst3`.
+    let (arch_dir, ext) = if cfg!(target_os = "windows") {
+        (format!("{}-win", vst3_windows_arch()), "vst3")
+    } else {
+        (format!("{}-linux", vst3_linux_arch()), "so")
+    };
+
+    std::fs::read_dir(contents.join(arch_dir))
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .any(|e| e.path().extension().is_some_and(|e| e == ext))
+        })
+        .unwrap_or(false)
+}
+
+/// Architecture names used for VST3 bundle directories, which don't all match Rust's.
+fn vst3_linux_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86" => "i386",
+        "arm" => "armv7l",
+        arch => arch,
+    }
+}
+
+fn vst3_windows_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "aarch64" => "arm64",
+        arch => arch,
+    }
+}
@@ -1,19 +1,75 @@
-use std::fmt::Display;
+use std::{fmt::Display, path::PathBuf};
 
-pub fn err<T>(message: impl ToString) -> Result<T, Error> {
-    Err(Error {
-        message: message.to_string(),
-    })
-}
+use crate::parameter::ParamId;
 
-#[derive(Debug, Clone)]
-pub struct Error {
-    pub message: String,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The plugin path does not exist.
+    PathNotFound(PathBuf),
+    /// The path is not a plugin format supported by this crate.
+    UnsupportedFormat(PathBuf),
+    /// The plugin binary or bundle couldn't be loaded. Contains the reason given by the loader.
+    LibraryLoadFailed(String),
+    /// The plugin binary doesn't export the entry point (or factory) required by its format.
+    EntryMissing(&'static str),
+    /// None of the plugins in the file have the requested ID.
+    PluginNotFoundInFactory(String),
+    /// The plugin was found but failed to initialise.
+    InitFailed(String),
+    /// The plugin refused to activate with the current sample rate and block size.
+    ActivationFailed,
+    /// The plugin refused to load or save its state, or the state data is invalid.
+    StateRejected(String),
+    /// The plugin (or format) doesn't support a required extension or feature. E.g. "clap.state".
+    ExtensionMissing(&'static str),
+    /// The plugin has no editor or it couldn't be opened.
+    EditorUnavailable(String),
+    /// The editor is already open.
+    EditorAlreadyOpen,
+    /// The buses given don't match the plugin's IO configuration.
+    IoMismatch(String),
+    /// A fixed-capacity buffer or queue is full.
+    CapacityExceeded,
+    /// The `AudioHandle` is in the middle of processing. Try again once it's stopped.
+    ProcessingActive,
+    /// No parameter has the given ID.
+    ParameterNotFound(ParamId),
+    /// The plugin couldn't format the value or parse the text. Contains the text for parsing.
+    ParameterConversionFailed(String),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message)
+        match self {
+            Error::PathNotFound(path) => write!(f, "Path does not exist: {}", path.display()),
+            Error::UnsupportedFormat(path) => write!(
+                f,
+                "The requested path was not a supported plugin format: {}",
+                path.display()
+            ),
+            Error::LibraryLoadFailed(reason) => write!(f, "Failed to load plugin library: {}", reason),
+            Error::EntryMissing(entry) => write!(f, "Plugin library does not provide `{}`", entry),
+            Error::PluginNotFoundInFactory(id) => write!(f, "No plugin found with ID: {}", id),
+            Error::InitFailed(reason) => write!(f, "Failed to initialize plugin: {}", reason),
+            Error::ActivationFailed => write!(f, "Failed to activate plugin"),
+            Error::StateRejected(reason) => write!(f, "Plugin state error: {}", reason),
+            Error::ExtensionMissing(extension) => {
+                write!(f, "Plugin does not support `{}`", extension)
+            }
+            Error::EditorUnavailable(reason) => write!(f, "Editor unavailable: {}", reason),
+            Error::EditorAlreadyOpen => write!(f, "Editor is already open"),
+            Error::IoMismatch(reason) => write!(
+                f,
+                "Inputs and outputs do not match the plugin's IO configuration: {}",
+                reason
+            ),
+            Error::CapacityExceeded => write!(f, "Capacity exceeded"),
+            Error::ProcessingActive => write!(f, "The plugin is processing audio"),
+            Error::ParameterNotFound(id) => write!(f, "No parameter found with ID: {}", id),
+            Error::ParameterConversionFailed(text) => {
+                write!(f, "Plugin failed to convert parameter value: {}", text)
+            }
+        }
     }
 }
 
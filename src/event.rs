@@ -1,7 +1,7 @@
-use crate::{parameter::ParameterUpdate, PpqTime, Samples};
+use crate::{host::Limits, parameter::ParameterUpdate, PlayingState, PpqTime, Samples, Tempo};
 
-/// Events sent to the plugin from the host. Can be passed into the `process` function or queued
-/// for the next process call with `queue_event`.
+/// Events sent to the plugin from the host. Can be passed into `AudioHandle::process` or queued
+/// for the next process call with `MainHandle::queue_event`.
 #[derive(Debug, Clone, Default)]
 #[repr(C)]
 pub struct HostIssuedEvent {
@@ -19,22 +19,284 @@ pub struct HostIssuedEvent {
 pub enum HostIssuedEventType {
     Midi(MidiEvent),
     Parameter(ParameterUpdate),
+    /// Changes one note. Plugins without note ids match on `channel` and `key` instead, which
+    /// are -1 to match any.
     NoteExpression {
         note_id: i32,
+        channel: i16,
+        key: i16,
         expression_type: NoteExpressionType,
         value: f64,
     },
+    /// The transport changes at `block_time`. Use this for tempo ramps, loop wrap-arounds and
+    /// time signature changes inside a block. The start of the block uses `ProcessDetails`.
+    Transport(Transport),
+    /// Sent on the MIDI port `bus_index`.
+    SysEx(SysEx),
+    /// A MIDI 2.0 Universal MIDI Packet. Sent as is to CLAP plugins, translated to notes, note
+    /// expressions and `IMidiMapping` parameters for VST3 and down-converted to MIDI 1.0 for VST2.
+    Midi2(Ump),
 }
 
+/// A SysEx message including the leading `0xF0` and trailing `0xF7`. Only points at the bytes so
+/// events stay cheap to clone and never allocate.
 #[repr(C)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
+pub struct SysEx {
+    data: *const u8,
+    len: usize,
+}
+
+unsafe impl Send for SysEx {}
+unsafe impl Sync for SysEx {}
+
+impl SysEx {
+    /// # Safety
+    /// `data` must stay alive and unchanged until the `AudioHandle::process` call the event is
+    /// passed to returns. Events given to `MainHandle::queue_event` are copied so only need to
+    /// outlive that call.
+    pub unsafe fn new(data: &[u8]) -> Self {
+        Self {
+            data: data.as_ptr(),
+            len: data.len(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// # Safety
+    /// The bytes given to `SysEx::new` must still be alive.
+    pub unsafe fn as_slice(&self) -> &[u8] {
+        if self.data.is_null() {
+            return &[];
+        }
+
+        std::slice::from_raw_parts(self.data, self.len)
+    }
+}
+
+/// A Universal MIDI Packet. Only MIDI 2.0 channel voice messages (message type 4) are translated
+/// for VST3 and VST2, other packets only reach CLAP plugins.
+///
+/// Note velocities are 16-bit and controller, pressure and pitch bend values are 32-bit.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Ump {
+    pub data: [u32; 4],
+}
+
+impl Ump {
+    pub const MIDI2_CHANNEL_VOICE: u8 = 0x4;
+
+    pub const REGISTERED_PER_NOTE_CONTROLLER: u8 = 0x0;
+    pub const ASSIGNABLE_PER_NOTE_CONTROLLER: u8 = 0x1;
+    pub const PER_NOTE_PITCH_BEND: u8 = 0x6;
+    pub const NOTE_OFF: u8 = 0x8;
+    pub const NOTE_ON: u8 = 0x9;
+    pub const POLY_PRESSURE: u8 = 0xA;
+    pub const CONTROL_CHANGE: u8 = 0xB;
+    pub const PROGRAM_CHANGE: u8 = 0xC;
+    pub const CHANNEL_PRESSURE: u8 = 0xD;
+    pub const PITCH_BEND: u8 = 0xE;
+
+    fn channel_voice(group: u8, status: u8, channel: u8, byte3: u8, byte4: u8, data: u32) -> Self {
+        Self {
+            data: [
+                (Self::MIDI2_CHANNEL_VOICE as u32) << 28
+                    | ((group & 0xF) as u32) << 24
+                    | ((status & 0xF) as u32) << 20
+                    | ((channel & 0xF) as u32) << 16
+                    | ((byte3 & 0x7F) as u32) << 8
+                    | byte4 as u32,
+                data,
+                0,
+                0,
+            ],
+        }
+    }
+
+    pub fn note_on(group: u8, channel: u8, key: u8, velocity: u16) -> Self {
+        Self::channel_voice(group, Self::NOTE_ON, channel, key, 0, (velocity as u32) << 16)
+    }
+
+    pub fn note_off(group: u8, channel: u8, key: u8, velocity: u16) -> Self {
+        Self::channel_voice(group, Self::NOTE_OFF, channel, key, 0, (velocity as u32) << 16)
+    }
+
+    pub fn poly_pressure(group: u8, channel: u8, key: u8, pressure: u32) -> Self {
+        Self::channel_voice(group, Self::POLY_PRESSURE, channel, key, 0, pressure)
+    }
+
+    /// `index` follows the MIDI 1.0 controller numbers, e.g. 7 is volume and 10 is pan.
+    pub fn registered_per_note_controller(
+        group: u8,
+        channel: u8,
+        key: u8,
+        index: u8,
+        value: u32,
+    ) -> Self {
+        Self::channel_voice(
+            group,
+            Self::REGISTERED_PER_NOTE_CONTROLLER,
+            channel,
+            key,
+            index,
+            value,
+        )
+    }
+
+    pub fn assignable_per_note_controller(
+        group: u8,
+        channel: u8,
+        key: u8,
+        index: u8,
+        value: u32,
+    ) -> Self {
+        Self::channel_voice(
+            group,
+            Self::ASSIGNABLE_PER_NOTE_CONTROLLER,
+            channel,
+            key,
+            index,
+            value,
+        )
+    }
+
+    /// `0x8000_0000` is centered.
+    pub fn per_note_pitch_bend(group: u8, channel: u8, key: u8, value: u32) -> Self {
+        Self::channel_voice(group, Self::PER_NOTE_PITCH_BEND, channel, key, 0, value)
+    }
+
+    pub fn control_change(group: u8, channel: u8, index: u8, value: u32) -> Self {
+        Self::channel_voice(group, Self::CONTROL_CHANGE, channel, index, 0, value)
+    }
+
+    pub fn program_change(group: u8, channel: u8, program: u8) -> Self {
+        Self::channel_voice(
+            group,
+            Self::PROGRAM_CHANGE,
+            channel,
+            0,
+            0,
+            ((program & 0x7F) as u32) << 24,
+        )
+    }
+
+    pub fn channel_pressure(group: u8, channel: u8, pressure: u32) -> Self {
+        Self::channel_voice(group, Self::CHANNEL_PRESSURE, channel, 0, 0, pressure)
+    }
+
+    /// `0x8000_0000` is centered.
+    pub fn pitch_bend(group: u8, channel: u8, value: u32) -> Self {
+        Self::channel_voice(group, Self::PITCH_BEND, channel, 0, 0, value)
+    }
+
+    pub fn message_type(&self) -> u8 {
+        (self.data[0] >> 28) as u8
+    }
+
+    pub fn group(&self) -> u8 {
+        (self.data[0] >> 24) as u8 & 0xF
+    }
+
+    /// The upper nibble of the status byte, e.g. `Ump::NOTE_ON`.
+    pub fn status(&self) -> u8 {
+        (self.data[0] >> 20) as u8 & 0xF
+    }
+
+    pub fn channel(&self) -> u8 {
+        (self.data[0] >> 16) as u8 & 0xF
+    }
+
+    /// The key for note and per-note messages, the controller index for control changes.
+    pub fn index(&self) -> u8 {
+        (self.data[0] >> 8) as u8 & 0x7F
+    }
+
+    /// The controller index for per-note controllers.
+    pub fn per_note_index(&self) -> u8 {
+        self.data[0] as u8
+    }
+
+    pub fn velocity(&self) -> u16 {
+        (self.data[1] >> 16) as u16
+    }
+
+    /// The 32-bit value of controller, pressure and pitch bend messages.
+    pub fn value(&self) -> u32 {
+        self.data[1]
+    }
+
+    /// Converts a MIDI 2.0 channel voice message to MIDI 1.0 with the scaling from the UMP
+    /// specification. Per-note messages, which have no MIDI 1.0 equivalent, return `None`.
+    pub fn to_midi1(&self) -> Option<[u8; 3]> {
+        if self.message_type() != Self::MIDI2_CHANNEL_VOICE {
+            return None;
+        }
+
+        let status = self.status() << 4 | self.channel();
+        let index = self.index();
+        let value7 = (self.value() >> 25) as u8;
+
+        match self.status() {
+            Self::NOTE_ON => {
+                // Velocity 0 would be a note off in MIDI 1.0.
+                let velocity = ((self.velocity() >> 9) as u8).max(1);
+                Some([status, index, velocity])
+            }
+            Self::NOTE_OFF => Some([status, index, (self.velocity() >> 9) as u8]),
+            Self::POLY_PRESSURE | Self::CONTROL_CHANGE => Some([status, index, value7]),
+            Self::PROGRAM_CHANGE => Some([status, (self.value() >> 24) as u8 & 0x7F, 0]),
+            Self::CHANNEL_PRESSURE => Some([status, value7, 0]),
+            Self::PITCH_BEND => {
+                let value14 = self.value() >> 18;
+                Some([status, value14 as u8 & 0x7F, (value14 >> 7) as u8 & 0x7F])
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Transport state from `ProcessDetails` that can change mid-block.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transport {
+    pub tempo: Tempo,
+    /// Position at the event's `block_time`.
+    pub player_time: PpqTime,
+    pub time_signature_numerator: usize,
+    pub time_signature_denominator: usize,
+    pub cycle_enabled: bool,
+    pub cycle_start: PpqTime,
+    pub cycle_end: PpqTime,
+    pub playing_state: PlayingState,
+    pub bar_start_pos: PpqTime,
+}
+
+/// Values are in the same units for every format and converted as needed.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum NoteExpressionType {
+    /// Linear gain from 0 to 4, 1 is unchanged.
     Volume,
+    /// 0 is left, 0.5 is center and 1 is right.
     Pan,
+    /// Semitones from -120 to 120.
     Tuning,
+    /// 0 to 1.
     Vibrato,
+    /// 0 to 1.
     Expression,
+    /// 0 to 1. This is the MPE timbre dimension (CC 74).
     Brightness,
+    /// 0 to 1. Sent to VST3 plugins as poly pressure so `key` must be set.
+    Pressure,
 }
 
 impl Default for HostIssuedEventType {
@@ -64,13 +326,250 @@ pub enum PluginIssuedEvent {
     Parameter(ParameterUpdate),
     // AddNoteLabels(HeaplessVec<NoteLabel, 128>),
     // RemoveNoteLabels(HeaplessVec<u32, 128>),
-    UpdateDisplay,
+    /// The plugin's parameters changed without a `Parameter` event for each of them, e.g. after
+    /// loading a preset. `MainHandle::parameters` has already been refreshed when this is
+    /// returned by `get_events`.
+    ParametersChanged {
+        /// Values (and their formatted text) changed.
+        values: bool,
+        /// Names, flags, ranges, value names or groups changed.
+        info: bool,
+        /// Parameters were added or removed. Indexes may have changed, IDs may no longer exist.
+        list: bool,
+    },
     IOChanged,
+    /// The buses given to `AudioHandle::process` didn't match the plugin's IO configuration so
+    /// its outputs were silenced. Reported once per mismatch. Call
+    /// `MainHandle::get_io_configuration` and rebuild the buses.
+    IOMismatch,
+    /// The plugin's IO configuration needs more buses or channels than `Host::limits` allows so
+    /// its outputs were silenced. Reported once. Reload the plugin with bigger limits.
+    LimitsExceeded,
+    /// More events were given to `AudioHandle::process` than `Limits::max_events_per_block`.
+    /// Contains the number of events that were dropped.
+    EventsDropped(usize),
     RequestEditorOpen,
     RequestEditorClose,
     /// Tail length in samples. This is how long the plugin will continue to produce audio after
     /// the last input sample (i.e. reverb tail).
     TailLengthChanged(usize),
+    /// The plugin refused to activate with the sample rate or block size given to
+    /// `AudioHandle::process`. It's tried again when either changes.
+    ActivationFailed,
+}
+
+/// Events produced by the plugin during the last `AudioHandle::process` call. The buffers are
+/// allocated when the plugin is loaded and reused every block so this never allocates.
+pub struct OutputEvents {
+    events: Vec<StoredOutputEvent>,
+    /// Payloads of the SysEx events.
+    sysex_data: Vec<u8>,
+    dropped: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum StoredOutputEvent {
+    /// Never `OutputEvent::SysEx`, those keep their payload in `sysex_data`.
+    Event(OutputEvent<'static>),
+    SysEx {
+        block_time: Samples,
+        bus_index: usize,
+        start: usize,
+        len: usize,
+    },
+}
+
+/// An event from `OutputEvents`. `block_time` is in samples from the start of the block.
+///
+/// `channel` and `key` are -1 when the plugin didn't specify them. `note_id` is -1 for plugins
+/// and formats without note ids. `velocity` is 0 to 1.
+#[derive(Debug, Clone, Copy)]
+pub enum OutputEvent<'a> {
+    NoteOn {
+        block_time: Samples,
+        bus_index: usize,
+        channel: i16,
+        key: i16,
+        velocity: f64,
+        note_id: i32,
+    },
+    NoteOff {
+        block_time: Samples,
+        bus_index: usize,
+        channel: i16,
+        key: i16,
+        velocity: f64,
+        note_id: i32,
+    },
+    /// A voice ended in the plugin. Only sent by CLAP plugins.
+    NoteEnd {
+        block_time: Samples,
+        bus_index: usize,
+        channel: i16,
+        key: i16,
+        note_id: i32,
+    },
+    Midi {
+        block_time: Samples,
+        bus_index: usize,
+        data: [u8; 3],
+    },
+    SysEx {
+        block_time: Samples,
+        bus_index: usize,
+        data: &'a [u8],
+    },
+}
+
+impl OutputEvent<'_> {
+    pub fn block_time(&self) -> Samples {
+        match *self {
+            OutputEvent::NoteOn { block_time, .. }
+            | OutputEvent::NoteOff { block_time, .. }
+            | OutputEvent::NoteEnd { block_time, .. }
+            | OutputEvent::Midi { block_time, .. }
+            | OutputEvent::SysEx { block_time, .. } => block_time,
+        }
+    }
+
+    pub fn bus_index(&self) -> usize {
+        match *self {
+            OutputEvent::NoteOn { bus_index, .. }
+            | OutputEvent::NoteOff { bus_index, .. }
+            | OutputEvent::NoteEnd { bus_index, .. }
+            | OutputEvent::Midi { bus_index, .. }
+            | OutputEvent::SysEx { bus_index, .. } => bus_index,
+        }
+    }
+}
+
+impl OutputEvents {
+    pub(crate) fn new(limits: &Limits) -> Self {
+        Self {
+            events: Vec::with_capacity(limits.max_events_per_block),
+            sysex_data: Vec::with_capacity(limits.max_sysex_bytes),
+            dropped: 0,
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.events.clear();
+        self.sysex_data.clear();
+        self.dropped = 0;
+    }
+
+    /// Copies the event, including SysEx payloads. Counted as dropped if there isn't room.
+    pub(crate) fn push(&mut self, event: OutputEvent<'_>) {
+        if self.events.len() == self.events.capacity() {
+            self.dropped += 1;
+            return;
+        }
+
+        let stored = match event {
+            OutputEvent::NoteOn {
+                block_time,
+                bus_index,
+                channel,
+                key,
+                velocity,
+                note_id,
+            } => StoredOutputEvent::Event(OutputEvent::NoteOn {
+                block_time,
+                bus_index,
+                channel,
+                key,
+                velocity,
+                note_id,
+            }),
+            OutputEvent::NoteOff {
+                block_time,
+                bus_index,
+                channel,
+                key,
+                velocity,
+                note_id,
+            } => StoredOutputEvent::Event(OutputEvent::NoteOff {
+                block_time,
+                bus_index,
+                channel,
+                key,
+                velocity,
+                note_id,
+            }),
+            OutputEvent::NoteEnd {
+                block_time,
+                bus_index,
+                channel,
+                key,
+                note_id,
+            } => StoredOutputEvent::Event(OutputEvent::NoteEnd {
+                block_time,
+                bus_index,
+                channel,
+                key,
+                note_id,
+            }),
+            OutputEvent::Midi {
+                block_time,
+                bus_index,
+                data,
+            } => StoredOutputEvent::Event(OutputEvent::Midi {
+                block_time,
+                bus_index,
+                data,
+            }),
+            OutputEvent::SysEx {
+                block_time,
+                bus_index,
+                data,
+            } => {
+                let start = self.sysex_data.len();
+                if data.len() > self.sysex_data.capacity() - start {
+                    self.dropped += 1;
+                    return;
+                }
+
+                self.sysex_data.extend_from_slice(data);
+                StoredOutputEvent::SysEx {
+                    block_time,
+                    bus_index,
+                    start,
+                    len: data.len(),
+                }
+            }
+        };
+
+        self.events.push(stored);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = OutputEvent<'_>> + '_ {
+        self.events.iter().map(|event| match *event {
+            StoredOutputEvent::Event(event) => event,
+            StoredOutputEvent::SysEx {
+                block_time,
+                bus_index,
+                start,
+                len,
+            } => OutputEvent::SysEx {
+                block_time,
+                bus_index,
+                data: &self.sysex_data[start..start + len],
+            },
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Events the plugin produced that didn't fit in the buffers sized from `Host::limits`.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -94,3 +593,46 @@ impl NoteLabel {
         (self.note, label)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_velocity_scales_to_7_bits() {
+        assert_eq!(Ump::note_on(0, 2, 60, 0xFFFF).to_midi1(), Some([0x92, 60, 127]));
+        assert_eq!(Ump::note_on(0, 2, 60, 0x8000).to_midi1(), Some([0x92, 60, 64]));
+        assert_eq!(Ump::note_off(0, 2, 60, 0x8000).to_midi1(), Some([0x82, 60, 64]));
+    }
+
+    #[test]
+    fn note_on_never_becomes_note_off() {
+        assert_eq!(Ump::note_on(0, 0, 60, 0).to_midi1(), Some([0x90, 60, 1]));
+        assert_eq!(Ump::note_on(0, 0, 60, 0x1FF).to_midi1(), Some([0x90, 60, 1]));
+        assert_eq!(Ump::note_off(0, 0, 60, 0).to_midi1(), Some([0x80, 60, 0]));
+    }
+
+    #[test]
+    fn controller_values_scale_to_7_bits() {
+        assert_eq!(Ump::control_change(0, 0, 7, u32::MAX).to_midi1(), Some([0xB0, 7, 127]));
+        assert_eq!(Ump::control_change(0, 0, 7, 0x8000_0000).to_midi1(), Some([0xB0, 7, 64]));
+        assert_eq!(Ump::poly_pressure(0, 1, 60, 0).to_midi1(), Some([0xA1, 60, 0]));
+        assert_eq!(Ump::channel_pressure(0, 3, u32::MAX).to_midi1(), Some([0xD3, 127, 0]));
+        assert_eq!(Ump::program_change(0, 0, 42).to_midi1(), Some([0xC0, 42, 0]));
+    }
+
+    #[test]
+    fn pitch_bend_scales_to_14_bits() {
+        assert_eq!(Ump::pitch_bend(0, 0, 0x8000_0000).to_midi1(), Some([0xE0, 0x00, 0x40]));
+        assert_eq!(Ump::pitch_bend(0, 0, 0).to_midi1(), Some([0xE0, 0x00, 0x00]));
+        assert_eq!(Ump::pitch_bend(0, 0, u32::MAX).to_midi1(), Some([0xE0, 0x7F, 0x7F]));
+    }
+
+    #[test]
+    fn per_note_messages_have_no_midi1_equivalent() {
+        assert_eq!(Ump::per_note_pitch_bend(0, 0, 60, 0x8000_0000).to_midi1(), None);
+        assert_eq!(Ump::registered_per_note_controller(0, 0, 60, 7, 0).to_midi1(), None);
+        assert_eq!(Ump::assignable_per_note_controller(0, 0, 60, 1, 0).to_midi1(), None);
+        assert_eq!(Ump { data: [0x2090_3C7F, 0, 0, 0] }.to_midi1(), None);
+    }
+}
@@ -2,10 +2,11 @@
 // TODO: save extensions and check if get_extension returns null pointers
 // TODO: sort out index/id mismatch
 
-use std::ffi::{c_char, c_void, CStr};
+use std::ffi::{c_char, c_void, CStr, CString};
 use std::mem::zeroed;
 use std::path::Path;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
 
 use clap_sys::audio_buffer::*;
 use clap_sys::entry::*;
@@ -16,6 +17,7 @@ use clap_sys::ext::audio_ports_config::*;
 use clap_sys::ext::gui::*;
 use clap_sys::ext::latency::*;
 use clap_sys::ext::log::*;
+use clap_sys::ext::note_ports::*;
 use clap_sys::ext::params::*;
 use clap_sys::ext::state::*;
 use clap_sys::ext::tail::*;
@@ -23,29 +25,32 @@ use clap_sys::ext::thread_check::*;
 use clap_sys::ext::thread_pool::*;
 use clap_sys::ext::track_info::*;
 use clap_sys::factory::plugin_factory::*;
+use clap_sys::fixedpoint::{CLAP_BEATTIME_FACTOR, CLAP_SECTIME_FACTOR};
 use clap_sys::host::*;
+use clap_sys::id::clap_id;
 use clap_sys::plugin::*;
 use clap_sys::process::*;
 use clap_sys::stream::{clap_istream, clap_ostream};
 use clap_sys::version::{clap_version_is_compatible, CLAP_VERSION};
 
-use ringbuf::traits::Producer;
-use ringbuf::HeapProd;
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
 
 use crate::audio_bus::{AudioBusDescriptor, IOConfigutaion};
-use crate::discovery::PluginDescriptor;
-use crate::error::{err, Error};
-use crate::event::{HostIssuedEvent, PluginIssuedEvent};
+use crate::discovery::{IoSummary, PluginCategory, PluginDescriptor};
+use crate::error::Error;
+use crate::event::{HostIssuedEvent, OutputEvent, OutputEvents, PluginIssuedEvent, Transport};
 use crate::formats::Common;
-use crate::heapless_vec::{HeaplessString, HeaplessVec};
+use crate::heapless_vec::HeaplessString;
 use crate::host::Host;
-use crate::plugin::PluginInner;
+use crate::parameter::ParamId;
+use crate::plugin::{PluginInner, ProcessStatus};
 use crate::thread_check::{
     ensure_main_thread, ensure_non_main_thread, is_main_thread, is_thread_checking_enabled,
 };
 use crate::track::Track;
 use crate::utils::macos_exec_location;
-use crate::{BlockSize, SampleRate, WindowIDType};
+use crate::WindowIDType;
 
 struct Clap {
     lib: libloading::Library,
@@ -54,39 +59,159 @@ struct Clap {
     plugin: *const clap_plugin,
     host: Option<Box<clap_host>>,
     host_data: Option<Box<HostData>>,
-    process: clap_process,
-    block_size: BlockSize,
-    sample_rate: SampleRate,
-    in_events: EventBuffer,
-    out_events: EventBuffer,
+    /// Only used by `process` and `process_f64`. Never contended, the lock is only there because
+    /// `PluginInner` takes `&self`.
+    audio: Mutex<ClapAudio>,
+    /// Events raised in `process`, forwarded to `plugin_issued_events_producer` by
+    /// `editor_updates`.
+    audio_events: Mutex<HeapCons<PluginIssuedEvent>>,
+    /// Parameter ranges the audio thread replaced, dropped in `editor_updates`.
+    retired_param_ranges: Mutex<HeapCons<Vec<ParamRange>>>,
+    /// Used to activate the plugin on the main thread.
+    block_size: AtomicUsize,
+    sample_rate: AtomicUsize,
+    /// One per input note port. Sized from `Limits::max_buses` at load.
+    note_dialects: Vec<NoteDialects>,
     active: AtomicBool,
     processing: AtomicBool,
     last_io_config: Option<IOConfigutaion>,
-    track_details: Option<Track>,
+    track_details: Mutex<Option<Track>>,
+}
+
+/// The parts of `Clap` only the audio thread uses.
+struct ClapAudio {
+    process: clap_process,
+    in_events: EventBuffer,
+    out_events: EventBuffer,
+    /// Channel pointers for the current block. Sized from `Limits::max_channels` at load and
+    /// shared by `process` and `process_f64`.
+    input_pointers: Vec<*mut c_void>,
+    output_pointers: Vec<*mut c_void>,
+    /// One buffer per audio port, pointing into `input_pointers` and `output_pointers`. Sized
+    /// from `Limits::max_buses` at load.
+    input_buffers: Vec<clap_audio_buffer>,
+    output_buffers: Vec<clap_audio_buffer>,
+    /// Rebuilt from `ProcessDetails` every block.
+    transport: clap_event_transport,
+    /// Samples processed by this instance. Passed as `clap_process::steady_time`.
+    steady_time: i64,
+    events: HeapProd<PluginIssuedEvent>,
+    /// The audio thread's copy of `HostData::param_ranges`, replaced from
+    /// `HostData::param_ranges_update`.
+    param_ranges: Vec<ParamRange>,
+    retired_param_ranges: HeapProd<Vec<ParamRange>>,
+}
+
+/// Sized from `Limits::max_events_per_block` at load and never grown.
+type EventBuffer = Vec<ClapEvent>;
+
+#[derive(Debug, Clone, Copy)]
+struct ParamRange {
+    id: clap_id,
+    min: f64,
+    max: f64,
 }
 
-type EventBuffer = HeaplessVec<ClapEvent, 64>;
+impl ParamRange {
+    fn to_plain(self, normalized: f64) -> f64 {
+        self.min + (self.max - self.min) * normalized
+    }
+
+    fn to_normalized(self, plain: f64) -> f64 {
+        if self.max <= self.min {
+            return 0.;
+        }
+
+        ((plain - self.min) / (self.max - self.min)).clamp(0., 1.)
+    }
+}
+
+/// Note dialects of an input note port. Written on the main thread when the IO configuration is
+/// read and used on the audio thread to translate events.
+struct NoteDialects {
+    supported: AtomicU32,
+    preferred: AtomicU32,
+}
+
+impl Default for NoteDialects {
+    /// For plugins without the note-ports extension.
+    fn default() -> Self {
+        Self {
+            supported: AtomicU32::new(CLAP_NOTE_DIALECT_CLAP | CLAP_NOTE_DIALECT_MIDI),
+            preferred: AtomicU32::new(CLAP_NOTE_DIALECT_CLAP),
+        }
+    }
+}
+
+impl NoteDialects {
+    fn set(&self, info: &clap_note_port_info) {
+        self.supported
+            .store(info.supported_dialects, Ordering::Relaxed);
+        self.preferred
+            .store(info.preferred_dialect, Ordering::Relaxed);
+    }
+
+    /// Whether MIDI 1.0 notes should be sent as CLAP note events.
+    fn clap_notes(&self) -> bool {
+        let supported = self.supported.load(Ordering::Relaxed);
+
+        match self.preferred.load(Ordering::Relaxed) {
+            CLAP_NOTE_DIALECT_CLAP => true,
+            CLAP_NOTE_DIALECT_MIDI | CLAP_NOTE_DIALECT_MIDI_MPE => false,
+            _ => {
+                supported & CLAP_NOTE_DIALECT_CLAP != 0
+                    || supported & (CLAP_NOTE_DIALECT_MIDI | CLAP_NOTE_DIALECT_MIDI_MPE) == 0
+            }
+        }
+    }
+
+    fn midi(&self) -> bool {
+        self.supported.load(Ordering::Relaxed)
+            & (CLAP_NOTE_DIALECT_MIDI | CLAP_NOTE_DIALECT_MIDI_MPE)
+            != 0
+    }
+
+    fn midi2(&self) -> bool {
+        self.supported.load(Ordering::Relaxed) & CLAP_NOTE_DIALECT_MIDI2 != 0
+    }
+}
+
+/// `ctx` of the `clap_output_events` given to the plugin.
+struct OutEventsContext<'a> {
+    events: &'a mut EventBuffer,
+    /// SysEx is copied straight into here because the payload is only valid during `try_push`.
+    output_events: &'a mut OutputEvents,
+}
 
 #[derive(Clone, Copy)]
 union ClapEvent {
     header: clap_event_header,
     note: clap_event_note,
-    _note_expression: clap_event_note_expression,
+    note_expression: clap_event_note_expression,
     param_value: clap_event_param_value,
     _param_mod: clap_event_param_mod,
     _param_gesture: clap_event_param_gesture,
-    // Note: This one is kinda long. Maybe add a special case for it
-    // transport: clap_event_transport,
+    transport: clap_event_transport,
     midi: clap_event_midi,
-    _midi_sysex: clap_event_midi_sysex,
-    _midi2: clap_event_midi2,
+    midi_sysex: clap_event_midi_sysex,
+    midi2: clap_event_midi2,
 }
 
 // Everything in this must be thread-safe or not mutated.
 struct HostData {
-    plugin_issued_events_producer: HeapProd<PluginIssuedEvent>,
+    /// Pushed to from plugin callbacks on the main thread. Events from `process` go through
+    /// `ClapAudio::events` instead.
+    plugin_issued_events_producer: Mutex<HeapProd<PluginIssuedEvent>>,
     host: Host,
     plugin: *const clap_plugin,
+    /// Sorted by ID. CLAP parameter events use plain values so these convert to and from the
+    /// normalized values used by the host. Only re-read on `CLAP_PARAM_RESCAN_ALL`. Main thread
+    /// only, the audio thread has its own copy in `ClapAudio::param_ranges`.
+    param_ranges: RwLock<Vec<ParamRange>>,
+    /// A re-read copy of `param_ranges` the audio thread hasn't picked up yet. Only the newest
+    /// one matters so this is a slot rather than a queue. The audio thread only ever `try_lock`s
+    /// it.
+    param_ranges_update: Mutex<Option<Vec<ParamRange>>>,
 }
 
 unsafe fn get_extension<T>(plugin: *const clap_plugin, extension: &CStr) -> Option<&T> {
@@ -104,55 +229,92 @@ pub fn load(
     common: Common,
 ) -> Result<(Box<dyn PluginInner>, PluginDescriptor), Error> {
     unsafe {
-        let mut plugin = Clap::load_factory(path).map_err(|e| Error {
-            message: format!("Failed to load CLAP plugin factory: {}", e),
-        })?;
-        let descriptors = plugin.get_descriptors(path).map_err(|e| Error {
-            message: format!("Failed to get CLAP plugin descriptors: {}", e),
-        })?;
+        let mut plugin = Clap::load_factory(path)?;
+        let descriptors = plugin.get_descriptors(path);
 
         for descriptor in descriptors.iter() {
             if descriptor.id == id {
-                plugin.load_plugin(id, common).map_err(|e| Error {
-                    message: format!("Failed to load CLAP plugin: {}", e),
-                })?;
+                plugin.load_plugin(id, common)?;
 
                 return Ok((Box::new(plugin), descriptor.clone()));
             }
         }
 
-        Err(Error {
-            message: format!("No CLAP plugin found with ID: {}", id),
-        })
+        Err(Error::PluginNotFoundInFactory(id.to_string()))
     }
 }
 
 pub(crate) fn get_descriptor(path: &Path) -> Vec<PluginDescriptor> {
     unsafe {
         Clap::load_factory(path)
-            .and_then(|p| p.get_descriptors(path))
+            .map(|p| {
+                let mut descriptors = p.get_descriptors(path);
+                for descriptor in descriptors.iter_mut() {
+                    p.probe_descriptor(descriptor);
+                }
+                descriptors
+            })
             .unwrap_or(vec![])
     }
 }
 
+/// Null-terminated CLAP string that may be null.
+unsafe fn optional_c_string(s: *const c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+
+    CStr::from_ptr(s).to_string_lossy().into_owned()
+}
+
+/// Host given to plugins created while scanning. It has no extensions and ignores requests, the
+/// plugin is destroyed again before it could act on them.
+unsafe extern "C" fn clap_scan_callback_get_extension(
+    _host: *const clap_host,
+    _ext: *const c_char,
+) -> *const c_void {
+    std::ptr::null()
+}
+
+unsafe extern "C" fn clap_scan_callback_ignore(_host: *const clap_host) {}
+
 impl Clap {
-    unsafe fn load_factory(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+    unsafe fn load_factory(path: &Path) -> Result<Self, Error> {
         let Some(path) = macos_exec_location(path) else {
-            return Err(Box::new(crate::error::Error {
-                message: "Not a valid Mac plugin".to_string(),
-            }));
+            return Err(Error::LibraryLoadFailed(
+                "Not a valid Mac plugin".to_string(),
+            ));
         };
 
-        let lib = libloading::Library::new(&path)?;
-        let entry_symbol: libloading::Symbol<*const clap_plugin_entry> = lib.get(b"clap_entry")?;
+        let lib = libloading::Library::new(&path)
+            .map_err(|e| Error::LibraryLoadFailed(e.to_string()))?;
+        let entry_symbol: libloading::Symbol<*const clap_plugin_entry> = lib
+            .get(b"clap_entry")
+            .map_err(|_| Error::EntryMissing("clap_entry"))?;
         let entry = *entry_symbol;
 
         let path_cstr = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
 
-        (*entry).init.unwrap()(path_cstr.as_ptr());
+        if !(*entry).init.unwrap()(path_cstr.as_ptr()) {
+            return Err(Error::InitFailed(
+                "CLAP entry failed to initialize".to_string(),
+            ));
+        }
+
         let factory = (*entry).get_factory.unwrap()(CLAP_PLUGIN_FACTORY_ID.as_ptr())
             as *const clap_plugin_factory;
 
+        if factory.is_null() {
+            (*entry).deinit.unwrap()();
+            return Err(Error::EntryMissing("clap.plugin-factory"));
+        }
+
+        let (audio_events_producer, audio_events_consumer) =
+            HeapRb::<PluginIssuedEvent>::new(512).split();
+        // Replaced ranges are sent back to be dropped on the main thread.
+        let (retired_param_ranges_producer, retired_param_ranges_consumer) =
+            HeapRb::<Vec<ParamRange>>::new(8).split();
+
         Ok(Clap {
             lib,
             factory,
@@ -160,15 +322,29 @@ impl Clap {
             host: None,
             host_data: None,
             plugin: std::ptr::null_mut(),
-            process: zeroed(),
-            block_size: 512,
-            sample_rate: 44100,
-            in_events: HeaplessVec::new(),
-            out_events: HeaplessVec::new(),
+            audio: Mutex::new(ClapAudio {
+                process: zeroed(),
+                in_events: Vec::new(),
+                out_events: Vec::new(),
+                input_pointers: Vec::new(),
+                output_pointers: Vec::new(),
+                input_buffers: Vec::new(),
+                output_buffers: Vec::new(),
+                transport: zeroed(),
+                steady_time: 0,
+                events: audio_events_producer,
+                param_ranges: Vec::new(),
+                retired_param_ranges: retired_param_ranges_producer,
+            }),
+            audio_events: Mutex::new(audio_events_consumer),
+            retired_param_ranges: Mutex::new(retired_param_ranges_consumer),
+            block_size: AtomicUsize::new(512),
+            sample_rate: AtomicUsize::new(44100),
+            note_dialects: Vec::new(),
             active: AtomicBool::new(false),
             processing: AtomicBool::new(false),
             last_io_config: None,
-            track_details: None,
+            track_details: Mutex::new(None),
         })
     }
 
@@ -176,26 +352,38 @@ impl Clap {
         &mut self,
         id: &str,
         common: Common,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), Error> {
         assert!(!self.entry.is_null(), "CLAP entry is not initialized");
         assert!(!self.factory.is_null(), "CLAP factory is not initialized");
 
         if !clap_version_is_compatible((*self.entry).clap_version) {
-            return Err(Box::new(Error {
-                message: "Incompatible CLAP version".to_string(),
-            }));
+            return Err(Error::InitFailed("Incompatible CLAP version".to_string()));
         }
 
+        let limits = common.host.limits;
+        let audio = self.audio.get_mut().unwrap();
+        audio.in_events = Vec::with_capacity(limits.max_events_per_block);
+        audio.out_events = Vec::with_capacity(limits.max_events_per_block);
+        audio.input_pointers = Vec::with_capacity(limits.max_channels);
+        audio.output_pointers = Vec::with_capacity(limits.max_channels);
+        audio.input_buffers = Vec::with_capacity(limits.max_buses);
+        audio.output_buffers = Vec::with_capacity(limits.max_buses);
+        self.note_dialects = (0..limits.max_buses)
+            .map(|_| NoteDialects::default())
+            .collect();
+
         let host_name = std::ffi::CString::new(common.host.name).unwrap();
         let host_vendor = std::ffi::CString::new(common.host.vendor).unwrap();
         let host_version = std::ffi::CString::new(common.host.version).unwrap();
         let host_url = std::ffi::CString::new(common.host.url).unwrap();
 
         let mut host_data = Box::new(HostData {
-            plugin_issued_events_producer: common.plugin_issued_events_producer,
+            plugin_issued_events_producer: Mutex::new(common.plugin_issued_events_producer),
             // Assigned below
             plugin: std::ptr::null(),
             host: common.host,
+            param_ranges: RwLock::new(Vec::new()),
+            param_ranges_update: Mutex::new(None),
         });
 
         let clap_host_ = Box::new(clap_host {
@@ -216,21 +404,31 @@ impl Clap {
         let plugin =
             (*self.factory).create_plugin.unwrap()(self.factory, &*clap_host_, id_c_str.as_ptr());
 
+        if plugin.is_null() {
+            return Err(Error::PluginNotFoundInFactory(id.to_string()));
+        }
+
         if !(*plugin).init.unwrap()(plugin) {
-            return Err(Box::new(Error {
-                message: format!("Failed to initialize CLAP plugin with ID: {}", id),
-            }));
+            (*plugin).destroy.unwrap()(plugin);
+            return Err(Error::InitFailed(format!(
+                "Failed to initialize CLAP plugin with ID: {}",
+                id
+            )));
         }
 
         self.host = Some(clap_host_);
 
         host_data.plugin = plugin;
 
+        read_param_ranges(plugin, host_data.param_ranges.get_mut().unwrap());
+        self.audio.get_mut().unwrap().param_ranges =
+            host_data.param_ranges.get_mut().unwrap().clone();
+
         self.host_data = Some(host_data);
 
         self.plugin = plugin;
 
-        self.activate();
+        self.activate()?;
 
         self.last_io_config = Some(self.get_current_io_configuration());
 
@@ -249,16 +447,21 @@ impl Clap {
         Ok(())
     }
 
-    unsafe fn get_descriptors(
-        &self,
-        path: &Path,
-    ) -> Result<Vec<PluginDescriptor>, Box<dyn std::error::Error>> {
+    unsafe fn get_descriptors(&self, path: &Path) -> Vec<PluginDescriptor> {
         let count = (*self.factory).get_plugin_count.unwrap()(self.factory);
 
         let mut descriptors = Vec::with_capacity(count as usize);
 
         for i in 0..count {
             let desc = (*self.factory).get_plugin_descriptor.unwrap()(self.factory, i);
+
+            let mut features = Vec::new();
+            let mut feature = (*desc).features;
+            while !feature.is_null() && !(*feature).is_null() {
+                features.push(CStr::from_ptr(*feature).to_string_lossy().into_owned());
+                feature = feature.add(1);
+            }
+
             descriptors.push(PluginDescriptor {
                 name: std::ffi::CStr::from_ptr((*desc).name)
                     .to_string_lossy()
@@ -275,13 +478,83 @@ impl Clap {
                 path: path.to_path_buf(),
                 format: crate::discovery::Format::Clap,
                 initial_latency: 0,
+                category: PluginCategory::from_features(&features),
+                features,
+                description: optional_c_string((*desc).description),
+                url: optional_c_string((*desc).url),
+                manual_url: optional_c_string((*desc).manual_url),
+                support_url: optional_c_string((*desc).support_url),
+                ..Default::default()
             });
         }
 
-        Ok(descriptors)
+        descriptors
+    }
+
+    /// Creates the plugin to fill in what its CLAP descriptor doesn't say: whether it has an
+    /// editor and its buses. Left as `None` if it can't be created.
+    unsafe fn probe_descriptor(&self, descriptor: &mut PluginDescriptor) {
+        let empty = c"";
+        let host = clap_host {
+            clap_version: CLAP_VERSION,
+            host_data: std::ptr::null_mut(),
+            name: empty.as_ptr(),
+            vendor: empty.as_ptr(),
+            url: empty.as_ptr(),
+            version: empty.as_ptr(),
+            get_extension: Some(clap_scan_callback_get_extension),
+            request_restart: Some(clap_scan_callback_ignore),
+            request_process: Some(clap_scan_callback_ignore),
+            request_callback: Some(clap_scan_callback_ignore),
+        };
+
+        let Ok(id) = CString::new(descriptor.id.as_str()) else {
+            return;
+        };
+
+        let plugin = (*self.factory).create_plugin.unwrap()(self.factory, &host, id.as_ptr());
+        if plugin.is_null() {
+            return;
+        }
+
+        if (*plugin).init.unwrap()(plugin) {
+            let mut io = IoSummary::default();
+
+            if let Some(ports) = get_extension::<clap_plugin_audio_ports>(plugin, CLAP_EXT_AUDIO_PORTS) {
+                io.audio_inputs = ports.count.unwrap()(plugin, true) as usize;
+                io.audio_outputs = ports.count.unwrap()(plugin, false) as usize;
+
+                let main_channels = |is_input: bool| {
+                    let mut info: clap_audio_port_info = zeroed();
+                    if ports.get.unwrap()(plugin, 0, is_input, &mut info) {
+                        info.channel_count as usize
+                    } else {
+                        0
+                    }
+                };
+
+                if io.audio_inputs > 0 {
+                    io.main_input_channels = main_channels(true);
+                }
+                if io.audio_outputs > 0 {
+                    io.main_output_channels = main_channels(false);
+                }
+            }
+
+            if let Some(ports) = get_extension::<clap_plugin_note_ports>(plugin, CLAP_EXT_NOTE_PORTS) {
+                io.event_inputs = ports.count.unwrap()(plugin, true) as usize;
+                io.event_outputs = ports.count.unwrap()(plugin, false) as usize;
+            }
+
+            descriptor.has_editor =
+                Some(get_extension::<clap_plugin_gui>(plugin, CLAP_EXT_GUI).is_some());
+            descriptor.io = Some(io);
+        }
+
+        (*plugin).destroy.unwrap()(plugin);
     }
 
-    unsafe fn activate(&mut self) {
+    unsafe fn activate(&self) -> Result<(), Error> {
         ensure_main_thread("[CLAP] Clap::activate");
         if self.active.load(Ordering::Relaxed) {
             eprintln!("Clap::activate while plugin already activated");
@@ -291,19 +564,30 @@ impl Clap {
 
         if !plugin.activate.unwrap()(
             self.plugin,
-            self.sample_rate as f64,
-            self.block_size as u32,
-            self.block_size as u32,
+            self.sample_rate.load(Ordering::Relaxed) as f64,
+            self.block_size.load(Ordering::Relaxed) as u32,
+            self.block_size.load(Ordering::Relaxed) as u32,
         ) {
-            eprintln!("Failed to reactivate CLAP plugin");
-            return;
+            return Err(Error::ActivationFailed);
         }
 
         self.active
             .store(true, std::sync::atomic::Ordering::Relaxed);
+
+        Ok(())
     }
 
-    unsafe fn deactivate(&mut self) {
+    /// Reactivates the plugin after a configuration change. The plugin stays deactivated if it
+    /// refuses the new configuration.
+    unsafe fn reactivate(&self) -> Result<(), Error> {
+        if self.active.load(Ordering::Relaxed) {
+            self.deactivate();
+        }
+
+        self.activate()
+    }
+
+    unsafe fn deactivate(&self) {
         ensure_main_thread("[CLAP] Clap::deactivate");
         if !self.active.load(Ordering::Relaxed) {
             eprintln!("Clap::deactivate while plugin not activated");
@@ -317,7 +601,7 @@ impl Clap {
             .store(false, std::sync::atomic::Ordering::Relaxed);
     }
 
-    unsafe fn start_processing(&mut self) {
+    unsafe fn start_processing(&self) {
         ensure_non_main_thread("[CLAP] Clap::set_processing");
         if !self.active.load(Ordering::Relaxed) || self.processing.load(Ordering::Relaxed) {
             eprintln!("Clap::start_processing while plugin not activated or already processing");
@@ -331,7 +615,7 @@ impl Clap {
             .store(true, std::sync::atomic::Ordering::Relaxed);
     }
 
-    unsafe fn stop_processing(&mut self) {
+    unsafe fn stop_processing(&self) {
         ensure_non_main_thread("[CLAP] Clap::set_processing");
         if !self.active.load(Ordering::Relaxed) || !self.processing.load(Ordering::Relaxed) {
             eprintln!("Clap::stop_processing while plugin not activated or not processing");
@@ -344,12 +628,133 @@ impl Clap {
         self.processing.store(false, Ordering::Relaxed);
     }
 
+    /// Shared by `process` and `process_f64` once `input_buffers` and `output_buffers` are set
+    /// up.
+    unsafe fn process_buffers(
+        &self,
+        audio: &mut ClapAudio,
+        events: &[crate::event::HostIssuedEvent],
+        output_events: &mut OutputEvents,
+        process_details: &crate::ProcessDetails,
+    ) -> ProcessStatus {
+        // Left deactivated by a configuration it refused.
+        if !self.active.load(Ordering::Relaxed) {
+            return ProcessStatus::Error;
+        }
+
+        if !self.processing.load(Ordering::Relaxed) {
+            self.start_processing();
+        }
+
+        let plugin = *self.plugin;
+        let host_data = self.host_data.as_ref().unwrap();
+
+        audio.process.frames_count = process_details.block_size as u32;
+        audio.process.steady_time = audio.steady_time;
+
+        audio.transport = clap_transport(&process_details.transport(), 0);
+        audio.process.transport = &audio.transport;
+
+        audio.process.audio_inputs = audio.input_buffers.as_ptr();
+        audio.process.audio_inputs_count = audio.input_buffers.len() as u32;
+        audio.process.audio_outputs = audio.output_buffers.as_mut_ptr();
+        audio.process.audio_outputs_count = audio.output_buffers.len() as u32;
+
+        // `AudioHandle` never sends more than `Limits::max_events_per_block` events.
+        audio.in_events.clear();
+
+        // Left for the next block if the main thread is writing it or hasn't dropped the ranges
+        // replaced before yet.
+        if !audio.retired_param_ranges.is_full() {
+            if let Ok(mut update) = host_data.param_ranges_update.try_lock() {
+                if let Some(param_ranges) = update.take() {
+                    let old = std::mem::replace(&mut audio.param_ranges, param_ranges);
+                    // Dropping it here would deallocate on the audio thread.
+                    let _ = audio.retired_param_ranges.try_push(old);
+                }
+            }
+        }
+
+        let default_dialects = NoteDialects::default();
+        for event in events.iter().take(audio.in_events.capacity()) {
+            let dialects = self
+                .note_dialects
+                .get(event.bus_index)
+                .unwrap_or(&default_dialects);
+
+            if let Some(clap_event) =
+                create_clap_event(event.clone(), dialects, &audio.param_ranges)
+            {
+                audio.in_events.push(clap_event);
+            }
+        }
+
+        let in_events = clap_input_events {
+            ctx: &audio.in_events as *const EventBuffer as *mut c_void,
+            size: Some(clap_callback_events_size),
+            get: Some(clap_callback_events_get),
+        };
+
+        let mut out_events_context = OutEventsContext {
+            events: &mut audio.out_events,
+            output_events,
+        };
+
+        let out_events = clap_output_events {
+            ctx: &mut out_events_context as *mut OutEventsContext as *mut c_void,
+            try_push: Some(clap_callback_events_try_push),
+        };
+
+        audio.process.in_events = &in_events as *const clap_input_events;
+        audio.process.out_events = &out_events as *const clap_output_events;
+
+        let status = plugin.process.unwrap()(self.plugin, &audio.process);
+
+        for out_event in audio.out_events.iter() {
+            match out_event.header.type_ {
+                CLAP_EVENT_PARAM_VALUE => {
+                    let param_value = out_event.param_value;
+                    let Some(range) = find_param_range(&audio.param_ranges, param_value.param_id)
+                    else {
+                        continue;
+                    };
+                    let value = range.to_normalized(param_value.value) as f32;
+
+                    audio
+                        .events
+                        .try_push(PluginIssuedEvent::Parameter(
+                            crate::parameter::ParameterUpdate {
+                                parameter_id: ParamId(param_value.param_id),
+                                parameter_index: -1,
+                                current_value: value,
+                                initial_value: value,
+                                end_edit: false,
+                            },
+                        ))
+                        .ok();
+                }
+                _ => {}
+            }
+        }
+
+        audio.in_events.clear();
+        audio.out_events.clear();
+
+        audio.steady_time += process_details.block_size as i64;
+
+        match status {
+            CLAP_PROCESS_ERROR => ProcessStatus::Error,
+            CLAP_PROCESS_SLEEP => ProcessStatus::Sleeping,
+            _ => ProcessStatus::Ok,
+        }
+    }
+
     fn get_current_io_configuration(&self) -> IOConfigutaion {
         ensure_main_thread("[CLAP] Clap::get_io_configuration");
 
         unsafe {
-            let mut audio_inputs = HeaplessVec::new();
-            let mut audio_outputs = HeaplessVec::new();
+            let mut audio_inputs = Vec::new();
+            let mut audio_outputs = Vec::new();
 
             if let Some(ports) =
                 get_extension::<clap_plugin_audio_ports>(self.plugin, CLAP_EXT_AUDIO_PORTS)
@@ -357,9 +762,6 @@ impl Clap {
                 let input_count = ports.count.unwrap()(self.plugin, true);
                 let output_count = ports.count.unwrap()(self.plugin, false);
 
-                assert!(input_count <= 16);
-                assert!(output_count <= 16);
-
                 for i in 0..input_count {
                     let is_input = true;
 
@@ -367,11 +769,9 @@ impl Clap {
 
                     ports.get.unwrap()(self.plugin, i, is_input, &mut info);
 
-                    audio_inputs
-                        .push(AudioBusDescriptor {
-                            channels: info.channel_count as usize,
-                        })
-                        .unwrap();
+                    audio_inputs.push(AudioBusDescriptor {
+                        channels: info.channel_count as usize,
+                    });
                 }
 
                 for i in 0..output_count {
@@ -381,30 +781,196 @@ impl Clap {
 
                     ports.get.unwrap()(self.plugin, i, is_input, &mut info);
 
-                    audio_outputs
-                        .push(AudioBusDescriptor {
-                            channels: info.channel_count as usize,
-                        })
-                        .unwrap();
+                    audio_outputs.push(AudioBusDescriptor {
+                        channels: info.channel_count as usize,
+                    });
+                }
+            }
+
+            let mut event_inputs_count = 0;
+
+            if let Some(ports) =
+                get_extension::<clap_plugin_note_ports>(self.plugin, CLAP_EXT_NOTE_PORTS)
+            {
+                event_inputs_count = ports.count.unwrap()(self.plugin, true);
+
+                for i in 0..event_inputs_count {
+                    let mut info: clap_note_port_info = zeroed();
+
+                    if ports.get.unwrap()(self.plugin, i, true, &mut info) {
+                        if let Some(dialects) = self.note_dialects.get(i as usize) {
+                            dialects.set(&info);
+                        }
+                    }
                 }
             }
 
             crate::audio_bus::IOConfigutaion {
                 audio_inputs,
                 audio_outputs,
-                event_inputs_count: 0,
+                event_inputs_count: event_inputs_count as i32,
             }
         }
     }
+
+    fn param_range(&self, id: ParamId) -> Option<ParamRange> {
+        find_param_range(&self.host_data.as_ref()?.param_ranges.read().unwrap(), id.0)
+    }
+
+    unsafe fn params(&self) -> Result<&clap_plugin_params, Error> {
+        get_extension::<clap_plugin_params>(self.plugin, CLAP_EXT_PARAMS)
+            .ok_or(Error::ExtensionMissing("clap.params"))
+    }
+}
+
+/// Must be called while the plugin is deactivated.
+unsafe fn read_param_ranges(plugin: *const clap_plugin, ranges: &mut Vec<ParamRange>) {
+    ranges.clear();
+
+    let Some(params) = get_extension::<clap_plugin_params>(plugin, CLAP_EXT_PARAMS) else {
+        return;
+    };
+
+    for index in 0..params.count.unwrap()(plugin) {
+        let mut info: clap_param_info = zeroed();
+        if params.get_info.unwrap()(plugin, index, &mut info) {
+            ranges.push(ParamRange {
+                id: info.id,
+                min: info.min_value,
+                max: info.max_value,
+            });
+        }
+    }
+
+    ranges.sort_unstable_by_key(|range| range.id);
 }
 
-fn access_host_data<'a>(host: &'a mut clap_host) -> &'a mut HostData {
-    unsafe {
-        let host_data_ptr = (*host).host_data as *mut HostData;
-        &mut *host_data_ptr
+fn find_param_range(ranges: &[ParamRange], id: clap_id) -> Option<ParamRange> {
+    ranges
+        .binary_search_by_key(&id, |range| range.id)
+        .ok()
+        .map(|index| ranges[index])
+}
+
+unsafe fn clap_value_to_text(
+    plugin: *const clap_plugin,
+    params: &clap_plugin_params,
+    id: clap_id,
+    value: f64,
+) -> Option<String> {
+    let mut buffer = [0 as c_char; 256];
+
+    if !params.value_to_text?(plugin, id, value, buffer.as_mut_ptr(), buffer.len() as u32) {
+        return None;
+    }
+
+    Some(CStr::from_ptr(buffer.as_ptr()).to_string_lossy().into_owned())
+}
+
+/// Seconds positions assume the tempo is constant, same as the VST2 and VST3 time info.
+fn clap_transport(transport: &Transport, time: u32) -> clap_event_transport {
+    let beats = |ppq: f64| (ppq * CLAP_BEATTIME_FACTOR as f64).round() as i64;
+    let seconds = |ppq: f64| (ppq * 60. / transport.tempo * CLAP_SECTIME_FACTOR as f64).round() as i64;
+
+    let mut flags = CLAP_TRANSPORT_HAS_TEMPO
+        | CLAP_TRANSPORT_HAS_BEATS_TIMELINE
+        | CLAP_TRANSPORT_HAS_SECONDS_TIMELINE
+        | CLAP_TRANSPORT_HAS_TIME_SIGNATURE;
+
+    if transport.playing_state.is_playing() {
+        flags |= CLAP_TRANSPORT_IS_PLAYING;
+    }
+
+    if transport.playing_state == crate::PlayingState::Recording {
+        flags |= CLAP_TRANSPORT_IS_RECORDING;
+    }
+
+    if transport.cycle_enabled {
+        flags |= CLAP_TRANSPORT_IS_LOOP_ACTIVE;
+    }
+
+    // Quarter notes per bar.
+    let bar_length = transport.time_signature_numerator as f64 * 4.
+        / transport.time_signature_denominator.max(1) as f64;
+
+    clap_event_transport {
+        header: clap_event_header {
+            size: std::mem::size_of::<clap_event_transport>() as u32,
+            time,
+            space_id: CLAP_CORE_EVENT_SPACE_ID,
+            type_: CLAP_EVENT_TRANSPORT,
+            flags: 0,
+        },
+        flags,
+        song_pos_beats: beats(transport.player_time),
+        song_pos_seconds: seconds(transport.player_time),
+        tempo: transport.tempo,
+        tempo_inc: 0.,
+        loop_start_beats: beats(transport.cycle_start),
+        loop_end_beats: beats(transport.cycle_end),
+        loop_start_seconds: seconds(transport.cycle_start),
+        loop_end_seconds: seconds(transport.cycle_end),
+        bar_start: beats(transport.bar_start_pos),
+        bar_number: (transport.bar_start_pos / bar_length).round() as i32,
+        tsig_num: transport.time_signature_numerator as u16,
+        tsig_denom: transport.time_signature_denominator as u16,
+    }
+}
+
+/// Builds one `clap_audio_buffer` per bus. `pointers` is never grown past the capacity reserved
+/// at load so the buffers can point into it. `AudioHandle` checks the IO configuration against
+/// the limits before processing so nothing should be cut off. Only inputs carry the caller's
+/// constant mask, the plugin sets its own on the outputs.
+fn fill_audio_buffers<T>(
+    buffers: &mut Vec<clap_audio_buffer>,
+    pointers: &mut Vec<*mut c_void>,
+    buses: &[crate::audio_bus::AudioBus<T>],
+    f64: bool,
+    is_input: bool,
+) {
+    buffers.clear();
+    pointers.clear();
+
+    for bus in buses.iter().take(buffers.capacity()) {
+        let start = pointers.len();
+        for channel in bus.data.iter() {
+            if pointers.len() == pointers.capacity() {
+                break;
+            }
+            pointers.push(channel.as_ptr() as *mut c_void);
+        }
+
+        let data = unsafe { pointers.as_mut_ptr().add(start) };
+        let (data32, data64) = if f64 {
+            (std::ptr::null_mut(), data as *mut *mut f64)
+        } else {
+            (data as *mut *mut f32, std::ptr::null_mut())
+        };
+
+        buffers.push(clap_audio_buffer {
+            data32,
+            data64,
+            channel_count: (pointers.len() - start) as u32,
+            latency: bus.latency,
+            constant_mask: if is_input { bus.constant_mask } else { 0 },
+        });
+    }
+}
+
+/// Passes the constant mask the plugin set on each output port back to the caller.
+fn read_constant_masks<T>(
+    buffers: &[clap_audio_buffer],
+    buses: &mut [crate::audio_bus::AudioBus<T>],
+) {
+    for (bus, buffer) in buses.iter_mut().zip(buffers) {
+        bus.constant_mask = buffer.constant_mask;
     }
 }
 
+unsafe fn access_host_data<'a>(host: *const clap_host) -> &'a HostData {
+    &*((*host).host_data as *const HostData)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn clap_callback_request_restart(host: *const clap_host) {}
 
@@ -439,6 +1005,22 @@ pub unsafe extern "C" fn clap_callback_get_extension(
         // static PORTS: clap_host_audio_ports = clap_host_audio_ports { is_rescan_flag_supported: todo!(), rescan: todo!() };
 
         // return &PORTS as *const _ as *const c_void;
+    } else if CStr::from_ptr(ext) == CLAP_EXT_PARAMS {
+        static PARAMS: clap_host_params = clap_host_params {
+            rescan: Some(clap_callback_params_rescan),
+            clear: Some(clap_callback_params_clear),
+            // Queued changes are flushed by the next `process` call.
+            request_flush: Some(clap_callback_request_process),
+        };
+
+        return &PARAMS as *const _ as *const c_void;
+    } else if CStr::from_ptr(ext) == CLAP_EXT_NOTE_PORTS {
+        static NOTE_PORTS: clap_host_note_ports = clap_host_note_ports {
+            supported_dialects: Some(clap_callback_note_dialects),
+            rescan: Some(clap_callback_note_ports_rescan),
+        };
+
+        return &NOTE_PORTS as *const _ as *const c_void;
     } else if CStr::from_ptr(ext) == CLAP_EXT_AUDIO_PORTS_CONFIG {
         static PORTS_CONFIG: clap_host_audio_ports_config = clap_host_audio_ports_config {
             rescan: Some(clap_callback_send_io_changed),
@@ -488,19 +1070,70 @@ pub unsafe extern "C" fn clap_callback_resize_window(
     w: u32,
     h: u32,
 ) -> bool {
-    access_host_data(&mut *(host as *mut _))
+    access_host_data(host)
         .plugin_issued_events_producer
+        .lock()
+        .unwrap()
         .try_push(PluginIssuedEvent::ResizeWindow(w as usize, h as usize))
         .is_ok()
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn clap_callback_send_io_changed(host: *const clap_host) {
-    let _ = access_host_data(&mut *(host as *mut _))
+    let _ = access_host_data(host)
         .plugin_issued_events_producer
+        .lock()
+        .unwrap()
         .try_push(PluginIssuedEvent::IOChanged);
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn clap_callback_note_dialects(_host: *const clap_host) -> clap_note_dialect {
+    CLAP_NOTE_DIALECT_CLAP | CLAP_NOTE_DIALECT_MIDI | CLAP_NOTE_DIALECT_MIDI_MPE | CLAP_NOTE_DIALECT_MIDI2
+}
+
+/// The dialects are read again with the IO configuration.
+#[no_mangle]
+pub unsafe extern "C" fn clap_callback_note_ports_rescan(host: *const clap_host, _flags: u32) {
+    clap_callback_send_io_changed(host);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn clap_callback_params_rescan(
+    host: *const clap_host,
+    flags: clap_param_rescan_flags,
+) {
+    let host_data = access_host_data(host);
+    let list = flags & CLAP_PARAM_RESCAN_ALL != 0;
+
+    // The plugin is read after `init` if this is called during it.
+    if list && !host_data.plugin.is_null() {
+        let mut param_ranges = host_data.param_ranges.write().unwrap();
+        read_param_ranges(host_data.plugin, &mut param_ranges);
+        // Replaces ranges the audio thread hasn't picked up yet, dropping them here.
+        *host_data.param_ranges_update.lock().unwrap() = Some(param_ranges.clone());
+    }
+
+    let _ = host_data
+        .plugin_issued_events_producer
+        .lock()
+        .unwrap()
+        .try_push(PluginIssuedEvent::ParametersChanged {
+            values: list || flags & CLAP_PARAM_RESCAN_VALUES != 0,
+            info: list || flags & (CLAP_PARAM_RESCAN_INFO | CLAP_PARAM_RESCAN_TEXT) != 0,
+            list,
+        });
+}
+
+/// The host doesn't keep automation or modulation per parameter so there's nothing to clear.
+#[no_mangle]
+pub unsafe extern "C" fn clap_callback_params_clear(
+    _host: *const clap_host,
+    _param_id: clap_id,
+    _flags: clap_param_clear_flags,
+) {
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn clap_callback_is_main_thread(_host: *const clap_host) -> bool {
     is_main_thread()
@@ -513,8 +1146,10 @@ pub unsafe extern "C" fn clap_callback_is_audio_thread(_host: *const clap_host)
 
 #[no_mangle]
 pub unsafe extern "C" fn clap_callback_send_request_editor_open(host: *const clap_host) -> bool {
-    let _ = access_host_data(&mut *(host as *mut _))
+    let _ = access_host_data(host)
         .plugin_issued_events_producer
+        .lock()
+        .unwrap()
         .try_push(PluginIssuedEvent::RequestEditorOpen);
 
     // Note: The host may not actually handle this. There may need to be some kind of "can do" for
@@ -524,8 +1159,10 @@ pub unsafe extern "C" fn clap_callback_send_request_editor_open(host: *const cla
 
 #[no_mangle]
 pub unsafe extern "C" fn clap_callback_send_request_editor_close(host: *const clap_host) -> bool {
-    let _ = access_host_data(&mut *(host as *mut _))
+    let _ = access_host_data(host)
         .plugin_issued_events_producer
+        .lock()
+        .unwrap()
         .try_push(PluginIssuedEvent::RequestEditorClose);
 
     // Note: The host may not actually handle this. There may need to be some kind of "can do" for
@@ -535,7 +1172,7 @@ pub unsafe extern "C" fn clap_callback_send_request_editor_close(host: *const cl
 
 #[no_mangle]
 pub unsafe extern "C" fn clap_callback_tail_changed(host: *const clap_host) {
-    let host_data = access_host_data(&mut *(host as *mut _));
+    let host_data = access_host_data(host);
 
     // TODO: get this value's initial state and send an event for it.
 
@@ -544,6 +1181,8 @@ pub unsafe extern "C" fn clap_callback_tail_changed(host: *const clap_host) {
 
         let _ = host_data
             .plugin_issued_events_producer
+            .lock()
+            .unwrap()
             .try_push(PluginIssuedEvent::TailLengthChanged(tail as usize));
     }
 }
@@ -594,15 +1233,94 @@ pub unsafe extern "C" fn clap_ostream_write(
     0
 }
 
+/// Notes, MIDI and SysEx from the plugin go straight to `OutputEvents`, everything else is
+/// handled after `process` returns.
+unsafe fn clap_output_event<'a>(event: *const clap_event_header) -> Option<OutputEvent<'a>> {
+    if (*event).space_id != CLAP_CORE_EVENT_SPACE_ID {
+        return None;
+    }
+
+    let block_time = (*event).time as usize;
+
+    match (*event).type_ {
+        CLAP_EVENT_NOTE_ON | CLAP_EVENT_NOTE_OFF | CLAP_EVENT_NOTE_END => {
+            let note = &*(event as *const clap_event_note);
+            let bus_index = note.port_index.max(0) as usize;
+
+            Some(match (*event).type_ {
+                CLAP_EVENT_NOTE_ON => OutputEvent::NoteOn {
+                    block_time,
+                    bus_index,
+                    channel: note.channel,
+                    key: note.key,
+                    velocity: note.velocity,
+                    note_id: note.note_id,
+                },
+                CLAP_EVENT_NOTE_OFF => OutputEvent::NoteOff {
+                    block_time,
+                    bus_index,
+                    channel: note.channel,
+                    key: note.key,
+                    velocity: note.velocity,
+                    note_id: note.note_id,
+                },
+                _ => OutputEvent::NoteEnd {
+                    block_time,
+                    bus_index,
+                    channel: note.channel,
+                    key: note.key,
+                    note_id: note.note_id,
+                },
+            })
+        }
+        CLAP_EVENT_MIDI => {
+            let midi = &*(event as *const clap_event_midi);
+
+            Some(OutputEvent::Midi {
+                block_time,
+                bus_index: midi.port_index as usize,
+                data: midi.data,
+            })
+        }
+        CLAP_EVENT_MIDI_SYSEX => {
+            let sysex = &*(event as *const clap_event_midi_sysex);
+            let data = if sysex.buffer.is_null() {
+                &[]
+            } else {
+                std::slice::from_raw_parts(sysex.buffer, sysex.size as usize)
+            };
+
+            Some(OutputEvent::SysEx {
+                block_time,
+                bus_index: sysex.port_index as usize,
+                data,
+            })
+        }
+        _ => None,
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn clap_callback_events_try_push(
     list: *const clap_output_events,
     event: *const clap_event_header,
 ) -> bool {
-    let buffer = (*list).ctx as *mut EventBuffer;
+    let context = &mut *((*list).ctx as *mut OutEventsContext);
+
+    if let Some(output_event) = clap_output_event(event) {
+        context.output_events.push(output_event);
+        return true;
+    }
+
+    let buffer = &mut *context.events;
 
     let event_len = (*event).size as usize;
 
+    // Don't grow the buffer on the audio thread or copy events bigger than `ClapEvent`.
+    if buffer.len() == buffer.capacity() || event_len > std::mem::size_of::<ClapEvent>() {
+        return false;
+    }
+
     let mut new_event: ClapEvent = zeroed();
 
     std::ptr::copy_nonoverlapping(
@@ -611,7 +1329,8 @@ pub unsafe extern "C" fn clap_callback_events_try_push(
         event_len,
     );
 
-    (*buffer).push(new_event).is_ok()
+    buffer.push(new_event);
+    true
 }
 
 #[no_mangle]
@@ -625,9 +1344,8 @@ pub unsafe extern "C" fn clap_callback_events_get(
     list: *const clap_input_events,
     index: u32,
 ) -> *const clap_event_header {
-    let buffer = (*list).ctx as *const EventBuffer;
-    (*buffer)
-        .as_slice()
+    let buffer = &*((*list).ctx as *const EventBuffer);
+    buffer
         .get(index as usize)
         .map_or(std::ptr::null(), |event| {
             event as *const _ as *const clap_event_header
@@ -709,118 +1427,112 @@ impl Drop for Clap {
 
 impl PluginInner for Clap {
     fn process(
-        &mut self,
+        &self,
         inputs: &[crate::audio_bus::AudioBus<f32>],
         outputs: &mut [crate::audio_bus::AudioBus<f32>],
-        events: Vec<crate::event::HostIssuedEvent>,
+        events: &[crate::event::HostIssuedEvent],
+        output_events: &mut OutputEvents,
         process_details: &crate::ProcessDetails,
-    ) {
-        unsafe {
-            if !self.processing.load(Ordering::Relaxed) {
-                self.start_processing();
-            }
-
-            let plugin = *self.plugin;
-
-            self.process.frames_count = process_details.block_size as u32;
-            self.process.steady_time = -1;
-
-            let mut input_pointers = HeaplessVec::<*mut f32, 16>::new();
-            for input in inputs {
-                for channel in input.data.iter() {
-                    let _ = input_pointers.push(channel.as_ptr() as *mut f32);
-                }
-            }
-
-            let inputs = clap_audio_buffer {
-                data32: input_pointers.as_mut_ptr(),
-                data64: std::ptr::null_mut(),
-                channel_count: input_pointers.len() as u32,
-                latency: 0,
-                constant_mask: 0,
-            };
-
-            let mut output_pointers = HeaplessVec::<*mut f32, 16>::new();
-            for output in outputs {
-                for channel in output.data.iter() {
-                    let _ = output_pointers.push(channel.as_ptr() as *mut f32);
-                }
-            }
-
-            let mut outputs = clap_audio_buffer {
-                data32: output_pointers.as_mut_ptr(),
-                data64: std::ptr::null_mut(),
-                channel_count: output_pointers.len() as u32,
-                latency: 0,
-                constant_mask: 0,
-            };
-
-            self.process.audio_inputs = &inputs as *const clap_audio_buffer;
-            self.process.audio_outputs = &mut outputs as *mut clap_audio_buffer;
+    ) -> ProcessStatus {
+        let Ok(mut audio) = self.audio.try_lock() else {
+            return ProcessStatus::Error;
+        };
+        let audio = &mut *audio;
+
+        fill_audio_buffers(
+            &mut audio.input_buffers,
+            &mut audio.input_pointers,
+            inputs,
+            false,
+            true,
+        );
+        fill_audio_buffers(
+            &mut audio.output_buffers,
+            &mut audio.output_pointers,
+            outputs,
+            false,
+            false,
+        );
+
+        let status = unsafe { self.process_buffers(audio, events, output_events, process_details) };
+        read_constant_masks(&audio.output_buffers, outputs);
+
+        status
+    }
 
-            self.in_events.clear();
+    fn process_f64(
+        &self,
+        inputs: &[crate::audio_bus::AudioBus<f64>],
+        outputs: &mut [crate::audio_bus::AudioBus<f64>],
+        events: &[crate::event::HostIssuedEvent],
+        output_events: &mut OutputEvents,
+        process_details: &crate::ProcessDetails,
+    ) -> ProcessStatus {
+        let Ok(mut audio) = self.audio.try_lock() else {
+            return ProcessStatus::Error;
+        };
+        let audio = &mut *audio;
+
+        fill_audio_buffers(
+            &mut audio.input_buffers,
+            &mut audio.input_pointers,
+            inputs,
+            true,
+            true,
+        );
+        fill_audio_buffers(
+            &mut audio.output_buffers,
+            &mut audio.output_pointers,
+            outputs,
+            true,
+            false,
+        );
+
+        let status = unsafe { self.process_buffers(audio, events, output_events, process_details) };
+        read_constant_masks(&audio.output_buffers, outputs);
+
+        status
+    }
 
-            for event in events {
-                let new_event = create_clap_event(event);
+    // Note expressions only go with notes sent as CLAP events, ports that get MIDI notes get
+    // the MPE MIDI too.
+    fn takes_note_expressions(&self, bus_index: usize) -> bool {
+        self.note_dialects
+            .get(bus_index)
+            .is_none_or(NoteDialects::clap_notes)
+    }
 
-                let _ = self.in_events.push(new_event);
-            }
+    fn supports_f64(&self) -> bool {
+        ensure_main_thread("[CLAP] Clap::supports_f64");
 
-            let in_events = clap_input_events {
-                ctx: &self.in_events as *const EventBuffer as *mut c_void,
-                size: Some(clap_callback_events_size),
-                get: Some(clap_callback_events_get),
+        unsafe {
+            let Some(ports) =
+                get_extension::<clap_plugin_audio_ports>(self.plugin, CLAP_EXT_AUDIO_PORTS)
+            else {
+                return false;
             };
 
-            let out_events = clap_output_events {
-                ctx: &mut self.out_events as *mut EventBuffer as *mut c_void,
-                try_push: Some(clap_callback_events_try_push),
-            };
+            for is_input in [true, false] {
+                for i in 0..ports.count.unwrap()(self.plugin, is_input) {
+                    let mut info: clap_audio_port_info = zeroed();
+                    ports.get.unwrap()(self.plugin, i, is_input, &mut info);
 
-            self.process.in_events = &in_events as *const clap_input_events;
-            self.process.out_events = &out_events as *const clap_output_events;
-
-            // self.process.in_events = &self.in_events as *const clap_input_events;
-
-            plugin.process.unwrap()(self.plugin, &self.process);
-
-            for out_event in self.out_events.iter() {
-                println!("[CLAP] Event: {}", out_event.header.type_);
-
-                match out_event.header.type_ {
-                    CLAP_EVENT_PARAM_VALUE => {
-                        let param_value = out_event.param_value;
-
-                        self.host_data
-                            .as_mut()
-                            .unwrap()
-                            .plugin_issued_events_producer
-                            .try_push(PluginIssuedEvent::Parameter(
-                                crate::parameter::ParameterUpdate {
-                                    parameter_id: param_value.param_id as i32,
-                                    parameter_index: param_value.param_id as i32,
-                                    current_value: param_value.value as f32,
-                                    initial_value: param_value.value as f32,
-                                    end_edit: false,
-                                },
-                            ))
-                            .ok();
+                    if info.flags & CLAP_AUDIO_PORT_SUPPORTS_64BITS == 0 {
+                        return false;
                     }
-                    _ => {}
                 }
             }
 
-            self.in_events.clear();
-            self.out_events.clear();
+            true
         }
     }
 
-    fn set_preset_data(&mut self, mut data: Vec<u8>) -> Result<(), String> {
+    fn set_preset_data(&self, mut data: Vec<u8>) -> Result<(), Error> {
         ensure_main_thread("[CLAP] Clap::set_preset_data");
         unsafe {
             let Some(state) = get_extension::<clap_plugin_state>(self.plugin, CLAP_EXT_STATE)
             else {
-                return Err("Plugin does not support state extension".to_string());
+                return Err(Error::ExtensionMissing("clap.state"));
             };
 
             // See comment in `clap_istream_read`
@@ -832,19 +1544,19 @@ impl PluginInner for Clap {
             };
 
             if !state.load.unwrap()(self.plugin, &stream) {
-                return Err("Failed to save state".to_string());
+                return Err(Error::StateRejected("Failed to load state".to_string()));
             }
 
             Ok(())
         }
     }
 
-    fn get_preset_data(&mut self) -> Result<Vec<u8>, String> {
+    fn get_preset_data(&self) -> Result<Vec<u8>, Error> {
         ensure_main_thread("[CLAP] Clap::set_preset_data");
         unsafe {
             let Some(state) = get_extension::<clap_plugin_state>(self.plugin, CLAP_EXT_STATE)
             else {
-                return Err("Plugin does not support state extension".to_string());
+                return Err(Error::ExtensionMissing("clap.state"));
             };
 
             let mut data = vec![];
@@ -855,26 +1567,27 @@ impl PluginInner for Clap {
             };
 
             if !state.save.unwrap()(self.plugin, &stream) {
-                return Err("Failed to save state".to_string());
+                return Err(Error::StateRejected("Failed to save state".to_string()));
             }
 
             Ok(data)
         }
     }
 
-    fn get_preset_name(&mut self, id: i32) -> Result<String, String> {
-        todo!()
+    fn get_preset_name(&self, _id: i32) -> Result<String, Error> {
+        // TODO: clap.preset-load
+        Err(Error::ExtensionMissing("preset list"))
     }
 
-    fn set_preset(&mut self, id: i32) -> Result<(), String> {
-        todo!()
+    fn set_preset(&self, _id: i32) -> Result<(), Error> {
+        Err(Error::ExtensionMissing("preset list"))
     }
 
-    fn get_parameter(&self, index: i32) -> crate::parameter::Parameter {
+    fn get_parameter(&self, index: usize) -> crate::parameter::Parameter {
         unsafe {
             let Some(params) = get_extension::<clap_plugin_params>(self.plugin, CLAP_EXT_PARAMS)
             else {
-                return zeroed();
+                return Default::default();
             };
 
             // let count = params.count.unwrap()(self.plugin);
@@ -885,43 +1598,126 @@ impl PluginInner for Clap {
             }
 
             let mut value: f64 = 0.;
-            if !params.get_value.unwrap()(self.plugin, index as u32, &mut value) {
+            if !params.get_value.unwrap()(self.plugin, info.id, &mut value) {
                 // TODO: error
             }
 
-            const VALUE_STRING_CAPACITY: usize = 256;
-            let value_string = HeaplessString::<VALUE_STRING_CAPACITY>::new();
+            let value_to_text =
+                |value: f64| clap_value_to_text(self.plugin, params, info.id, value).unwrap_or_default();
 
-            if !params.value_to_text.unwrap()(
-                self.plugin,
-                index as u32,
-                value,
-                value_string.data_raw() as *mut c_char,
-                VALUE_STRING_CAPACITY as u32,
-            ) {
-                // TODO: error
-            }
+            let range = info.max_value - info.min_value;
+            let normalize = |plain: f64| {
+                if range > 0. {
+                    ((plain - info.min_value) / range) as f32
+                } else {
+                    0.
+                }
+            };
+
+            let step_count = if info.flags & CLAP_PARAM_IS_STEPPED != 0 {
+                range.round().max(0.) as u32
+            } else {
+                0
+            };
+
+            // Capped so a huge integer range doesn't format thousands of values.
+            const MAX_VALUE_NAMES: u32 = 1024;
+            let value_names = if info.flags & CLAP_PARAM_IS_ENUM != 0 && step_count < MAX_VALUE_NAMES {
+                (0..=step_count)
+                    .map(|step| value_to_text(info.min_value + step as f64))
+                    .collect()
+            } else {
+                Vec::new()
+            };
 
             crate::parameter::Parameter {
-                id: index,
+                id: ParamId(info.id),
                 name: HeaplessString::from_str(
                     CStr::from_ptr(info.name.as_ptr()).to_str().unwrap(),
                 )
                 .unwrap(),
-                index,
-                value: value as f32,
+                index: index as i32,
+                value: normalize(value),
                 hidden: info.flags & CLAP_PARAM_IS_HIDDEN != 0,
                 can_automate: info.flags & CLAP_PARAM_IS_AUTOMATABLE != 0,
                 is_wrap_around: info.flags & CLAP_PARAM_IS_PERIODIC != 0,
                 read_only: info.flags & CLAP_PARAM_IS_READONLY != 0,
-                default_value: info.default_value as f32,
-                formatted_value: value_string,
+                default_value: normalize(info.default_value),
+                formatted_value: HeaplessString::from_str(&value_to_text(value)).unwrap_or_default(),
+                min_value: info.min_value,
+                max_value: info.max_value,
+                step_count,
+                unit: String::new(),
+                value_names,
+                group: CStr::from_ptr(info.module.as_ptr())
+                    .to_string_lossy()
+                    .into_owned(),
+                is_bypass: info.flags & CLAP_PARAM_IS_BYPASS != 0,
+            }
+        }
+    }
+
+    fn get_parameter_id(&self, index: usize) -> ParamId {
+        unsafe {
+            let Ok(params) = self.params() else {
+                return ParamId(index as u32);
+            };
+
+            let mut info: clap_param_info = zeroed();
+            params.get_info.unwrap()(self.plugin, index as u32, &mut info);
+
+            ParamId(info.id)
+        }
+    }
+
+    fn get_parameter_value(&self, id: ParamId) -> Result<f32, Error> {
+        unsafe {
+            let params = self.params()?;
+            let range = self.param_range(id).ok_or(Error::ParameterNotFound(id))?;
+
+            let mut plain: f64 = 0.;
+            if !params.get_value.unwrap()(self.plugin, range.id, &mut plain) {
+                return Err(Error::ParameterNotFound(id));
+            }
+
+            Ok(range.to_normalized(plain) as f32)
+        }
+    }
+
+    fn format_parameter_value(&self, id: ParamId, value: f32) -> Result<String, Error> {
+        unsafe {
+            let params = self.params()?;
+            let range = self.param_range(id).ok_or(Error::ParameterNotFound(id))?;
+            let plain = range.to_plain(value as f64);
+
+            clap_value_to_text(self.plugin, params, range.id, plain)
+                .ok_or_else(|| Error::ParameterConversionFailed(plain.to_string()))
+        }
+    }
+
+    fn parse_parameter_text(&self, id: ParamId, text: &str) -> Result<f32, Error> {
+        unsafe {
+            let params = self.params()?;
+            let range = self.param_range(id).ok_or(Error::ParameterNotFound(id))?;
+            let failed = || Error::ParameterConversionFailed(text.to_string());
+
+            let c_text = CString::new(text).map_err(|_| failed())?;
+            let mut plain: f64 = 0.;
+            if !params.text_to_value.ok_or_else(failed)?(
+                self.plugin,
+                range.id,
+                c_text.as_ptr(),
+                &mut plain,
+            ) {
+                return Err(failed());
             }
+
+            Ok(range.to_normalized(plain) as f32)
         }
     }
 
     fn show_editor(
-        &mut self,
+        &self,
         window_id: *mut std::ffi::c_void,
         window_id_type: WindowIDType,
     ) -> Result<(usize, usize), Error> {
@@ -929,9 +1725,7 @@ impl PluginInner for Clap {
             crate::thread_check::ensure_main_thread("Clap::show_editor");
 
             let Some(gui) = get_extension::<clap_plugin_gui>(self.plugin, CLAP_EXT_GUI) else {
-                return Err(Error {
-                    message: "CLAP plugin does not support GUI extension".to_string(),
-                });
+                return Err(Error::ExtensionMissing("clap.gui"));
             };
 
             let api = match window_id_type {
@@ -943,7 +1737,11 @@ impl PluginInner for Clap {
             }
             .as_ptr();
 
-            gui.create.unwrap()(self.plugin, api, false);
+            if !gui.create.unwrap()(self.plugin, api, false) {
+                return Err(Error::EditorUnavailable(
+                    "CLAP plugin failed to create its GUI".to_string(),
+                ));
+            }
 
             let wind = clap_window {
                 api,
@@ -962,7 +1760,7 @@ impl PluginInner for Clap {
         }
     }
 
-    fn hide_editor(&mut self) {
+    fn hide_editor(&self) {
         unsafe {
             let Some(gui) = get_extension::<clap_plugin_gui>(self.plugin, CLAP_EXT_GUI) else {
                 return;
@@ -972,15 +1770,15 @@ impl PluginInner for Clap {
         }
     }
 
-    fn suspend(&mut self) {}
+    fn suspend(&self) {}
 
-    fn resume(&mut self) {}
+    fn resume(&self) {}
 
-    fn get_io_configuration(&mut self) -> crate::audio_bus::IOConfigutaion {
+    fn get_io_configuration(&self) -> crate::audio_bus::IOConfigutaion {
         self.last_io_config.clone().unwrap()
     }
 
-    fn get_latency(&mut self) -> crate::Samples {
+    fn get_latency(&self) -> crate::Samples {
         unsafe {
             let Some(latency) = get_extension::<clap_plugin_latency>(self.plugin, CLAP_EXT_LATENCY)
             else {
@@ -990,6 +1788,23 @@ impl PluginInner for Clap {
         }
     }
 
+    fn editor_updates(&self) {
+        self.retired_param_ranges.lock().unwrap().clear();
+
+        let Some(host_data) = self.host_data.as_ref() else {
+            return;
+        };
+        let mut producer = host_data.plugin_issued_events_producer.lock().unwrap();
+        let mut audio_events = self.audio_events.lock().unwrap();
+        // Whatever doesn't fit is left for the next call.
+        while !producer.is_full() {
+            let Some(event) = audio_events.try_pop() else {
+                break;
+            };
+            let _ = producer.try_push(event);
+        }
+    }
+
     fn get_parameter_count(&self) -> usize {
         unsafe {
             let Some(params) = get_extension::<clap_plugin_params>(self.plugin, CLAP_EXT_PARAMS)
@@ -1001,24 +1816,22 @@ impl PluginInner for Clap {
         }
     }
 
-    fn change_sample_rate(&mut self, rate: crate::SampleRate) {
+    fn change_sample_rate(&self, rate: crate::SampleRate) -> Result<(), Error> {
         unsafe {
-            self.sample_rate = rate;
-            self.deactivate();
-            self.activate();
+            self.sample_rate.store(rate, Ordering::Relaxed);
+            self.reactivate()
         }
     }
 
-    fn change_block_size(&mut self, size: crate::BlockSize) {
+    fn change_block_size(&self, size: crate::BlockSize) -> Result<(), Error> {
         unsafe {
-            self.block_size = size;
-            self.deactivate();
-            self.activate();
+            self.block_size.store(size, Ordering::Relaxed);
+            self.reactivate()
         }
     }
 
-    fn set_track_details(&mut self, details: &crate::track::Track) {
-        self.track_details = Some(details.clone());
+    fn set_track_details(&self, details: &crate::track::Track) {
+        *self.track_details.lock().unwrap() = Some(details.clone());
 
         unsafe {
             if let Some(track_info) =
@@ -1030,7 +1843,11 @@ impl PluginInner for Clap {
     }
 }
 
-unsafe fn create_clap_event(event: HostIssuedEvent) -> ClapEvent {
+unsafe fn create_clap_event(
+    event: HostIssuedEvent,
+    dialects: &NoteDialects,
+    param_ranges: &[ParamRange],
+) -> Option<ClapEvent> {
     let mut new_event: ClapEvent = zeroed();
     new_event.header.time = event.block_time as u32;
 
@@ -1038,78 +1855,177 @@ unsafe fn create_clap_event(event: HostIssuedEvent) -> ClapEvent {
         new_event.header.flags |= CLAP_EVENT_IS_LIVE;
     }
 
-    const NOTE_ON: u8 = 0x90;
-    const NOTE_OFF: u8 = 0x80;
-
     match event.event_type {
         crate::event::HostIssuedEventType::Midi(midi_event) => {
-            match midi_event.midi_data[0] {
-                NOTE_ON => {
-                    new_event.note.header.type_ = CLAP_EVENT_NOTE_ON;
-                    new_event.note.header.size = std::mem::size_of::<clap_event_note>() as u32;
-                    new_event.note.port_index = event.bus_index as i16;
-                    new_event.note.key = midi_event.midi_data[1] as i16;
-                    new_event.note.velocity = midi_event.midi_data[2] as f64 / 127.;
-                    new_event.note.note_id = midi_event.note_id;
-                    new_event.note.channel = 0;
-                }
-                NOTE_OFF => {
-                    new_event.note.header.type_ = CLAP_EVENT_NOTE_OFF;
-                    new_event.note.header.size = std::mem::size_of::<clap_event_note>() as u32;
-                    new_event.note.port_index = event.bus_index as i16;
-                    new_event.note.key = midi_event.midi_data[1] as i16;
-                    new_event.note.velocity = midi_event.midi_data[2] as f64 / 127.;
-                    new_event.note.note_id = midi_event.note_id;
-                    new_event.note.channel = 0;
-                }
-                // 0xE0 => {
-                //     new_event._note_expression.header.type_ = CLAP_EVENT_NOTE_EXPRESSION;
-                //     new_event._note_expression.header.size = std::mem::size_of::<clap_event_note>() as u32;
-                //     new_event._note_expression.port_index = event.bus_index as i16;
-                //     new_event._note_expression.key = -1;
-                //     new_event._note_expression.note_id = -1;
-                //     new_event._note_expression.value =
-                // }
-                _ => {
-                    new_event.midi.header.type_ = CLAP_EVENT_MIDI;
-                    new_event.midi.header.size = std::mem::size_of::<clap_event_midi>() as u32;
-                    new_event.midi.port_index = event.bus_index as u16;
-                    new_event.midi.data = midi_event.midi_data;
-                }
-            };
+            return create_clap_midi_event(
+                new_event,
+                event.bus_index,
+                midi_event.midi_data,
+                midi_event.note_id,
+                dialects,
+            );
         }
         crate::event::HostIssuedEventType::Parameter(parameter_update) => {
             new_event.param_value.header.type_ = CLAP_EVENT_PARAM_VALUE;
             new_event.param_value.header.size =
                 std::mem::size_of::<clap_event_param_value>() as u32;
-            new_event.param_value.param_id = parameter_update.parameter_id as u32;
-            new_event.param_value.value = parameter_update.current_value as f64;
+            let range = find_param_range(param_ranges, parameter_update.parameter_id.0)?;
+
+            new_event.param_value.param_id = range.id;
+            new_event.param_value.value = range.to_plain(parameter_update.current_value as f64);
 
             // There's a bunch of other stuff in `clap_event_param_value` that needs to
             // be looked at.
         }
         crate::event::HostIssuedEventType::NoteExpression {
             note_id,
+            channel,
+            key,
             expression_type,
             value,
         } => {
-            new_event._note_expression.header.type_ = CLAP_EVENT_NOTE_EXPRESSION;
-            new_event._note_expression.header.size =
-                std::mem::size_of::<clap_note_expression>() as u32;
-            new_event._note_expression.port_index = event.bus_index as i16;
-            new_event._note_expression.key = -1;
-            new_event._note_expression.channel = -1;
-            new_event._note_expression.note_id = note_id;
-            new_event._note_expression.value = value as f64;
-            new_event._note_expression.expression_id = match expression_type {
+            new_event.note_expression.header.type_ = CLAP_EVENT_NOTE_EXPRESSION;
+            new_event.note_expression.header.size =
+                std::mem::size_of::<clap_event_note_expression>() as u32;
+            new_event.note_expression.port_index = event.bus_index as i16;
+            new_event.note_expression.key = key;
+            new_event.note_expression.channel = channel;
+            new_event.note_expression.note_id = note_id;
+            new_event.note_expression.value = value;
+            new_event.note_expression.expression_id = match expression_type {
                 crate::event::NoteExpressionType::Volume => CLAP_NOTE_EXPRESSION_VOLUME,
                 crate::event::NoteExpressionType::Pan => CLAP_NOTE_EXPRESSION_PAN,
                 crate::event::NoteExpressionType::Tuning => CLAP_NOTE_EXPRESSION_TUNING,
                 crate::event::NoteExpressionType::Vibrato => CLAP_NOTE_EXPRESSION_VIBRATO,
                 crate::event::NoteExpressionType::Expression => CLAP_NOTE_EXPRESSION_EXPRESSION,
                 crate::event::NoteExpressionType::Brightness => CLAP_NOTE_EXPRESSION_BRIGHTNESS,
+                crate::event::NoteExpressionType::Pressure => CLAP_NOTE_EXPRESSION_PRESSURE,
             }
         }
+        crate::event::HostIssuedEventType::Transport(transport) => {
+            new_event.transport = clap_transport(&transport, event.block_time as u32);
+        }
+        crate::event::HostIssuedEventType::SysEx(sysex) => {
+            // Points at the caller's bytes, which outlive the process call.
+            let data = sysex.as_slice();
+            new_event.midi_sysex.header.type_ = CLAP_EVENT_MIDI_SYSEX;
+            new_event.midi_sysex.header.size = std::mem::size_of::<clap_event_midi_sysex>() as u32;
+            new_event.midi_sysex.port_index = event.bus_index as u16;
+            new_event.midi_sysex.buffer = data.as_ptr();
+            new_event.midi_sysex.size = data.len() as u32;
+        }
+        crate::event::HostIssuedEventType::Midi2(ump) => {
+            if !dialects.midi2() {
+                let data = ump.to_midi1()?;
+                return create_clap_midi_event(new_event, event.bus_index, data, -1, dialects);
+            }
+
+            new_event.midi2.header.type_ = CLAP_EVENT_MIDI2;
+            new_event.midi2.header.size = std::mem::size_of::<clap_event_midi2>() as u32;
+            new_event.midi2.port_index = event.bus_index as u16;
+            new_event.midi2.data = ump.data;
+        }
+    }
+
+    Some(new_event)
+}
+
+/// Translates a MIDI 1.0 message for the port's note dialect. Notes go to plugins that prefer the
+/// CLAP dialect as note events, including all notes off (CC 123) as a note off and all sound off
+/// (CC 120) as a choke for every key on the channel. Everything else is sent as MIDI if the port
+/// supports it and dropped otherwise.
+unsafe fn create_clap_midi_event(
+    mut new_event: ClapEvent,
+    bus_index: usize,
+    data: [u8; 3],
+    note_id: i32,
+    dialects: &NoteDialects,
+) -> Option<ClapEvent> {
+    const NOTE_OFF: u8 = 0x80;
+    const NOTE_ON: u8 = 0x90;
+    const POLY_PRESSURE: u8 = 0xA0;
+    const CONTROL_CHANGE: u8 = 0xB0;
+    const ALL_SOUND_OFF: i16 = 120;
+    const ALL_NOTES_OFF: i16 = 123;
+
+    let status = data[0] & 0xF0;
+    let channel = (data[0] & 0x0F) as i16;
+    let key = (data[1] & 0x7F) as i16;
+    let value = (data[2] & 0x7F) as f64 / 127.;
+
+    if dialects.clap_notes() && data[0] < 0xF0 {
+        let note = match status {
+            NOTE_ON if data[2] > 0 => Some((CLAP_EVENT_NOTE_ON, key, value, note_id)),
+            // A note on with velocity 0 is a note off.
+            NOTE_ON | NOTE_OFF => Some((CLAP_EVENT_NOTE_OFF, key, value, note_id)),
+            CONTROL_CHANGE if key == ALL_NOTES_OFF => Some((CLAP_EVENT_NOTE_OFF, -1, 0., -1)),
+            CONTROL_CHANGE if key == ALL_SOUND_OFF => Some((CLAP_EVENT_NOTE_CHOKE, -1, 0., -1)),
+            _ => None,
+        };
+
+        if let Some((type_, key, velocity, note_id)) = note {
+            new_event.note.header.type_ = type_;
+            new_event.note.header.size = std::mem::size_of::<clap_event_note>() as u32;
+            new_event.note.port_index = bus_index as i16;
+            new_event.note.channel = channel;
+            new_event.note.key = key;
+            new_event.note.velocity = velocity;
+            new_event.note.note_id = note_id;
+            return Some(new_event);
+        }
+
+        if status == POLY_PRESSURE {
+            new_event.note_expression.header.type_ = CLAP_EVENT_NOTE_EXPRESSION;
+            new_event.note_expression.header.size =
+                std::mem::size_of::<clap_event_note_expression>() as u32;
+            new_event.note_expression.expression_id = CLAP_NOTE_EXPRESSION_PRESSURE;
+            new_event.note_expression.port_index = bus_index as i16;
+            new_event.note_expression.channel = channel;
+            new_event.note_expression.key = key;
+            new_event.note_expression.note_id = note_id;
+            new_event.note_expression.value = value;
+            return Some(new_event);
+        }
+    }
+
+    if !dialects.midi() {
+        return None;
+    }
+
+    new_event.midi.header.type_ = CLAP_EVENT_MIDI;
+    new_event.midi.header.size = std::mem::size_of::<clap_event_midi>() as u32;
+    new_event.midi.port_index = bus_index as u16;
+    new_event.midi.data = data;
+
+    Some(new_event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dialects(supported: clap_note_dialect, preferred: clap_note_dialect) -> NoteDialects {
+        NoteDialects {
+            supported: AtomicU32::new(supported),
+            preferred: AtomicU32::new(preferred),
+        }
+    }
+
+    #[test]
+    fn notes_follow_the_preferred_dialect() {
+        let mpe = dialects(
+            CLAP_NOTE_DIALECT_CLAP | CLAP_NOTE_DIALECT_MIDI_MPE,
+            CLAP_NOTE_DIALECT_MIDI_MPE,
+        );
+        assert!(!mpe.clap_notes());
+
+        let clap = dialects(
+            CLAP_NOTE_DIALECT_CLAP | CLAP_NOTE_DIALECT_MIDI_MPE,
+            CLAP_NOTE_DIALECT_CLAP,
+        );
+        assert!(clap.clap_notes());
+
+        assert!(!dialects(CLAP_NOTE_DIALECT_MIDI, 0).clap_notes());
+        assert!(NoteDialects::default().clap_notes());
     }
-    new_event
 }
@@ -5,15 +5,17 @@ pub(crate) mod vst2;
 
 pub(crate) mod vst3;
 
+use std::ops::Range;
 use std::path::Path;
 
 use ringbuf::HeapProd;
 
 use crate::discovery::*;
-use crate::error::{err, Error};
-use crate::event::PluginIssuedEvent;
+use crate::error::Error;
+use crate::event::{HostIssuedEvent, HostIssuedEventType, PluginIssuedEvent};
 use crate::host::Host;
 use crate::plugin::PluginInner;
+use crate::ProcessDetails;
 
 pub fn load_any(
     path: &Path,
@@ -32,7 +34,7 @@ pub fn load_any(
         return clap::load(path, id, common);
     }
 
-    err("The requested path was not a supported plugin format.")
+    Err(Error::UnsupportedFormat(path.to_path_buf()))
 }
 
 /// Common data shared between all plugin formats.
@@ -40,3 +42,94 @@ pub struct Common {
     pub host: Host,
     pub plugin_issued_events_producer: HeapProd<PluginIssuedEvent>,
 }
+
+/// Splits a block at `HostIssuedEventType::Transport` events for formats that can't change the
+/// transport mid-block. Each sub-block is processed with the transport state at its start.
+pub(crate) struct SubBlocks {
+    details: ProcessDetails,
+    /// The sample `details` describes the time at.
+    origin: usize,
+    start: usize,
+    done: bool,
+    /// Also split at parameter events, keeping sub-blocks at least this long.
+    parameter_splitting: Option<usize>,
+}
+
+impl SubBlocks {
+    pub fn new(process_details: &ProcessDetails) -> Self {
+        Self {
+            details: process_details.clone(),
+            origin: 0,
+            start: 0,
+            done: false,
+            parameter_splitting: None,
+        }
+    }
+
+    /// Also split at `HostIssuedEventType::Parameter` events. Parameter changes that would leave
+    /// a sub-block shorter than `min_size` don't split the block.
+    pub fn split_at_parameters(mut self, min_size: Option<usize>) -> Self {
+        self.parameter_splitting = min_size;
+        self
+    }
+
+    /// The next sub-block and the details to process it with. `block_size` in the details is
+    /// left as the size of the whole block. Always returns at least one sub-block.
+    pub fn next(&mut self, events: &[HostIssuedEvent]) -> Option<(Range<usize>, &ProcessDetails)> {
+        if self.done {
+            return None;
+        }
+
+        let block_size = self.details.block_size;
+        let start = self.start;
+        let mut end = block_size;
+
+        // Move the time up to the start of this sub-block. A transport event at the start
+        // replaces the position below.
+        let elapsed = (start - self.origin) as f64 / self.details.sample_rate as f64;
+        if self.details.playing_state.is_playing() {
+            self.details.player_time += elapsed * self.details.tempo / 60.0;
+        }
+        self.details.nanos += elapsed * 1_000_000_000.0;
+        self.origin = start;
+
+        for event in events {
+            let time = event.block_time;
+
+            match event.event_type {
+                HostIssuedEventType::Transport(ref transport) => {
+                    if time == start {
+                        self.details.set_transport(transport);
+                    } else if time > start && time < end {
+                        end = time;
+                    }
+                }
+                HostIssuedEventType::Parameter(_) => {
+                    let Some(min_size) = self.parameter_splitting else {
+                        continue;
+                    };
+
+                    if time > start
+                        && time < end
+                        && time >= start + min_size
+                        && time + min_size <= block_size
+                    {
+                        end = time;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.start = end;
+        self.done = end >= block_size;
+
+        Some((start..end, &self.details))
+    }
+}
+
+/// Whether `event` should be sent with the sub-block `range`. Events past the end of the block
+/// go in the last sub-block.
+pub(crate) fn in_sub_block(event: &HostIssuedEvent, range: &Range<usize>, block_size: usize) -> bool {
+    range.contains(&event.block_time) || (range.end >= block_size && event.block_time >= block_size)
+}
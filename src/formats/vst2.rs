@@ -1,33 +1,39 @@
 use std::cell::RefCell;
+use std::ops::Range;
 use std::path::Path;
 use std::sync::Mutex;
 
 use crate::audio_bus::{AudioBus, AudioBusDescriptor, IOConfigutaion};
-use crate::discovery::PluginDescriptor;
-use crate::error::err;
-use crate::event::{HostIssuedEvent, HostIssuedEventType, PluginIssuedEvent};
-use crate::formats::Format;
-use crate::heapless_vec::{HeaplessString, HeaplessVec};
-use crate::host::{Host, KnobPreference, Language};
-use crate::parameter::Parameter;
-use crate::plugin::PluginInner;
+use crate::discovery::{IoSummary, PluginCategory, PluginDescriptor};
+use crate::event::{HostIssuedEvent, HostIssuedEventType, OutputEvent, OutputEvents, PluginIssuedEvent};
+use crate::formats::{in_sub_block, Format, SubBlocks};
+use crate::heapless_vec::HeaplessString;
+use crate::host::{Host, KnobPreference, Language, Limits};
+use crate::parameter::{ParamId, Parameter};
+use crate::plugin::{PluginInner, ProcessStatus};
 use crate::thread_check::{ensure_main_thread, ensure_non_main_thread};
 use crate::utils::macos_exec_location;
 use crate::{error::Error, SampleRate};
 use crate::{BlockSize, PlayingState, ProcessDetails, WindowIDType};
 
-use std::sync::{atomic::AtomicBool, Arc};
+use std::sync::{
+    atomic::{AtomicBool, AtomicPtr, AtomicUsize},
+    Arc,
+};
 
 use ringbuf::traits::Producer;
 use ringbuf::HeapProd;
-use vst::api::{HostLanguage, SpeakerArrangementType};
+use vst::api::{AEffect, HostLanguage, PluginFlags, SpeakerArrangementType, Supported};
 use vst::channels::StereoChannel;
-use vst::host::Dispatch;
-use vst::plugin::{Plugin, PluginParameters};
+use vst::plugin::{CanDo, Category, Info, Plugin, PluginParameters};
 use vst::{
-    api::{Event, TimeInfoFlags},
+    api::TimeInfoFlags,
+    buffer::SendEventBuffer,
+    event::{Event, MidiEvent, SysExEvent},
     editor::{Editor, KnobMode},
-    host::{HostBuffer, PluginInstance},
+    buffer::AudioBuffer,
+    host::{Dispatch, PluginInstance},
+    plugin::OpCode,
 };
 
 use super::Common;
@@ -41,9 +47,40 @@ pub(crate) fn get_descriptor(path: &Path) -> Option<PluginDescriptor> {
 
     let instance = loader.instance().ok()?;
 
+    Some(descriptor_from_instance(&instance, path))
+}
+
+fn descriptor_from_instance(instance: &PluginInstance, path: &Path) -> PluginDescriptor {
     let info = instance.get_info();
+    let is_synth = instance.flags().contains(PluginFlags::IS_SYNTH);
+    let supports = |can_do| matches!(instance.can_do(can_do), Supported::Yes);
+
+    let category = match info.category {
+        Category::Synth => PluginCategory::Instrument,
+        Category::Analysis => PluginCategory::Analyzer,
+        Category::Generator => PluginCategory::Generator,
+        Category::Unknown | Category::Shell if is_synth => PluginCategory::Instrument,
+        Category::Unknown | Category::Shell => PluginCategory::Unknown,
+        _ => PluginCategory::Effect,
+    };
+
+    let mut features = vec![format!("{:?}", info.category)];
+    if is_synth && !matches!(info.category, Category::Synth) {
+        features.push("Synth".to_string());
+    }
+
+    let io = IoSummary {
+        audio_inputs: (info.inputs > 0) as usize,
+        audio_outputs: (info.outputs > 0) as usize,
+        main_input_channels: info.inputs.max(0) as usize,
+        main_output_channels: info.outputs.max(0) as usize,
+        event_inputs: (is_synth
+            || supports(CanDo::ReceiveEvents)
+            || supports(CanDo::ReceiveMidiEvent)) as usize,
+        event_outputs: (supports(CanDo::SendEvents) || supports(CanDo::SendMidiEvent)) as usize,
+    };
 
-    let descriptor = PluginDescriptor {
+    PluginDescriptor {
         name: info.name,
         id: info.unique_id.to_string(),
         path: path.to_path_buf(),
@@ -51,9 +88,12 @@ pub(crate) fn get_descriptor(path: &Path) -> Option<PluginDescriptor> {
         vendor: info.vendor,
         format: Format::Vst2,
         initial_latency: info.initial_delay as usize,
-    };
-
-    Some(descriptor)
+        category,
+        features,
+        has_editor: Some(instance.flags().contains(PluginFlags::HAS_EDITOR)),
+        io: Some(io),
+        ..Default::default()
+    }
 }
 
 pub(super) fn load(
@@ -61,7 +101,7 @@ pub(super) fn load(
     common: Common,
 ) -> Result<(Box<dyn PluginInner>, PluginDescriptor), Error> {
     let Some(path) = macos_exec_location(path) else {
-        return err("Invalid app bundle".to_string());
+        return Err(Error::LibraryLoadFailed("Invalid app bundle".to_string()));
     };
 
     let details = Arc::new(std::sync::Mutex::new(ProcessDetails::default()));
@@ -72,6 +112,7 @@ pub(super) fn load(
     }));
 
     let io_changed = Arc::new(AtomicBool::new(false));
+    let output_target = Arc::new(OutputTarget::default());
 
     let host = Arc::new(Mutex::new(Vst2Host {
         host: common.host.clone(),
@@ -80,52 +121,171 @@ pub(super) fn load(
         size_change: size_change.clone(),
         editor_params_state: editor_param_state.clone(),
         io_changed: io_changed.clone(),
+        output_target: output_target.clone(),
     }));
 
     let mut loader =
-        vst::host::PluginLoader::load(&path, Arc::clone(&host)).map_err(|e| Error {
-            message: e.to_string(),
-        })?;
+        vst::host::PluginLoader::load(&path, Arc::clone(&host)).map_err(load_error)?;
 
-    let mut instance = loader.instance().map_err(|e| Error {
-        message: e.to_string(),
-    })?;
+    let mut instance = loader.instance().map_err(load_error)?;
 
-    let info = instance.get_info();
-
-    let descriptor = PluginDescriptor {
-        name: info.name,
-        id: info.unique_id.to_string(),
-        path: path.to_path_buf(),
-        version: info.version.to_string(),
-        vendor: info.vendor,
-        format: Format::Vst2,
-        initial_latency: info.initial_delay as usize,
-    };
+    let descriptor = descriptor_from_instance(&instance, &path);
 
     instance.init();
 
     let plugin = Vst2 {
-        process_details: details,
         parameter_object: instance.get_parameter_object(),
-        plugin_instance: instance,
-        state: Vst2State::Suspended,
-        host_buffer: HostBuffer::new(info.inputs as usize, info.outputs as usize),
-        editor: None,
+        effect: Effect(instance.get_effect()),
+        info: instance.get_info(),
+        editor: Mutex::new(instance.get_editor()),
+        audio: Mutex::new(Vst2Audio {
+            process_details: details,
+            plugin_instance: instance,
+            state: Vst2State::Suspended,
+            channel_pointers: ChannelPointers::new(&common.host.limits),
+            channel_pointers_f64: ChannelPointers::new(&common.host.limits),
+            send_event_buffer: SendEventBuffer::new(common.host.limits.max_events_per_block),
+            output_target,
+            sent_parameters: Vec::with_capacity(common.host.limits.max_events_per_block),
+        }),
         host: common.host,
     };
 
     Ok((Box::new(plugin), descriptor))
 }
 
+fn load_error(error: vst::host::PluginLoadError) -> Error {
+    match error {
+        vst::host::PluginLoadError::InvalidPath => Error::LibraryLoadFailed(error.to_string()),
+        vst::host::PluginLoadError::NotAPlugin => Error::EntryMissing("VSTPluginMain"),
+        vst::host::PluginLoadError::InstanceFailed
+        | vst::host::PluginLoadError::InvalidApiVersion => Error::InitFailed(error.to_string()),
+    }
+}
+
 pub(super) struct Vst2 {
-    process_details: Arc<std::sync::Mutex<ProcessDetails>>,
     parameter_object: Arc<dyn PluginParameters>,
+    /// For dispatcher calls on the main thread. The `PluginInstance` itself is in `audio`.
+    effect: Effect,
+    /// Read once at load, same as `PluginInstance::get_info`.
+    info: Info,
+    editor: Mutex<Option<Box<dyn Editor>>>,
+    /// VST2 is suspended, resumed and reconfigured from the audio thread so everything that
+    /// needs the `PluginInstance` is in here. Never contended, the lock is only there because
+    /// `PluginInner` takes `&self`.
+    audio: Mutex<Vst2Audio>,
+    host: Host,
+}
+
+/// The parts of `Vst2` only the audio thread uses.
+struct Vst2Audio {
+    process_details: Arc<std::sync::Mutex<ProcessDetails>>,
     plugin_instance: PluginInstance,
     state: Vst2State,
-    host_buffer: HostBuffer<f32>,
-    host: Host,
-    editor: Option<Box<dyn Editor>>,
+    channel_pointers: ChannelPointers<f32>,
+    channel_pointers_f64: ChannelPointers<f64>,
+    send_event_buffer: SendEventBuffer,
+    output_target: Arc<OutputTarget>,
+    /// Parameters already sent in the current sub-block. Sized from
+    /// `Limits::max_events_per_block` at load, one block can't change more parameters than that.
+    sent_parameters: Vec<ParamId>,
+}
+
+/// Calls the plugin's dispatcher without the `&mut PluginInstance` that `vst` wants.
+struct Effect(*mut AEffect);
+
+impl Dispatch for Effect {
+    fn get_effect(&self) -> *mut AEffect {
+        self.0
+    }
+}
+
+impl Effect {
+    fn initial_delay(&self) -> i32 {
+        unsafe { (*self.0).initialDelay }
+    }
+}
+
+impl Vst2Audio {
+    /// Sends parameter changes and MIDI to the plugin before processing.
+    /// Sends the events in the sub-block `range`. `process_details` is stored for
+    /// `get_time_info`.
+    fn send_events(
+        &mut self,
+        events: &[HostIssuedEvent],
+        range: &Range<usize>,
+        process_details: &ProcessDetails,
+    ) {
+        {
+            let mut details_lock = self.process_details.lock().unwrap();
+            *details_lock = process_details.clone();
+        }
+
+        let block_size = process_details.block_size;
+        self.output_target
+            .offset
+            .store(range.start, std::sync::atomic::Ordering::Relaxed);
+
+        // Events are sorted by time.
+        // Since sample-accurate parameters are not supported we want to only take the last value
+        // for each parameter in the sub-block. With `Host::vst2_parameter_splitting` the block is
+        // split at each change so this is usually the only value.
+        // Loop from last to first skipping anything we've already seen.
+        self.sent_parameters.clear();
+        for event in events.iter().rev() {
+            if !in_sub_block(event, range, block_size) {
+                continue;
+            }
+
+            if let HostIssuedEventType::Parameter(ref param) = event.event_type {
+                if self.sent_parameters.contains(&param.parameter_id) {
+                    continue;
+                }
+
+                self.plugin_instance
+                    .get_parameter_object()
+                    .set_parameter(param.parameter_id.0 as i32, param.current_value);
+
+                self.sent_parameters.push(param.parameter_id);
+            }
+        }
+
+        self.send_midi_events(events, range, block_size);
+    }
+
+    /// Events must already be sorted by time. `AudioHandle` limits the number of events to
+    /// `Limits::max_events_per_block` which is the capacity of `send_event_buffer`.
+    fn send_midi_events(&mut self, events: &[HostIssuedEvent], range: &Range<usize>, block_size: usize) {
+        self.send_event_buffer.store_events(
+            events
+                .iter()
+                .filter(|event| in_sub_block(event, range, block_size))
+                .filter_map(|event| host_event_to_vst2_event(event, range.start)),
+        );
+
+        self.plugin_instance
+            .process_events(self.send_event_buffer.events());
+    }
+
+    fn suspend(&mut self) {
+        if self.state == Vst2State::Suspended {
+            return;
+        }
+
+        self.plugin_instance.stop_process();
+        self.plugin_instance.suspend();
+        self.state = Vst2State::Suspended;
+    }
+
+    fn resume(&mut self) {
+        if self.state == Vst2State::Resumed {
+            return;
+        }
+
+        self.plugin_instance.resume();
+        self.plugin_instance.start_process();
+        self.state = Vst2State::Resumed;
+    }
 }
 
 unsafe impl Send for Vst2 {}
@@ -149,52 +309,78 @@ enum Vst2State {
     Resumed,
 }
 
-impl PluginInner for Vst2 {
-    fn change_sample_rate(&mut self, rate: SampleRate) {
-        self.suspend();
-        self.plugin_instance.set_sample_rate(rate as f32);
+impl Vst2 {
+    /// `effGetParameterProperties`, `None` if the plugin doesn't support it.
+    fn parameter_properties(&self, id: i32) -> Option<ParameterProperties> {
+        let mut properties: ParameterProperties = unsafe { std::mem::zeroed() };
+        let has_properties = self.effect.dispatch(
+            OpCode::GetParamInfo,
+            id,
+            0,
+            &mut properties as *mut ParameterProperties as *mut std::ffi::c_void,
+            0.,
+        ) != 0;
+
+        has_properties.then_some(properties)
     }
 
-    fn change_block_size(&mut self, size: BlockSize) {
-        self.suspend();
-        self.plugin_instance.set_block_size(size as i64);
+    /// The current value's text and unit.
+    fn formatted_value(&self, id: i32) -> String {
+        let text = self.parameter_object.get_parameter_text(id);
+        let label = self.parameter_object.get_parameter_label(id);
+
+        format!("{} {}", text.trim(), label.trim())
+            .trim()
+            .to_string()
     }
+}
 
-    fn suspend(&mut self) {
-        if self.state == Vst2State::Suspended {
-            return;
+impl PluginInner for Vst2 {
+    // VST2 plugins can't refuse these.
+
+    fn change_sample_rate(&self, rate: SampleRate) -> Result<(), Error> {
+        if let Ok(mut audio) = self.audio.try_lock() {
+            audio.suspend();
+            audio.plugin_instance.set_sample_rate(rate as f32);
         }
 
-        self.plugin_instance.stop_process();
-        self.plugin_instance.suspend();
-        self.state = Vst2State::Suspended;
+        Ok(())
     }
 
-    fn resume(&mut self) {
-        if self.state == Vst2State::Resumed {
-            return;
+    fn change_block_size(&self, size: BlockSize) -> Result<(), Error> {
+        if let Ok(mut audio) = self.audio.try_lock() {
+            audio.suspend();
+            audio.plugin_instance.set_block_size(size as i64);
         }
 
-        self.plugin_instance.resume();
-        self.plugin_instance.start_process();
-        self.state = Vst2State::Resumed;
+        Ok(())
+    }
+
+    fn suspend(&self) {
+        if let Ok(mut audio) = self.audio.try_lock() {
+            audio.suspend();
+        }
+    }
+
+    fn resume(&self) {
+        if let Ok(mut audio) = self.audio.try_lock() {
+            audio.resume();
+        }
     }
 
     fn show_editor(
-        &mut self,
+        &self,
         window_id: *mut std::ffi::c_void,
         _window_id_type: WindowIDType,
     ) -> Result<(usize, usize), Error> {
         ensure_main_thread("[VST2] show_editor");
 
-        if self.editor.is_none() {
-            let Some(editor) = self.plugin_instance.get_editor() else {
-                return err("Plugin does not have an editor".to_string());
-            };
-            self.editor = Some(editor);
-        }
-
-        let editor = self.editor.as_mut().unwrap();
+        let mut editor = self.editor.lock().unwrap();
+        let Some(editor) = editor.as_mut() else {
+            return Err(Error::EditorUnavailable(
+                "Plugin does not have an editor".to_string(),
+            ));
+        };
 
         let (w, h) = editor.size();
 
@@ -207,13 +393,15 @@ impl PluginInner for Vst2 {
             });
         }
 
-        self.plugin_instance.resume();
+        // Same as `PluginInstance::resume`.
+        self.effect
+            .dispatch(OpCode::StateChanged, 0, 1, std::ptr::null_mut(), 0.);
 
         Ok((w as usize, h as usize))
     }
 
-    fn hide_editor(&mut self) {
-        if let Some(editor) = &mut self.editor {
+    fn hide_editor(&self) {
+        if let Some(editor) = self.editor.lock().unwrap().as_mut() {
             editor.close();
         }
     }
@@ -238,129 +426,245 @@ impl PluginInner for Vst2 {
     // }
 
     fn process(
-        &mut self,
+        &self,
         inputs: &[AudioBus<f32>],
         outputs: &mut [AudioBus<f32>],
-        events: Vec<HostIssuedEvent>,
+        events: &[HostIssuedEvent],
+        output_events: &mut OutputEvents,
         process_details: &ProcessDetails,
-    ) {
+    ) -> ProcessStatus {
         ensure_non_main_thread("[VST2] process");
 
-        let _ = outputs;
-        {
-            let mut details_lock = self.process_details.lock().unwrap();
-            *details_lock = process_details.clone();
+        let Ok(mut audio) = self.audio.try_lock() else {
+            return ProcessStatus::Error;
+        };
+        let audio = &mut *audio;
+
+        audio.output_target.set(output_events);
+
+        // VST2 has no mid-block transport changes or sample-accurate parameters so split the
+        // block at `Transport` events, and parameter events if enabled.
+        let mut sub_blocks = SubBlocks::new(process_details)
+            .split_at_parameters(self.host.vst2_parameter_splitting);
+        while let Some((range, details)) = sub_blocks.next(events) {
+            audio.send_events(events, &range, details);
+
+            let pointers = &mut audio.channel_pointers;
+            pointers.update(inputs, outputs, range.start);
+
+            let mut audio_buffer = unsafe {
+                AudioBuffer::from_raw(
+                    pointers.inputs.len(),
+                    pointers.outputs.len(),
+                    pointers.inputs.as_ptr(),
+                    pointers.outputs.as_mut_ptr(),
+                    range.len(),
+                )
+            };
+
+            audio.plugin_instance.process(&mut audio_buffer);
         }
 
-        // Events are sorted by time.
-        // Since sample-accurate parameters are not supported we want to only take the last value
-        // for each parameter. Loop from last to first skipping anything we've already seen.
-        let mut seen = HeaplessVec::<i32, 300>::new();
-        for i in 0..events.len() {
-            if let HostIssuedEventType::Parameter(ref param) =
-                unsafe { events.get_unchecked(events.len() - 1 - i) }.event_type
-            {
-                if seen.contains(param.parameter_id) {
-                    continue;
-                }
+        audio.output_target.clear();
 
-                self.plugin_instance
-                    .get_parameter_object()
-                    .set_parameter(param.parameter_id, param.current_value);
+        // VST2 has no way to report errors or sleeping.
+        ProcessStatus::Ok
+    }
 
-                let _ = seen.push(param.parameter_id);
-            }
-        }
+    fn process_f64(
+        &self,
+        inputs: &[AudioBus<f64>],
+        outputs: &mut [AudioBus<f64>],
+        events: &[HostIssuedEvent],
+        output_events: &mut OutputEvents,
+        process_details: &ProcessDetails,
+    ) -> ProcessStatus {
+        ensure_non_main_thread("[VST2] process_f64");
+
+        let Ok(mut audio) = self.audio.try_lock() else {
+            return ProcessStatus::Error;
+        };
+        let audio = &mut *audio;
+
+        audio.output_target.set(output_events);
+
+        // VST2 has no mid-block transport changes or sample-accurate parameters so split the
+        // block at `Transport` events, and parameter events if enabled.
+        let mut sub_blocks = SubBlocks::new(process_details)
+            .split_at_parameters(self.host.vst2_parameter_splitting);
+        while let Some((range, details)) = sub_blocks.next(events) {
+            audio.send_events(events, &range, details);
+
+            let pointers = &mut audio.channel_pointers_f64;
+            pointers.update(inputs, outputs, range.start);
+
+            let mut audio_buffer = unsafe {
+                AudioBuffer::from_raw(
+                    pointers.inputs.len(),
+                    pointers.outputs.len(),
+                    pointers.inputs.as_ptr(),
+                    pointers.outputs.as_mut_ptr(),
+                    range.len(),
+                )
+            };
 
-        process_vst2_midi_events_list(&self.plugin_instance, events);
+            audio.plugin_instance.process_f64(&mut audio_buffer);
+        }
 
-        let mut input = vec![];
-        let mut output = vec![];
+        audio.output_target.clear();
 
-        for input_bus in inputs.iter() {
-            for channel in input_bus.data.iter() {
-                input.push(channel);
-            }
-        }
-        for output_bus in outputs.iter_mut() {
-            for channel in output_bus.data.iter_mut() {
-                output.push(channel);
-            }
-        }
+        ProcessStatus::Ok
+    }
 
-        let mut audio_buffer = self.host_buffer.bind(&input, &mut output);
+    fn supports_f64(&self) -> bool {
+        self.info.f64_precision
+    }
 
-        self.plugin_instance.process(&mut audio_buffer);
+    // VST2 has no per-note expression.
+    fn takes_note_expressions(&self, _bus_index: usize) -> bool {
+        false
     }
 
-    fn set_preset_data(&mut self, data: Vec<u8>) -> Result<(), String> {
-        if !self.plugin_instance.get_info().preset_chunks {
-            return Err("Plugin does not support preset data".to_string());
+    fn set_preset_data(&self, data: Vec<u8>) -> Result<(), Error> {
+        if !self.info.preset_chunks {
+            return Err(Error::ExtensionMissing("preset chunks"));
         }
 
-        self.plugin_instance
-            .get_parameter_object()
-            .load_preset_data(data.as_slice());
+        self.parameter_object.load_preset_data(data.as_slice());
         Ok(())
     }
 
-    fn get_preset_data(&mut self) -> Result<Vec<u8>, String> {
-        if !self.plugin_instance.get_info().preset_chunks {
-            return Err("Plugin does not support preset data".to_string());
+    fn get_preset_data(&self) -> Result<Vec<u8>, Error> {
+        if !self.info.preset_chunks {
+            return Err(Error::ExtensionMissing("preset chunks"));
         }
 
-        Ok(self
-            .plugin_instance
-            .get_parameter_object()
-            .get_preset_data())
+        Ok(self.parameter_object.get_preset_data())
     }
 
-    fn get_preset_name(&mut self, id: i32) -> Result<String, String> {
-        Ok(self
-            .plugin_instance
-            .get_parameter_object()
-            .get_preset_name(id))
+    fn get_preset_name(&self, id: i32) -> Result<String, Error> {
+        Ok(self.parameter_object.get_preset_name(id))
     }
 
-    fn set_preset(&mut self, id: i32) -> Result<(), String> {
-        self.plugin_instance
-            .get_parameter_object()
-            .change_preset(id);
+    fn set_preset(&self, id: i32) -> Result<(), Error> {
+        self.parameter_object.change_preset(id);
         Ok(())
     }
 
-    fn get_parameter(&self, id: i32) -> crate::parameter::Parameter {
+    fn get_parameter(&self, index: usize) -> crate::parameter::Parameter {
+        let id = index as i32;
         let value = self.parameter_object.get_parameter(id);
         let name = self.parameter_object.get_parameter_name(id);
 
-        let text = self.parameter_object.get_parameter_text(id);
         let label = self.parameter_object.get_parameter_label(id);
-        let formatted_value = format!("{} {}", text.trim(), label.trim())
-            .trim()
-            .to_string();
+        let formatted_value = self.formatted_value(id);
         let can_automate = self.parameter_object.can_be_automated(id);
 
+        let properties = self.parameter_properties(id);
+        let flag = |flag: i32| properties.as_ref().is_some_and(|p| p.flags & flag != 0);
+
+        let integer_range = properties.as_ref().and_then(ParameterProperties::integer_range);
+        let (min_value, max_value, step_count) = if let Some((min, max)) = integer_range {
+            (min as f64, max as f64, (max - min).max(0) as u32)
+        } else if flag(PARAMETER_IS_SWITCH) {
+            (0., 1., 1)
+        } else {
+            (0., 1., 0)
+        };
+
+        let group = match &properties {
+            Some(properties) if flag(PARAMETER_SUPPORTS_DISPLAY_CATEGORY) => {
+                c_chars_to_string(&properties.category_label)
+            }
+            _ => String::new(),
+        };
+
         Parameter {
-            id,
-            name: HeaplessString::from_str(&name).unwrap(),
+            id: ParamId(id as u32),
+            name: HeaplessString::from_str(&name).unwrap_or_default(),
             index: id,
             value,
-            formatted_value: HeaplessString::from_str(&formatted_value).unwrap(),
+            formatted_value: HeaplessString::from_str(&formatted_value).unwrap_or_default(),
             hidden: false,
             can_automate,
             is_wrap_around: false,
             read_only: false,
             default_value: f32::NAN,
+            min_value,
+            max_value,
+            step_count,
+            unit: label.trim().to_string(),
+            value_names: Vec::new(),
+            group,
+            is_bypass: false,
         }
     }
 
-    fn get_io_configuration(&mut self) -> IOConfigutaion {
-        let info = self.plugin_instance.get_info();
+    fn get_parameter_id(&self, index: usize) -> ParamId {
+        ParamId(index as u32)
+    }
+
+    fn get_parameter_value(&self, id: ParamId) -> Result<f32, Error> {
+        Ok(self.parameter_object.get_parameter(id.0 as i32))
+    }
+
+    // VST2 can only format the current value and `effString2Parameter` sets the parameter, so
+    // anything else is formatted and parsed by the host from the plugin's integer range and
+    // unit. Without one there's nothing to go on.
+
+    fn format_parameter_value(&self, id: ParamId, value: f32) -> Result<String, Error> {
+        if value == self.parameter_object.get_parameter(id.0 as i32) {
+            return Ok(self.formatted_value(id.0 as i32));
+        }
+
+        let parameter = self.get_parameter(id.0 as usize);
+
+        let properties = self.parameter_properties(id.0 as i32);
+        if properties.as_ref().and_then(ParameterProperties::integer_range).is_none() {
+            return Err(Error::ParameterConversionFailed(value.to_string()));
+        }
+
+        let plain = parameter.to_plain(parameter.snap(value));
+        let text = if parameter.step_count > 0 {
+            format!("{}", plain.round())
+        } else {
+            format!("{:.2}", plain)
+        };
+
+        Ok(format!("{} {}", text, parameter.unit).trim().to_string())
+    }
+
+    fn parse_parameter_text(&self, id: ParamId, text: &str) -> Result<f32, Error> {
+        let parameter = self.get_parameter(id.0 as usize);
+        let text = text.trim();
+        if text == parameter.formatted_value.as_str() {
+            return Ok(parameter.value);
+        }
+
+        let properties = self.parameter_properties(id.0 as i32);
+        if properties.as_ref().and_then(ParameterProperties::integer_range).is_none() {
+            return Err(Error::ParameterConversionFailed(text.to_string()));
+        }
+
+        let number = if parameter.unit.is_empty() {
+            text
+        } else {
+            text.strip_suffix(parameter.unit.as_str()).unwrap_or(text).trim_end()
+        };
+
+        number
+            .parse::<f64>()
+            .map(|plain| parameter.snap(parameter.to_normalized(plain)))
+            .map_err(|_| Error::ParameterConversionFailed(text.to_string()))
+    }
+
+    fn get_io_configuration(&self) -> IOConfigutaion {
+        let info = &self.info;
 
         let event_inputs_count = info.midi_inputs.min(1); // TODO: Look into supporting more channels
 
-        let mut inputs = HeaplessVec::new();
-        let mut outputs = HeaplessVec::new();
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
 
         // TODO: Check these. Lots of plugins don't specify this correctly.
         // for i in 0..info.inputs {
@@ -385,13 +689,13 @@ impl PluginInner for Vst2 {
                 0 => {}
                 1 | 2 => {
                     // Mono or stereo
-                    let _ = inputs.push(AudioBusDescriptor {
+                    inputs.push(AudioBusDescriptor {
                         channels: info.inputs as usize,
                     });
                 }
                 _ => {
-                    let _ = inputs.push(AudioBusDescriptor { channels: 2 });
-                    let _ = inputs.push(AudioBusDescriptor {
+                    inputs.push(AudioBusDescriptor { channels: 2 });
+                    inputs.push(AudioBusDescriptor {
                         channels: info.inputs as usize - 2,
                     });
                 }
@@ -400,22 +704,20 @@ impl PluginInner for Vst2 {
                 0 => {}
                 1 | 2 => {
                     // Mono or stereo
-                    let _ = outputs.push(AudioBusDescriptor {
+                    outputs.push(AudioBusDescriptor {
                         channels: info.outputs as usize,
                     });
                 }
                 _ => {
                     // Stereo with sidechain
-                    let _ = outputs.push(AudioBusDescriptor { channels: 2 });
-                    let _ = outputs.push(AudioBusDescriptor {
+                    outputs.push(AudioBusDescriptor { channels: 2 });
+                    outputs.push(AudioBusDescriptor {
                         channels: info.outputs as usize - 2,
                     });
                 }
             }
         }
 
-        self.plugin_instance.info = info;
-
         IOConfigutaion {
             audio_inputs: inputs,
             audio_outputs: outputs,
@@ -423,32 +725,32 @@ impl PluginInner for Vst2 {
         }
     }
 
-    fn get_latency(&mut self) -> usize {
-        self.plugin_instance.initial_delay() as usize
+    fn get_latency(&self) -> usize {
+        self.effect.initial_delay() as usize
     }
 
     fn get_parameter_count(&self) -> usize {
-        self.plugin_instance.info.parameters as usize
+        self.info.parameters as usize
     }
 }
 
-fn total_channels<const N: usize>(buses: &HeaplessVec<AudioBusDescriptor, N>) -> usize {
+fn total_channels(buses: &[AudioBusDescriptor]) -> usize {
     buses.iter().map(|b| b.channels).sum()
 }
 
 fn append_bus_info_from_arrangment(
-    buses: &mut HeaplessVec<AudioBusDescriptor, 16>,
+    buses: &mut Vec<AudioBusDescriptor>,
     arrangement: vst::channels::SpeakerArrangementType,
 ) {
     match arrangement {
         vst::channels::SpeakerArrangementType::Empty => {}
         vst::channels::SpeakerArrangementType::Mono => {
-            let _ = buses.push(AudioBusDescriptor { channels: 1 });
+            buses.push(AudioBusDescriptor { channels: 1 });
         }
         vst::channels::SpeakerArrangementType::Stereo(_, channel) => {
             // Assume left will also be present and ignore it
             if channel == StereoChannel::Right {
-                let _ = buses.push(AudioBusDescriptor { channels: 2 });
+                buses.push(AudioBusDescriptor { channels: 2 });
             }
         }
         vst::channels::SpeakerArrangementType::Surround(config) => {
@@ -468,9 +770,14 @@ struct Vst2Host {
     size_change: Arc<std::sync::Mutex<Option<(i32, i32)>>>,
     editor_params_state: Arc<std::sync::Mutex<EditorParamsState>>,
     io_changed: Arc<AtomicBool>,
+    output_target: Arc<OutputTarget>,
 }
 
 impl vst::host::Host for Vst2Host {
+    fn process_events(&self, events: &vst::api::Events) {
+        self.output_target.push(events);
+    }
+
     fn automate(&self, index: i32, value: f32) {
         let mut initial_value = None;
 
@@ -502,7 +809,7 @@ impl vst::host::Host for Vst2Host {
                 .borrow_mut()
                 .try_push(PluginIssuedEvent::Parameter(
                     crate::parameter::ParameterUpdate {
-                        parameter_id: index,
+                        parameter_id: ParamId(index as u32),
                         parameter_index: index,
                         current_value: value,
                         initial_value: initial_value.unwrap_or(f32::NAN),
@@ -581,7 +888,7 @@ impl vst::host::Host for Vst2Host {
 
             let _ = self.plugin_issued_events_producer.borrow_mut().try_push(
                 PluginIssuedEvent::Parameter(crate::parameter::ParameterUpdate {
-                    parameter_id: index as i32,
+                    parameter_id: ParamId(index as u32),
                     parameter_index: index,
                     current_value: param.current,
                     initial_value: param.initial,
@@ -595,7 +902,11 @@ impl vst::host::Host for Vst2Host {
         let _ = self
             .plugin_issued_events_producer
             .borrow_mut()
-            .try_push(PluginIssuedEvent::UpdateDisplay);
+            .try_push(PluginIssuedEvent::ParametersChanged {
+                values: true,
+                info: true,
+                list: false,
+            });
     }
 
     fn can_do(&self, can_do: &str) -> isize {
@@ -644,86 +955,160 @@ impl vst::host::Host for Vst2Host {
     }
 }
 
-fn process_vst2_midi_events_list(
-    plugin_instance: &PluginInstance,
-    mut midi_events: Vec<HostIssuedEvent>,
-) {
-    midi_events.sort_by(|a, b| a.block_time.cmp(&b.block_time));
-
-    let events: Vec<*mut Event> = midi_events
-        .into_iter()
-        .filter_map(|event| midi_event_to_vst2_event(&event))
-        .collect();
-
-    let num_events = events.len();
+/// `offset` is the start of the sub-block the event is sent with.
+fn host_event_to_vst2_event(host_event: &HostIssuedEvent, offset: usize) -> Option<Event<'static>> {
+    let delta_frames = host_event.block_time.saturating_sub(offset) as i32;
+
+    match host_event.event_type {
+        HostIssuedEventType::Midi(ref event) => Some(Event::Midi(MidiEvent {
+            data: event.midi_data,
+            delta_frames,
+            live: host_event.is_live,
+            note_length: Some(event.note_length as i32),
+            note_offset: None,
+            detune: event.detune as i8,
+            note_off_velocity: 0,
+        })),
+        // The payload outlives the `process` call, which is as long as the plugin may keep it.
+        HostIssuedEventType::SysEx(ref sysex) => Some(Event::SysEx(SysExEvent {
+            payload: unsafe { &*(sysex.as_slice() as *const [u8]) },
+            delta_frames,
+        })),
+        HostIssuedEventType::Midi2(ref ump) => ump.to_midi1().map(|data| {
+            Event::Midi(MidiEvent {
+                data,
+                delta_frames,
+                live: host_event.is_live,
+                note_length: None,
+                note_offset: None,
+                detune: 0,
+                note_off_velocity: 0,
+            })
+        }),
+        _ => None,
+    }
+}
 
-    const MAX_EVENTS: usize = 100;
+const PARAMETER_IS_SWITCH: i32 = 1 << 0;
+const PARAMETER_USES_INTEGER_MIN_MAX: i32 = 1 << 1;
+const PARAMETER_SUPPORTS_DISPLAY_CATEGORY: i32 = 1 << 5;
 
-    let mut events_object = Vst2Events::<MAX_EVENTS>::new(num_events);
+/// `VstParameterProperties` from the VST 2.4 SDK, filled by `effGetParameterProperties`.
+#[repr(C)]
+struct ParameterProperties {
+    step_float: f32,
+    small_step_float: f32,
+    large_step_float: f32,
+    label: [std::ffi::c_char; 64],
+    flags: i32,
+    min_integer: i32,
+    max_integer: i32,
+    step_integer: i32,
+    large_step_integer: i32,
+    short_label: [std::ffi::c_char; 8],
+    display_index: i16,
+    category: i16,
+    num_parameters_in_category: i16,
+    reserved: i16,
+    category_label: [std::ffi::c_char; 24],
+    future: [std::ffi::c_char; 16],
+}
 
-    if num_events > MAX_EVENTS {
-        // TODO: Handle this case
-        return;
+impl ParameterProperties {
+    /// The plain range of the parameter, the only one VST2 has.
+    fn integer_range(&self) -> Option<(i32, i32)> {
+        (self.flags & PARAMETER_USES_INTEGER_MIN_MAX != 0)
+            .then_some((self.min_integer, self.max_integer))
     }
+}
 
-    for (i, event) in events.iter().enumerate() {
-        events_object.events[i] = *event;
-    }
+/// Reads a fixed size string that may not be null terminated.
+fn c_chars_to_string(chars: &[std::ffi::c_char]) -> String {
+    let bytes: Vec<u8> = chars
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8)
+        .collect();
 
-    plugin_instance.dispatch(
-        vst::plugin::OpCode::ProcessEvents,
-        0,
-        0,
-        &events_object as *const _ as *mut _,
-        0.0,
-    );
+    String::from_utf8_lossy(&bytes).into_owned()
+}
 
-    for event in events {
-        drop(unsafe { Box::from_raw(event) });
-    }
+/// Where `Vst2Host::process_events` puts events the plugin sends while processing. Plugins call
+/// back into the host on the audio thread from inside `process`.
+#[derive(Default)]
+struct OutputTarget {
+    events: AtomicPtr<OutputEvents>,
+    /// Start of the sub-block being processed.
+    offset: AtomicUsize,
 }
 
-fn midi_event_to_vst2_event(midi_event: &HostIssuedEvent) -> Option<*mut Event> {
-    let frame = midi_event.block_time;
+impl OutputTarget {
+    fn set(&self, output_events: &mut OutputEvents) {
+        self.events
+            .store(output_events, std::sync::atomic::Ordering::Relaxed);
+    }
 
-    let HostIssuedEventType::Midi(ref event) = midi_event.event_type else {
-        return None;
-    };
+    fn clear(&self) {
+        self.events
+            .store(std::ptr::null_mut(), std::sync::atomic::Ordering::Relaxed);
+    }
 
-    let event = vst::api::MidiEvent {
-        event_type: vst::api::EventType::Midi,
-        byte_size: 0,
-        delta_frames: frame as i32,
-        flags: 0,
-        note_length: event.note_length as i32,
-        note_offset: 0,
-        midi_data: event.midi_data,
-        detune: event.detune as i8,
-        note_off_velocity: 0,
-        _reserved1: 0,
-        _reserved2: 0,
-        _midi_reserved: 0,
-    };
+    fn push(&self, events: &vst::api::Events) {
+        let output_events = self.events.load(std::sync::atomic::Ordering::Relaxed);
+        if output_events.is_null() {
+            return;
+        }
 
-    let event: Event = unsafe { std::mem::transmute(event) };
-    let event = Box::into_raw(Box::new(event));
+        let output_events = unsafe { &mut *output_events };
+        let offset = self.offset.load(std::sync::atomic::Ordering::Relaxed);
 
-    Some(event)
+        for event in events.events() {
+            match event {
+                Event::Midi(midi) => output_events.push(OutputEvent::Midi {
+                    block_time: offset + midi.delta_frames.max(0) as usize,
+                    bus_index: 0,
+                    data: midi.data,
+                }),
+                Event::SysEx(sysex) => output_events.push(OutputEvent::SysEx {
+                    block_time: offset + sysex.delta_frames.max(0) as usize,
+                    bus_index: 0,
+                    data: sysex.payload,
+                }),
+                _ => {}
+            }
+        }
+    }
 }
 
-#[repr(C)]
-pub struct Vst2Events<const L: usize> {
-    pub num_events: i32,
-    pub _reserved: isize,
-    pub events: [*mut Event; L],
+/// Channel pointers for `AudioBuffer`. Allocated at load so binding doesn't allocate.
+struct ChannelPointers<T> {
+    inputs: Vec<*const T>,
+    outputs: Vec<*mut T>,
 }
 
-impl<const L: usize> Vst2Events<L> {
-    pub fn new(len: usize) -> Self {
+impl<T> ChannelPointers<T> {
+    fn new(limits: &Limits) -> Self {
         Self {
-            num_events: len as i32,
-            _reserved: 0,
-            events: [std::ptr::null_mut(); L],
+            inputs: Vec::with_capacity(limits.max_channels),
+            outputs: Vec::with_capacity(limits.max_channels),
+        }
+    }
+
+    /// Points at the channels of `inputs` and `outputs` starting `offset` samples in. Extra
+    /// channels are ignored.
+    fn update(&mut self, inputs: &[AudioBus<T>], outputs: &mut [AudioBus<T>], offset: usize) {
+        self.inputs.clear();
+        self.outputs.clear();
+
+        for channel in inputs.iter().flat_map(|bus| bus.data.iter()) {
+            if self.inputs.len() < self.inputs.capacity() {
+                self.inputs.push(unsafe { channel.as_ptr().add(offset) });
+            }
+        }
+        for channel in outputs.iter_mut().flat_map(|bus| bus.data.iter_mut()) {
+            if self.outputs.len() < self.outputs.capacity() {
+                self.outputs.push(unsafe { channel.as_mut_ptr().add(offset) });
+            }
         }
     }
 }
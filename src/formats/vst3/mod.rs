@@ -1,31 +1,54 @@
-use std::collections::HashMap;
-use std::ffi::c_void;
+use std::ffi::{c_void, CString};
 use std::path::Path;
+use std::sync::Mutex;
 
-use ringbuf::traits::{Consumer, Producer};
-use ringbuf::{HeapProd, HeapRb};
-use vst3_wrapper_sys::{descriptor, get_parameter, set_param_in_edit_controller};
+use ringbuf::traits::{Consumer, Producer, Split};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+use vst3_wrapper_sys::{descriptor, get_parameter, set_param_in_edit_controller, Dims, LoadStatus};
 
-use crate::audio_bus::AudioBus;
+use crate::audio_bus::{AudioBus, IOConfigutaion};
 use crate::discovery::PluginDescriptor;
 use crate::error::Error;
 use crate::event::HostIssuedEventType;
-use crate::event::{HostIssuedEvent, PluginIssuedEvent};
+use crate::event::{HostIssuedEvent, OutputEvents, PluginIssuedEvent};
 use crate::formats::vst3::vst3_wrapper_sys::FFIPluginDescriptor;
 use crate::heapless_vec::HeaplessVec;
-use crate::parameter::ParameterUpdate;
-use crate::plugin::PluginInner;
-use crate::{ProcessDetails, Samples, WindowIDType};
+use crate::parameter::{ParamId, ParameterUpdate};
+use crate::plugin::{PluginInner, ProcessStatus, SamplePrecision};
+use crate::{ProcessDetails, WindowIDType};
 
-use super::Common;
+use super::{in_sub_block, Common, SubBlocks};
 
+#[cfg(feature = "vst3_moduleinfo")]
+mod moduleinfo;
 mod vst3_wrapper_sys;
 
 struct Vst3 {
     app: *const c_void,
-    _plugin_issued_events_producer: Box<HeapProd<PluginIssuedEvent>>,
-    param_updates_for_edit_controller: HeapRb<ParameterUpdate>,
-    param_updates_for_audio_processor: HeapRb<ParameterUpdate>,
+    /// Used by `send_event_to_host`, which the C++ side calls on the main thread.
+    plugin_issued_events_producer: Mutex<HeapProd<PluginIssuedEvent>>,
+    param_updates_for_audio_processor: Mutex<HeapProd<ParameterUpdate>>,
+    /// Parameter changes from `process` that still need to be sent to the `IEditController`.
+    param_updates_for_edit_controller: Mutex<HeapCons<ParameterUpdate>>,
+    /// Only used by `process` and `process_f64`. Never contended, the lock is only there because
+    /// `PluginInner` takes `&self`.
+    audio: Mutex<Vst3Audio>,
+}
+
+/// The parts of `Vst3` only the audio thread uses.
+struct Vst3Audio {
+    param_updates_for_edit_controller: HeapProd<ParameterUpdate>,
+    param_updates_for_audio_processor: HeapCons<ParameterUpdate>,
+    /// Events for the current block including parameter changes from the editor. Allocated up
+    /// front so `process` doesn't allocate.
+    events: Vec<HostIssuedEvent>,
+    /// The part of `events` for the sub-block being processed. Same capacity as `events`.
+    sub_block_events: Vec<HostIssuedEvent>,
+    /// Channel pointers for every bus, inputs then outputs. Sized from `Limits` in `load`.
+    channel_pointers: Vec<*mut c_void>,
+    /// Per-bus pointers into `channel_pointers`.
+    input_bus_pointers: Vec<*mut *mut c_void>,
+    output_bus_pointers: Vec<*mut *mut c_void>,
 }
 
 pub fn load(
@@ -33,17 +56,31 @@ pub fn load(
     id: &str,
     common: Common,
 ) -> Result<(Box<dyn PluginInner>, PluginDescriptor), Error> {
-    let plugin_issued_events_producer = Box::new(common.plugin_issued_events_producer);
+    let limits = common.host.limits;
+
+    let (to_edit_controller, from_audio_processor) = HeapRb::new(512).split();
+    let (to_audio_processor, from_edit_controller) = HeapRb::new(512).split();
 
     let instance = Vst3 {
         app: std::ptr::null(),
-        _plugin_issued_events_producer: plugin_issued_events_producer,
-        param_updates_for_edit_controller: HeapRb::new(512),
-        param_updates_for_audio_processor: HeapRb::new(512),
+        plugin_issued_events_producer: Mutex::new(common.plugin_issued_events_producer),
+        param_updates_for_audio_processor: Mutex::new(to_audio_processor),
+        param_updates_for_edit_controller: Mutex::new(from_audio_processor),
+        audio: Mutex::new(Vst3Audio {
+            param_updates_for_edit_controller: to_edit_controller,
+            param_updates_for_audio_processor: from_edit_controller,
+            events: Vec::with_capacity(limits.max_events_per_block + 512),
+            sub_block_events: Vec::with_capacity(limits.max_events_per_block + 512),
+            channel_pointers: Vec::with_capacity(limits.max_channels * 2),
+            input_bus_pointers: Vec::with_capacity(limits.max_buses),
+            output_bus_pointers: Vec::with_capacity(limits.max_buses),
+        }),
     };
 
     let mut instance = Box::new(instance);
 
+    let mut status = LoadStatus::Ok;
+
     let app = unsafe {
         let plugin_path = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
         let plugin_id = std::ffi::CString::new(id).unwrap();
@@ -51,95 +88,219 @@ pub fn load(
             plugin_path.as_ptr(),
             plugin_id.as_ptr(),
             &*instance as *const _ as *const c_void,
+            &mut status,
         )
     };
 
+    match status {
+        LoadStatus::Ok => {}
+        LoadStatus::ModuleLoadFailed => {
+            return Err(Error::LibraryLoadFailed(
+                "Failed to load VST3 module".to_string(),
+            ))
+        }
+        LoadStatus::ClassNotFound => return Err(Error::PluginNotFoundInFactory(id.to_string())),
+        LoadStatus::InitFailed => {
+            return Err(Error::InitFailed(
+                "Failed to initialize VST3 component".to_string(),
+            ))
+        }
+    }
+
     instance.app = app;
 
     let descriptor = unsafe { descriptor(app) }.to_plugin_descriptor(path);
     Ok((instance, descriptor))
 }
 
-impl PluginInner for Vst3 {
-    fn process(
+impl Vst3Audio {
+    /// Queues the last update for each parameter for the `IEditController`. Events are sorted by
+    /// time so loop from last to first skipping anything we've already seen.
+    fn queue_param_updates_for_edit_controller(&mut self, events: &[HostIssuedEvent]) {
+        let mut seen = HeaplessVec::<ParamId, 300>::new();
+        for event in events.iter().rev() {
+            if let HostIssuedEventType::Parameter(ref param) = event.event_type {
+                if seen.contains(param.parameter_id) {
+                    continue;
+                }
+
+                let _ = self.param_updates_for_edit_controller.try_push(param.clone());
+                let _ = seen.push(param.parameter_id);
+            }
+        }
+    }
+
+    /// Points the bus pointers at the channels of `inputs` and `outputs` starting `offset`
+    /// samples in.
+    fn bind_buffers<T>(&mut self, inputs: &[AudioBus<T>], outputs: &mut [AudioBus<T>], offset: usize) {
+        // `channel_pointers` is never grown past its capacity so the bus pointers into it stay
+        // valid. `AudioHandle` has already checked the buses against the limits.
+        self.channel_pointers.clear();
+        self.input_bus_pointers.clear();
+        self.output_bus_pointers.clear();
+
+        for bus in inputs.iter().take(self.input_bus_pointers.capacity()) {
+            let start = self.channel_pointers.len();
+            for channel in bus.data.iter() {
+                if self.channel_pointers.len() == self.channel_pointers.capacity() {
+                    break;
+                }
+                let channel = unsafe { channel.as_ptr().add(offset) };
+                self.channel_pointers.push(channel as *mut c_void);
+            }
+            let bus_pointer = unsafe { self.channel_pointers.as_mut_ptr().add(start) };
+            self.input_bus_pointers.push(bus_pointer);
+        }
+
+        for bus in outputs.iter_mut().take(self.output_bus_pointers.capacity()) {
+            let start = self.channel_pointers.len();
+            for channel in bus.data.iter_mut() {
+                if self.channel_pointers.len() == self.channel_pointers.capacity() {
+                    break;
+                }
+                let channel = unsafe { channel.as_mut_ptr().add(offset) };
+                self.channel_pointers.push(channel as *mut c_void);
+            }
+            let bus_pointer = unsafe { self.channel_pointers.as_mut_ptr().add(start) };
+            self.output_bus_pointers.push(bus_pointer);
+        }
+    }
+
+    /// Shared by `process` and `process_f64`. The C++ side picks the 32 or 64-bit buffers based
+    /// on the sample size the plugin was set up with.
+    fn process_buffers<T>(
         &mut self,
-        inputs: &[AudioBus<f32>],
-        outputs: &mut [AudioBus<f32>],
-        mut events: Vec<HostIssuedEvent>,
+        app: *const c_void,
+        inputs: &[AudioBus<T>],
+        outputs: &mut [AudioBus<T>],
+        events: &[HostIssuedEvent],
+        output_events: &mut OutputEvents,
         process_details: &ProcessDetails,
-    ) {
+    ) -> ProcessStatus {
         // Queue parameters to be sent to the IEditController because they need to be sent to both
         // the IEditController and IAudioProcessor separately.
-        for update in last_param_updates(&events) {
-            let _ = self.param_updates_for_edit_controller.try_push(update);
+        self.queue_param_updates_for_edit_controller(events);
+
+        // Never grow past the capacity reserved in `load`.
+        self.events.clear();
+        let capacity = self.events.capacity();
+        for event in events.iter().take(capacity) {
+            self.events.push(event.clone());
         }
 
         // " When the controller transmits a parameter change to the host, the host synchronizes
         //   the processor by passing the new values as Steinberg::Vst::IParameterChanges to the
         //   process call. "
-        while let Some(param_update) = self.param_updates_for_audio_processor.try_pop() {
+        while self.events.len() < capacity {
+            let Some(param_update) = self.param_updates_for_audio_processor.try_pop() else {
+                break;
+            };
+
             if param_update.current_value.is_nan() {
                 continue;
             }
 
-            events.push(HostIssuedEvent {
+            self.events.push(HostIssuedEvent {
                 ppq_time: process_details.player_time,
                 event_type: HostIssuedEventType::Parameter(param_update),
                 ..HostIssuedEvent::default()
             });
         }
 
-        let mut channel_buffers = HeaplessVec::<HeaplessVec<*mut f32, 16>, 32>::new();
-
-        let mut input_ptrs = HeaplessVec::<*mut *mut f32, 16>::new();
-        let mut output_ptrs = HeaplessVec::<*mut *mut f32, 16>::new();
-
-        assert!(inputs.len() <= 16);
-        for bus in inputs.iter() {
-            let mut channels = HeaplessVec::<*mut f32, 16>::new();
-            assert!(bus.data.len() <= 16);
-            for channel_idx in 0..bus.data.len() {
-                channels
-                    .push(bus.data[channel_idx].as_ptr() as *mut f32)
-                    .unwrap();
+        // VST3 has no mid-block transport changes so split the block at `Transport` events.
+        let block_size = process_details.block_size;
+        let mut sub_blocks = SubBlocks::new(process_details);
+        let mut ok = true;
+
+        while let Some((range, details)) = sub_blocks.next(&self.events) {
+            let mut details = details.clone();
+            details.block_size = range.len();
+
+            // A subset of `events` so this never grows past its capacity.
+            self.sub_block_events.clear();
+            for event in self.events.iter() {
+                if in_sub_block(event, &range, block_size) {
+                    self.sub_block_events.push(HostIssuedEvent {
+                        block_time: event.block_time.saturating_sub(range.start),
+                        ..event.clone()
+                    });
+                }
             }
-            channel_buffers.push(channels).unwrap();
-            input_ptrs
-                .push(channel_buffers.last_mut().unwrap().as_mut_ptr())
-                .unwrap();
+
+            self.bind_buffers(inputs, outputs, range.start);
+
+            ok &= unsafe {
+                vst3_wrapper_sys::process(
+                    app,
+                    &details as *const ProcessDetails,
+                    self.input_bus_pointers.as_mut_ptr(),
+                    self.output_bus_pointers.as_mut_ptr(),
+                    self.sub_block_events.as_mut_ptr(),
+                    self.sub_block_events.len() as i32,
+                    output_events as *mut OutputEvents as *mut c_void,
+                    range.start as i32,
+                )
+            };
         }
 
-        assert!(outputs.len() <= 16);
-        for bus in outputs.iter_mut() {
-            let mut channels = HeaplessVec::<*mut f32, 16>::new();
-            assert!(bus.data.len() <= 16);
-            for channel_idx in 0..bus.data.len() {
-                channels.push(bus.data[channel_idx].as_mut_ptr()).unwrap();
-            }
-            channel_buffers.push(channels).unwrap();
-            output_ptrs
-                .push(channel_buffers.last_mut().unwrap().as_mut_ptr())
-                .unwrap();
+        if ok {
+            ProcessStatus::Ok
+        } else {
+            ProcessStatus::Error
         }
+    }
+}
 
-        unsafe {
-            vst3_wrapper_sys::process(
-                self.app,
-                process_details as *const ProcessDetails,
-                input_ptrs.as_mut_ptr(),
-                output_ptrs.as_mut_ptr(),
-                events.as_mut_ptr(),
-                events.len() as i32,
-            );
+impl PluginInner for Vst3 {
+    fn process(
+        &self,
+        inputs: &[AudioBus<f32>],
+        outputs: &mut [AudioBus<f32>],
+        events: &[HostIssuedEvent],
+        output_events: &mut OutputEvents,
+        process_details: &ProcessDetails,
+    ) -> ProcessStatus {
+        let Ok(mut audio) = self.audio.try_lock() else {
+            return ProcessStatus::Error;
+        };
+
+        audio.process_buffers(self.app, inputs, outputs, events, output_events, process_details)
+    }
+
+    fn process_f64(
+        &self,
+        inputs: &[AudioBus<f64>],
+        outputs: &mut [AudioBus<f64>],
+        events: &[HostIssuedEvent],
+        output_events: &mut OutputEvents,
+        process_details: &ProcessDetails,
+    ) -> ProcessStatus {
+        let Ok(mut audio) = self.audio.try_lock() else {
+            return ProcessStatus::Error;
+        };
+
+        audio.process_buffers(self.app, inputs, outputs, events, output_events, process_details)
+    }
+
+    fn supports_f64(&self) -> bool {
+        unsafe { vst3_wrapper_sys::supports_f64(self.app) }
+    }
+
+    fn set_sample_precision(&self, precision: SamplePrecision) -> Result<(), Error> {
+        let f64 = precision == SamplePrecision::F64;
+        if !unsafe { vst3_wrapper_sys::vst3_set_sample_precision(self.app, f64) } {
+            return Err(Error::ActivationFailed);
         }
+
+        Ok(())
     }
 
-    fn set_preset_data(&mut self, data: Vec<u8>) -> Result<(), String> {
+    fn set_preset_data(&self, data: Vec<u8>) -> Result<(), Error> {
         unsafe {
             let mut proc_data_len = 0;
 
             if data.len() < 4 {
-                return Err("Invalid data".to_string());
+                return Err(Error::StateRejected("Invalid data".to_string()));
             }
 
             proc_data_len |= data[0] as usize;
@@ -149,8 +310,8 @@ impl PluginInner for Vst3 {
 
             println!("loading with proc data len {}", proc_data_len);
 
-            if data.len() < proc_data_len {
-                return Err("Invalid data".to_string());
+            if data.len() < proc_data_len + 4 {
+                return Err(Error::StateRejected("Invalid data".to_string()));
             }
 
             let processor_data = &data[4..(proc_data_len + 4)];
@@ -159,22 +320,31 @@ impl PluginInner for Vst3 {
             println!("proc {}", processor_data.len());
             println!("cont {}", controller_data.len());
 
-            vst3_wrapper_sys::set_data(
+            if !vst3_wrapper_sys::set_data(
                 self.app,
                 processor_data.as_ptr() as *const c_void,
                 processor_data.len() as i32,
-            );
-            vst3_wrapper_sys::set_controller_data(
+            ) {
+                return Err(Error::StateRejected(
+                    "Failed to set processor state".to_string(),
+                ));
+            }
+
+            if !vst3_wrapper_sys::set_controller_data(
                 self.app,
                 controller_data.as_ptr() as *const c_void,
                 controller_data.len() as i32,
-            );
+            ) {
+                return Err(Error::StateRejected(
+                    "Failed to set controller state".to_string(),
+                ));
+            }
 
             Ok(())
         }
     }
 
-    fn get_preset_data(&mut self) -> Result<Vec<u8>, String> {
+    fn get_preset_data(&self) -> Result<Vec<u8>, Error> {
         unsafe {
             let mut len = 0;
             let mut stream = std::ptr::null();
@@ -183,15 +353,17 @@ impl PluginInner for Vst3 {
                 &mut len as *mut i32,
                 &mut stream as *mut *const c_void,
             );
-            let processor_data = if !data.is_null() {
-                let data = std::slice::from_raw_parts(data as *const u8, len as usize)
-                    .to_vec()
-                    .clone();
+            if data.is_null() {
                 vst3_wrapper_sys::free_data_stream(stream);
-                data
-            } else {
-                vec![]
-            };
+                return Err(Error::StateRejected(
+                    "Failed to get processor state".to_string(),
+                ));
+            }
+
+            let processor_data = std::slice::from_raw_parts(data as *const u8, len as usize)
+                .to_vec()
+                .clone();
+            vst3_wrapper_sys::free_data_stream(stream);
 
             let mut len = 0;
             let mut stream = std::ptr::null();
@@ -200,15 +372,15 @@ impl PluginInner for Vst3 {
                 &mut len as *mut i32,
                 &mut stream as *mut *const c_void,
             );
+            // Not all plugins have controller state so this isn't an error.
             let controller_data = if !data.is_null() {
-                let data = std::slice::from_raw_parts(data as *const u8, len as usize)
+                std::slice::from_raw_parts(data as *const u8, len as usize)
                     .to_vec()
-                    .clone();
-                vst3_wrapper_sys::free_data_stream(stream);
-                data
+                    .clone()
             } else {
                 vec![]
             };
+            vst3_wrapper_sys::free_data_stream(stream);
 
             let mut data = vec![];
 
@@ -231,50 +403,97 @@ impl PluginInner for Vst3 {
         }
     }
 
-    fn get_preset_name(&mut self, _id: i32) -> Result<String, String> {
-        todo!()
+    fn get_preset_name(&self, _id: i32) -> Result<String, Error> {
+        // TODO: IUnitInfo program lists
+        Err(Error::ExtensionMissing("preset list"))
     }
 
-    fn set_preset(&mut self, _id: i32) -> Result<(), String> {
-        todo!()
+    fn set_preset(&self, _id: i32) -> Result<(), Error> {
+        Err(Error::ExtensionMissing("preset list"))
     }
 
-    fn get_parameter(&self, id: i32) -> crate::parameter::Parameter {
-        unsafe { get_parameter(self.app, id) }
+    fn get_parameter(&self, index: usize) -> crate::parameter::Parameter {
+        let mut value_names: Vec<String> = Vec::new();
+        let parameter = unsafe {
+            get_parameter(self.app, index as i32, &mut value_names as *mut Vec<String> as *mut c_void)
+        };
+
+        parameter.into_parameter(value_names)
+    }
+
+    fn get_parameter_id(&self, index: usize) -> ParamId {
+        unsafe { vst3_wrapper_sys::get_parameter_id(self.app, index as i32) }
+    }
+
+    fn get_parameter_value(&self, id: ParamId) -> Result<f32, Error> {
+        Ok(unsafe { vst3_wrapper_sys::get_parameter_value(self.app, id) } as f32)
+    }
+
+    fn format_parameter_value(&self, id: ParamId, value: f32) -> Result<String, Error> {
+        let text = unsafe { vst3_wrapper_sys::format_parameter_value(self.app, id, value as f64) };
+
+        vst3_wrapper_sys::load_and_free_nullable_c_string(text)
+            .ok_or_else(|| Error::ParameterConversionFailed(value.to_string()))
+    }
+
+    fn parse_parameter_text(&self, id: ParamId, text: &str) -> Result<f32, Error> {
+        let failed = || Error::ParameterConversionFailed(text.to_string());
+
+        let c_text = CString::new(text).map_err(|_| failed())?;
+        let mut value: f64 = 0.;
+        if !unsafe { vst3_wrapper_sys::parse_parameter_text(self.app, id, c_text.as_ptr(), &mut value) } {
+            return Err(failed());
+        }
+
+        Ok(value.clamp(0., 1.) as f32)
     }
 
     fn show_editor(
-        &mut self,
+        &self,
         window_id: *mut std::ffi::c_void,
         window_id_type: WindowIDType,
     ) -> Result<(usize, usize), Error> {
-        let dims = unsafe { vst3_wrapper_sys::show_gui(self.app, window_id as *const c_void, window_id_type) };
+        let mut dims = Dims { width: 0, height: 0 };
+
+        if !unsafe { vst3_wrapper_sys::show_gui(self.app, window_id as *const c_void, window_id_type, &mut dims) } {
+            return Err(Error::EditorUnavailable(
+                "VST3 plugin failed to attach its editor view".to_string(),
+            ));
+        }
 
         Ok((dims.width as usize, dims.height as usize))
     }
 
-    fn hide_editor(&mut self) {
+    fn hide_editor(&self) {
         unsafe { vst3_wrapper_sys::hide_gui(self.app) };
     }
 
-    fn suspend(&mut self) {
+    fn suspend(&self) {
         unsafe { vst3_wrapper_sys::set_processing(self.app, false) };
     }
 
-    fn resume(&mut self) {
+    fn resume(&self) {
         unsafe { vst3_wrapper_sys::set_processing(self.app, true) };
     }
 
-    fn get_io_configuration(&mut self) -> crate::audio_bus::IOConfigutaion {
-        unsafe { vst3_wrapper_sys::io_config(self.app) }
+    fn get_io_configuration(&self) -> IOConfigutaion {
+        let mut config = IOConfigutaion::default();
+        unsafe { vst3_wrapper_sys::io_config(self.app, &mut config) };
+        config
     }
 
-    fn get_latency(&mut self) -> crate::Samples {
+    fn get_latency(&self) -> crate::Samples {
         unsafe { vst3_wrapper_sys::get_latency(self.app) as crate::Samples }
     }
 
-    fn editor_updates(&mut self) {
-        while let Some(update) = self.param_updates_for_edit_controller.try_pop() {
+    fn editor_updates(&self) {
+        loop {
+            // Not locked while calling into the plugin, which may call `send_event_to_host`.
+            let update = self.param_updates_for_edit_controller.lock().unwrap().try_pop();
+            let Some(update) = update else {
+                break;
+            };
+
             if !update.current_value.is_nan() {
                 unsafe {
                     set_param_in_edit_controller(
@@ -291,62 +510,58 @@ impl PluginInner for Vst3 {
         unsafe { vst3_wrapper_sys::parameter_count(self.app) }
     }
 
-    fn change_sample_rate(&mut self, _rate: crate::SampleRate) {
-        unsafe { vst3_wrapper_sys::vst3_set_sample_rate(self.app, _rate as i32) };
+    fn change_sample_rate(&self, rate: crate::SampleRate) -> Result<(), Error> {
+        if !unsafe { vst3_wrapper_sys::vst3_set_sample_rate(self.app, rate as i32) } {
+            return Err(Error::ActivationFailed);
+        }
+
+        Ok(())
     }
 
-    fn set_track_details(&mut self, details: &crate::track::Track) {
+    fn set_track_details(&self, details: &crate::track::Track) {
         unsafe { vst3_wrapper_sys::set_track_details(self.app, details) };
     }
 }
 
-/// Gets param updates taking the final update at the latest sample for each parameter
-fn last_param_updates(events: &[HostIssuedEvent]) -> Vec<ParameterUpdate> {
-    struct ParamUpdate<'a> {
-        param: &'a ParameterUpdate,
-        block_time: Samples,
+/// VST3 factories only give a support e-mail, which may or may not have the `mailto:` already.
+fn support_url(email: String) -> String {
+    if email.is_empty() || email.starts_with("mailto:") {
+        email
+    } else {
+        format!("mailto:{}", email)
     }
-
-    // FIXME: Make real-time safe
-    let mut updates: HashMap<i32, ParamUpdate> = HashMap::new();
-    for event in events {
-        if let HostIssuedEventType::Parameter(ref param) = event.event_type {
-            if let Some(existing) = updates.get_mut(&param.parameter_id) {
-                if event.block_time < existing.block_time {
-                    continue;
-                }
-            }
-
-            updates.insert(
-                param.parameter_id,
-                ParamUpdate {
-                    param,
-                    block_time: event.block_time,
-                },
-            );
-        }
-    }
-
-    updates.values().map(|v| v.param.clone()).collect()
 }
 
+/// Reads `moduleinfo.json` if the bundle has one and the `vst3_moduleinfo` feature is on,
+/// otherwise loads the module to get its classes.
 pub fn get_descriptor(path: &Path) -> Vec<PluginDescriptor> {
-    let mut descs = HeaplessVec::<FFIPluginDescriptor, 10>::new();
+    #[cfg(feature = "vst3_moduleinfo")]
+    if let Some(descriptors) = moduleinfo::get_descriptors(path) {
+        return descriptors;
+    }
+
+    let mut descs = Vec::<FFIPluginDescriptor>::new();
 
     let c_path = path.to_string_lossy().to_string() + "\0";
     unsafe {
-        vst3_wrapper_sys::get_descriptors(c_path.as_ptr() as *const std::ffi::c_char, &mut descs);
+        vst3_wrapper_sys::get_descriptors(
+            c_path.as_ptr() as *const std::ffi::c_char,
+            &mut descs as *mut _ as *mut c_void,
+        );
     }
 
     descs
-        .as_slice()
-        .iter()
+        .into_iter()
         .map(|d| d.to_plugin_descriptor(path))
         .collect::<Vec<_>>()
 }
 
 impl Drop for Vst3 {
     fn drop(&mut self) {
+        if self.app.is_null() {
+            return;
+        }
+
         unsafe {
             vst3_wrapper_sys::unload(self.app);
         }
@@ -0,0 +1,195 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::discovery::{Format, PluginCategory, PluginDescriptor};
+
+use super::support_url;
+
+/// Reads the descriptors from the bundle's `moduleinfo.json`, written by VST3 SDK 3.7.5 and
+/// later, without loading the binary. `None` if there's no such file or it can't be parsed, in
+/// which case the module has to be loaded.
+pub(super) fn get_descriptors(path: &Path) -> Option<Vec<PluginDescriptor>> {
+    let text = std::fs::read_to_string(module_info_path(path)?).ok()?;
+    let info: Value = serde_json::from_str(&strip_json5(&text)).ok()?;
+
+    let str_of = |value: &Value, key: &str| value[key].as_str().unwrap_or_default().to_string();
+
+    let factory = &info["Factory Info"];
+    let url = str_of(factory, "URL");
+    let support_url = support_url(str_of(factory, "E-Mail"));
+
+    // Old class IDs each new class can be loaded in place of.
+    let compatibility: Vec<(String, Vec<String>)> = info["Compatibility"]
+        .as_array()
+        .map(|entries| {
+            entries
+                .iter()
+                .map(|entry| {
+                    let old = entry["Old"]
+                        .as_array()
+                        .map(|old| old.iter().filter_map(|id| id.as_str()).map(class_id).collect())
+                        .unwrap_or_default();
+                    (class_id(entry["New"].as_str().unwrap_or_default()), old)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let descriptors = info["Classes"]
+        .as_array()?
+        .iter()
+        .filter(|class| class["Category"].as_str() == Some("Audio Module Class"))
+        .map(|class| {
+            let id = class_id(class["CID"].as_str().unwrap_or_default());
+            let features: Vec<String> = class["Sub Categories"]
+                .as_array()
+                .map(|sub| sub.iter().filter_map(|s| s.as_str()).map(str::to_string).collect())
+                .unwrap_or_default();
+
+            let mut version = str_of(class, "Version");
+            if version.is_empty() {
+                version = str_of(&info, "Version");
+            }
+
+            let mut vendor = str_of(class, "Vendor");
+            if vendor.is_empty() {
+                vendor = str_of(factory, "Vendor");
+            }
+
+            PluginDescriptor {
+                name: str_of(class, "Name"),
+                replaces: compatibility
+                    .iter()
+                    .filter(|(new, _)| *new == id)
+                    .flat_map(|(_, old)| old.iter().cloned())
+                    .collect(),
+                id,
+                path: path.to_path_buf(),
+                version,
+                vendor,
+                format: Format::Vst3,
+                initial_latency: 0,
+                category: PluginCategory::from_features(&features),
+                features,
+                url: url.clone(),
+                support_url: support_url.clone(),
+                sdk_version: str_of(class, "SDKVersion"),
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    Some(descriptors)
+}
+
+/// `Contents/Resources/moduleinfo.json`, or `Contents/moduleinfo.json` where SDK 3.7.5 put it.
+fn module_info_path(path: &Path) -> Option<PathBuf> {
+    let contents = path.join("Contents");
+
+    [
+        contents.join("Resources").join("moduleinfo.json"),
+        contents.join("moduleinfo.json"),
+    ]
+    .into_iter()
+    .find(|path| path.is_file())
+}
+
+/// Class IDs in the same form as the C++ side's `UID::toString`.
+fn class_id(id: &str) -> String {
+    id.chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// `moduleinfo.json` is JSON5, so removes comments and trailing commas to make it JSON.
+fn strip_json5(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                out.push(c);
+                while let Some(c) = chars.next() {
+                    out.push(c);
+                    match c {
+                        '\\' => out.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = '\0';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            '}' | ']' => {
+                // A comma followed only by whitespace, which was already copied.
+                let trimmed = out.trim_end();
+                if trimmed.ends_with(',') {
+                    out.truncate(trimmed.len() - 1);
+                }
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_comments() {
+        let text = "{\n  // line comment\n  \"a\": 1, /* block\n comment */ \"b\": 2\n}";
+        let value: Value = serde_json::from_str(&strip_json5(text)).unwrap();
+
+        assert_eq!(value["a"], 1);
+        assert_eq!(value["b"], 2);
+    }
+
+    #[test]
+    fn strips_trailing_commas() {
+        let text = "{ \"a\": [1, 2, ], \"b\": { \"c\": 3, }, }";
+        let value: Value = serde_json::from_str(&strip_json5(text)).unwrap();
+
+        assert_eq!(value["a"], serde_json::json!([1, 2]));
+        assert_eq!(value["b"]["c"], 3);
+    }
+
+    #[test]
+    fn keeps_strings_intact() {
+        let text = r#"{ "url": "https://example.com//x", "text": "a /* b */, }", "quote": "\"//" }"#;
+        let value: Value = serde_json::from_str(&strip_json5(text)).unwrap();
+
+        assert_eq!(value["url"], "https://example.com//x");
+        assert_eq!(value["text"], "a /* b */, }");
+        assert_eq!(value["quote"], "\"//");
+    }
+
+    #[test]
+    fn class_id_matches_uid_to_string() {
+        assert_eq!(
+            class_id("5d92f8e1-1b8c-4b3e-9a0f-7c6d5e4f3a2b"),
+            "5D92F8E11B8C4B3E9A0F7C6D5E4F3A2B"
+        );
+        assert_eq!(
+            class_id("5D92F8E11B8C4B3E9A0F7C6D5E4F3A2B"),
+            "5D92F8E11B8C4B3E9A0F7C6D5E4F3A2B"
+        );
+    }
+}
@@ -6,27 +6,35 @@ use std::{
 use ringbuf::{traits::Producer};
 
 use crate::{
-    audio_bus::IOConfigutaion, event::{HostIssuedEvent, PluginIssuedEvent}, formats::{vst3::Vst3, Format, PluginDescriptor}, heapless_vec::HeaplessVec, parameter::Parameter, track::Track, ProcessDetails, WindowIDType
+    audio_bus::IOConfigutaion, event::{HostIssuedEvent, OutputEvent, OutputEvents, PluginIssuedEvent}, formats::{vst3::{support_url, Vst3}, Format, IoSummary, PluginCategory, PluginDescriptor}, heapless_vec::HeaplessString, parameter::{ParamId, Parameter}, track::Track, ProcessDetails, WindowIDType
 };
 
 #[link(name = "vst3wrapper", kind = "static")]
 extern "C" {
-    pub(super) fn load_plugin(path: *const c_char, id: *const c_char, vst3_instance: *const c_void) -> *const c_void;
-    pub(super) fn show_gui(app: *const c_void, window_id: *const c_void, window_id_type: WindowIDType) -> Dims;
+    pub(super) fn load_plugin(path: *const c_char, id: *const c_char, vst3_instance: *const c_void, status: *mut LoadStatus) -> *const c_void;
+    pub(super) fn show_gui(app: *const c_void, window_id: *const c_void, window_id_type: WindowIDType, dims: *mut Dims) -> bool;
     pub(super) fn hide_gui(app: *const c_void);
     pub(super) fn descriptor(app: *const c_void) -> FFIPluginDescriptor;
-    pub(super) fn io_config(app: *const c_void) -> IOConfigutaion;
+    // Only passed back to `push_audio_bus_to_io_config`, the C++ side treats it as opaque.
+    #[allow(improper_ctypes)]
+    pub(super) fn io_config(app: *const c_void, config: *mut IOConfigutaion);
     pub(super) fn parameter_count(app: *const c_void) -> usize;
     pub(super) fn process(
         app: *const c_void,
         data: *const ProcessDetails,
-        input: *mut *mut *mut f32,
-        output: *mut *mut *mut f32,
+        input: *mut *mut *mut c_void,
+        output: *mut *mut *mut c_void,
         events: *mut HostIssuedEvent,
         events_len: i32,
-    );
-    pub(super) fn set_param_in_edit_controller(app: *const c_void, id: i32, value: f32);
-    pub(super) fn get_parameter(app: *const c_void, id: i32) -> Parameter;
+        output_events: *mut c_void,
+        output_offset: i32,
+    ) -> bool;
+    pub(super) fn set_param_in_edit_controller(app: *const c_void, id: ParamId, value: f32);
+    pub(super) fn get_parameter(app: *const c_void, index: i32, value_names: *mut c_void) -> FFIParameter;
+    pub(super) fn get_parameter_id(app: *const c_void, index: i32) -> ParamId;
+    pub(super) fn get_parameter_value(app: *const c_void, id: ParamId) -> f64;
+    pub(super) fn format_parameter_value(app: *const c_void, id: ParamId, value: f64) -> *const c_char;
+    pub(super) fn parse_parameter_text(app: *const c_void, id: ParamId, text: *const c_char, value: *mut f64) -> bool;
 
     pub(super) fn get_data(
         app: *const c_void,
@@ -39,13 +47,15 @@ extern "C" {
         stream: *mut *const c_void,
     ) -> *const c_void;
     pub(super) fn free_data_stream(stream: *const c_void);
-    pub(super) fn set_data(app: *const c_void, data: *const c_void, data_len: i32);
-    pub(super) fn set_controller_data(app: *const c_void, data: *const c_void, data_len: i32);
+    pub(super) fn set_data(app: *const c_void, data: *const c_void, data_len: i32) -> bool;
+    pub(super) fn set_controller_data(app: *const c_void, data: *const c_void, data_len: i32) -> bool;
     pub(super) fn set_processing(app: *const c_void, processing: bool);
     pub(super) fn get_latency(app: *const c_void) -> u32;
-    pub(super) fn get_descriptors(path: *const c_char, plugins: *mut HeaplessVec<FFIPluginDescriptor, 10>);
+    pub(super) fn get_descriptors(path: *const c_char, plugins: *mut c_void);
 
-    pub(super) fn vst3_set_sample_rate(app: *const c_void, sample_rate: i32);
+    pub(super) fn vst3_set_sample_rate(app: *const c_void, sample_rate: i32) -> bool;
+    pub(super) fn supports_f64(app: *const c_void) -> bool;
+    pub(super) fn vst3_set_sample_precision(app: *const c_void, f64: bool) -> bool;
     pub(super) fn set_track_details(app: *const c_void, details: *const Track);
     pub(super) fn unload(app: *const c_void);
 
@@ -58,13 +68,166 @@ pub extern "C" fn send_event_to_host(
     vst3_instance: *const c_void,
 ) {
     let event = unsafe { &*event };
-    let vst3 = unsafe { &mut *(vst3_instance as *mut Vst3) };
+    let vst3 = unsafe { &*(vst3_instance as *const Vst3) };
 
     if let PluginIssuedEvent::Parameter(p) = event {
-        let _ = vst3.param_updates_for_audio_processor.try_push(p.clone());
+        let _ = vst3
+            .param_updates_for_audio_processor
+            .lock()
+            .unwrap()
+            .try_push(p.clone());
     }
 
-    let _ = vst3._plugin_issued_events_producer.try_push(event.clone());
+    let _ = vst3
+        .plugin_issued_events_producer
+        .lock()
+        .unwrap()
+        .try_push(event.clone());
+}
+
+/// Called by `get_descriptors` for each plugin class in the module. `plugins` is the `Vec`
+/// passed to `get_descriptors`.
+#[no_mangle]
+pub extern "C" fn push_ffi_plugin_descriptor(plugins: *mut c_void, descriptor: FFIPluginDescriptor) {
+    let plugins = unsafe { &mut *(plugins as *mut Vec<FFIPluginDescriptor>) };
+    plugins.push(descriptor);
+}
+
+/// Called by `process` for each SysEx message the plugin outputs. `output_events` is the pointer
+/// passed to `process`.
+#[no_mangle]
+pub extern "C" fn push_sysex_output(
+    output_events: *mut c_void,
+    block_time: i32,
+    bus_index: i32,
+    data: *const u8,
+    len: u32,
+) {
+    let output_events = unsafe { &mut *(output_events as *mut OutputEvents) };
+    let data = if data.is_null() {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(data, len as usize) }
+    };
+
+    output_events.push(OutputEvent::SysEx {
+        block_time: block_time.max(0) as usize,
+        bus_index: bus_index.max(0) as usize,
+        data,
+    });
+}
+
+/// Called by `process` for each note on or off the plugin outputs.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub extern "C" fn push_note_output(
+    output_events: *mut c_void,
+    block_time: i32,
+    bus_index: i32,
+    note_on: bool,
+    channel: i16,
+    key: i16,
+    velocity: f32,
+    note_id: i32,
+) {
+    let output_events = unsafe { &mut *(output_events as *mut OutputEvents) };
+    let block_time = block_time.max(0) as usize;
+    let bus_index = bus_index.max(0) as usize;
+    let velocity = velocity as f64;
+
+    output_events.push(if note_on {
+        OutputEvent::NoteOn {
+            block_time,
+            bus_index,
+            channel,
+            key,
+            velocity,
+            note_id,
+        }
+    } else {
+        OutputEvent::NoteOff {
+            block_time,
+            bus_index,
+            channel,
+            key,
+            velocity,
+            note_id,
+        }
+    });
+}
+
+/// Called by `process` for each legacy MIDI CC output event, converted to a MIDI 1.0 message.
+#[no_mangle]
+pub extern "C" fn push_midi_output(
+    output_events: *mut c_void,
+    block_time: i32,
+    bus_index: i32,
+    status: u8,
+    data1: u8,
+    data2: u8,
+) {
+    let output_events = unsafe { &mut *(output_events as *mut OutputEvents) };
+
+    output_events.push(OutputEvent::Midi {
+        block_time: block_time.max(0) as usize,
+        bus_index: bus_index.max(0) as usize,
+        data: [status, data1, data2],
+    });
+}
+
+/// Called by `get_parameter` for each value of a list parameter. `value_names` is the `Vec`
+/// passed to `get_parameter`.
+#[no_mangle]
+pub extern "C" fn push_parameter_value_name(value_names: *mut c_void, name: *const c_char) {
+    let value_names = unsafe { &mut *(value_names as *mut Vec<String>) };
+    let name = unsafe { std::ffi::CStr::from_ptr(name) };
+    value_names.push(name.to_string_lossy().into_owned());
+}
+
+/// `Parameter` as returned by the C++ side. The strings are allocated with `alloc_string`.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct FFIParameter {
+    id: ParamId,
+    name: HeaplessString<256>,
+    index: i32,
+    value: f32,
+    formatted_value: HeaplessString<256>,
+    hidden: bool,
+    can_automate: bool,
+    is_wrap_around: bool,
+    read_only: bool,
+    default_value: f32,
+    min_value: f64,
+    max_value: f64,
+    step_count: i32,
+    unit: *const c_char,
+    group: *const c_char,
+    is_bypass: bool,
+}
+
+impl FFIParameter {
+    pub(super) fn into_parameter(self, value_names: Vec<String>) -> Parameter {
+        Parameter {
+            id: self.id,
+            name: self.name,
+            index: self.index,
+            value: self.value,
+            formatted_value: self.formatted_value,
+            hidden: self.hidden,
+            can_automate: self.can_automate,
+            is_wrap_around: self.is_wrap_around,
+            read_only: self.read_only,
+            default_value: self.default_value,
+            min_value: self.min_value,
+            max_value: self.max_value,
+            step_count: self.step_count.max(0) as u32,
+            unit: load_and_free_optional_c_string(self.unit),
+            value_names,
+            group: load_and_free_optional_c_string(self.group),
+            is_bypass: self.is_bypass,
+        }
+    }
 }
 
 #[repr(C)]
@@ -76,10 +239,40 @@ pub struct FFIPluginDescriptor {
     version: *const std::os::raw::c_char,
     id: *const std::os::raw::c_char,
     initial_latency: std::os::raw::c_int,
+    /// `|` separated, as in `ClassInfo::subCategoriesString`.
+    subcategories: *const c_char,
+    sdk_version: *const c_char,
+    /// From the module's factory info.
+    url: *const c_char,
+    email: *const c_char,
+    /// Whether `has_editor` and the bus counts were filled in by creating the plugin.
+    probed: bool,
+    has_editor: bool,
+    audio_inputs: i32,
+    audio_outputs: i32,
+    main_input_channels: i32,
+    main_output_channels: i32,
+    event_inputs: i32,
+    event_outputs: i32,
 }
 
 impl FFIPluginDescriptor {
     pub fn to_plugin_descriptor(self, plugin_path: &Path) -> PluginDescriptor {
+        let features: Vec<String> = load_and_free_optional_c_string(self.subcategories)
+            .split('|')
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        let io = IoSummary {
+            audio_inputs: self.audio_inputs.max(0) as usize,
+            audio_outputs: self.audio_outputs.max(0) as usize,
+            main_input_channels: self.main_input_channels.max(0) as usize,
+            main_output_channels: self.main_output_channels.max(0) as usize,
+            event_inputs: self.event_inputs.max(0) as usize,
+            event_outputs: self.event_outputs.max(0) as usize,
+        };
+
         PluginDescriptor {
             name: load_and_free_c_string(self.name),
             vendor: load_and_free_c_string(self.vendor),
@@ -88,10 +281,29 @@ impl FFIPluginDescriptor {
             initial_latency: self.initial_latency as usize,
             path: plugin_path.to_path_buf(),
             format: Format::Vst3,
+            category: PluginCategory::from_features(&features),
+            features,
+            url: load_and_free_optional_c_string(self.url),
+            support_url: support_url(load_and_free_optional_c_string(self.email)),
+            sdk_version: load_and_free_optional_c_string(self.sdk_version),
+            has_editor: self.probed.then_some(self.has_editor),
+            io: self.probed.then_some(io),
+            ..Default::default()
         }
     }
 }
 
+/// Set by the C++ side in `load_plugin`.
+#[repr(C)]
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum LoadStatus {
+    Ok,
+    ModuleLoadFailed,
+    ClassNotFound,
+    InitFailed,
+}
+
 #[repr(C)]
 #[allow(non_snake_case)]
 #[derive(Debug, Copy, Clone)]
@@ -100,6 +312,24 @@ pub(super) struct Dims {
     pub height: std::os::raw::c_int,
 }
 
+/// Same as `load_and_free_c_string` but `None` for null.
+pub(super) fn load_and_free_nullable_c_string(s: *const c_char) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+
+    Some(load_and_free_c_string(s))
+}
+
+/// Same as `load_and_free_c_string` but empty for null.
+fn load_and_free_optional_c_string(s: *const c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+
+    load_and_free_c_string(s)
+}
+
 fn load_and_free_c_string(s: *const c_char) -> String {
     if s.is_null() {
         return "?".to_string();
@@ -1,6 +1,6 @@
 use std::{fmt::Debug, mem, ops::Index};
 
-use crate::error::{err, Error};
+use crate::error::Error;
 
 #[repr(C)]
 #[derive(Copy)]
@@ -74,7 +74,7 @@ impl<T: Copy, const N: usize> HeaplessVec<T, N> {
             self.count += 1;
             Ok(())
         } else {
-            err("HeaplessVec: vector is full")
+            Err(Error::CapacityExceeded)
         }
     }
 
@@ -197,10 +197,6 @@ impl<const N: usize> HeaplessString<N> {
     pub fn as_str(&self) -> &str {
         std::str::from_utf8(self.data.as_slice()).expect("Invalid UTF-8")
     }
-
-    pub(crate) fn data_raw(&self) -> *const u8 {
-        self.data.as_slice().as_ptr()
-    }
 }
 
 impl<const N: usize> Default for HeaplessString<N> {
@@ -6,6 +6,12 @@ pub struct Host {
     pub url: &'static str,
     pub knob_preference: Option<KnobPreference>,
     pub language: Option<Language>,
+    pub limits: Limits,
+    /// Split VST2 blocks at parameter changes so automation is sample-accurate. Contains the
+    /// smallest sub-block size in samples, changes closer together than this are applied at the
+    /// start of the sub-block they fall in. `None` sends the last value of each parameter at the
+    /// start of the block.
+    pub vst2_parameter_splitting: Option<usize>,
     #[cfg(feature = "future_thread_pool")]
     pub thread_pool_handler: Option<fn(callback: Box<dyn std::future::Future<Output = ()>>)>,
     #[cfg(not(feature = "future_thread_pool"))]
@@ -23,6 +29,31 @@ impl Host {
     }
 }
 
+/// Upper bounds used to size buffers when a plugin is loaded so that processing doesn't allocate.
+/// Anything over these is reported with a `PluginIssuedEvent` instead of being processed.
+#[derive(Clone, Debug, Copy)]
+pub struct Limits {
+    /// Most events sent to a plugin in one `process` call, including queued events.
+    pub max_events_per_block: usize,
+    /// Most input buses, and separately output buses.
+    pub max_buses: usize,
+    /// Most channels across all input buses, and separately across all output buses.
+    pub max_channels: usize,
+    /// Most bytes of SysEx in one block, separately for queued events and plugin output.
+    pub max_sysex_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_events_per_block: 1024,
+            max_buses: 64,
+            max_channels: 256,
+            max_sysex_bytes: 64 * 1024,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Copy)]
 pub enum KnobPreference {
     Circular,
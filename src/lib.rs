@@ -1,10 +1,13 @@
 #![doc = include_str!("../README.md")]
 
+#[cfg(feature = "assert_no_alloc")]
+pub mod alloc_check;
 pub mod audio_bus;
 pub mod discovery;
 pub mod error;
 pub mod event;
 pub mod host;
+pub mod mpe;
 pub mod parameter;
 pub mod plugin;
 pub mod heapless_vec;
@@ -14,6 +17,8 @@ pub(crate) mod utils;
 
 pub use plugin::load;
 
+use event::Transport;
+
 mod formats;
 
 ///////////////////// Unsorted
@@ -60,6 +65,35 @@ impl Default for ProcessDetails {
     }
 }
 
+impl ProcessDetails {
+    /// The transport state at the start of the block.
+    pub fn transport(&self) -> Transport {
+        Transport {
+            tempo: self.tempo,
+            player_time: self.player_time,
+            time_signature_numerator: self.time_signature_numerator,
+            time_signature_denominator: self.time_signature_denominator,
+            cycle_enabled: self.cycle_enabled,
+            cycle_start: self.cycle_start,
+            cycle_end: self.cycle_end,
+            playing_state: self.playing_state,
+            bar_start_pos: self.bar_start_pos,
+        }
+    }
+
+    pub fn set_transport(&mut self, transport: &Transport) {
+        self.tempo = transport.tempo;
+        self.player_time = transport.player_time;
+        self.time_signature_numerator = transport.time_signature_numerator;
+        self.time_signature_denominator = transport.time_signature_denominator;
+        self.cycle_enabled = transport.cycle_enabled;
+        self.cycle_start = transport.cycle_start;
+        self.cycle_end = transport.cycle_end;
+        self.playing_state = transport.playing_state;
+        self.bar_start_pos = transport.bar_start_pos;
+    }
+}
+
 #[derive(Default, PartialEq, Eq, Clone, Copy, Debug)]
 #[repr(u8)]
 pub enum PlayingState {
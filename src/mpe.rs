@@ -0,0 +1,410 @@
+//! MPE (MIDI Polyphonic Expression) support. With `AudioHandle::set_mpe` the per-channel pitch
+//! bend, channel pressure and CC 74 of the MPE member channels are turned into per-note
+//! `HostIssuedEventType::NoteExpression` events for CLAP and VST3 plugins. Each note gets a
+//! `note_id` that stays the same from note on to note off. VST2 plugins and CLAP note ports that
+//! only take MIDI get the MPE MIDI as is.
+
+use crate::event::{HostIssuedEvent, HostIssuedEventType, MidiEvent, NoteExpressionType};
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const CONTROL_CHANGE: u8 = 0xB0;
+const CHANNEL_PRESSURE: u8 = 0xD0;
+const PITCH_BEND: u8 = 0xE0;
+
+const TIMBRE_CC: u8 = 74;
+
+/// Which end of the MIDI channels the zone starts at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MpeZone {
+    /// Master channel 1, member channels counting up from 2.
+    Lower,
+    /// Master channel 16, member channels counting down from 15.
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MpeConfig {
+    pub zone: MpeZone,
+    /// Number of member channels, 1 to 15.
+    pub member_channels: u8,
+    /// Pitch bend range of the member channels in semitones.
+    pub pitch_bend_range: f64,
+}
+
+impl Default for MpeConfig {
+    /// The MPE defaults: a lower zone using every channel with a 48 semitone pitch bend range.
+    fn default() -> Self {
+        Self {
+            zone: MpeZone::Lower,
+            member_channels: 15,
+            pitch_bend_range: 48.0,
+        }
+    }
+}
+
+impl MpeConfig {
+    fn is_member_channel(&self, channel: u8) -> bool {
+        let members = self.member_channels.clamp(1, 15);
+
+        match self.zone {
+            MpeZone::Lower => (1..=members).contains(&channel),
+            MpeZone::Upper => (15 - members..15).contains(&channel),
+        }
+    }
+}
+
+/// Expression of a member channel, applied to every note on it.
+#[derive(Debug, Clone, Copy)]
+struct ChannelState {
+    tuning: f64,
+    pressure: f64,
+    brightness: f64,
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        Self {
+            tuning: 0.0,
+            pressure: 0.0,
+            brightness: 0.5,
+        }
+    }
+}
+
+/// Runs on the audio thread. All state is fixed size so converting doesn't allocate.
+pub(crate) struct MpeConverter {
+    config: MpeConfig,
+    channels: [ChannelState; 16],
+    /// Note id of each sounding note by channel and key, -1 if it's not sounding.
+    note_ids: [[i32; 128]; 16],
+    /// Never 0, which is the id every note that isn't converted has.
+    next_note_id: i32,
+}
+
+impl MpeConverter {
+    pub(crate) fn new(config: MpeConfig) -> Self {
+        Self {
+            config,
+            channels: [ChannelState::default(); 16],
+            note_ids: [[-1; 128]; 16],
+            next_note_id: 1,
+        }
+    }
+
+    /// Converts `events` into `converted`, which is never grown past its capacity. Events on
+    /// buses `convert_bus` returns false for are copied as is. Returns the number of events that
+    /// didn't fit.
+    pub(crate) fn convert(
+        &mut self,
+        events: &[HostIssuedEvent],
+        converted: &mut Vec<HostIssuedEvent>,
+        convert_bus: impl Fn(usize) -> bool,
+    ) -> usize {
+        converted.clear();
+        let mut dropped = 0;
+
+        let mut push = |converted: &mut Vec<HostIssuedEvent>, event: HostIssuedEvent| {
+            if converted.len() < converted.capacity() {
+                converted.push(event);
+            } else {
+                dropped += 1;
+            }
+        };
+
+        for event in events {
+            let HostIssuedEventType::Midi(ref midi) = event.event_type else {
+                push(converted, event.clone());
+                continue;
+            };
+
+            if !convert_bus(event.bus_index) {
+                push(converted, event.clone());
+                continue;
+            }
+
+            let status = midi.midi_data[0] & 0xF0;
+            let channel = midi.midi_data[0] & 0x0F;
+
+            if !self.config.is_member_channel(channel) || midi.midi_data[0] >= 0xF0 {
+                push(converted, event.clone());
+                continue;
+            }
+
+            let key = midi.midi_data[1] & 0x7F;
+            let value = midi.midi_data[2] & 0x7F;
+
+            match status {
+                NOTE_ON if value > 0 => {
+                    let note_id = self.next_note_id;
+                    self.next_note_id = self.next_note_id % i32::MAX + 1;
+                    self.note_ids[channel as usize][key as usize] = note_id;
+
+                    push(converted, with_note_id(event, midi, note_id));
+
+                    // The member channel's current expression applies from the start of the note.
+                    let state = self.channels[channel as usize];
+                    for (expression_type, value) in [
+                        (NoteExpressionType::Tuning, state.tuning),
+                        (NoteExpressionType::Pressure, state.pressure),
+                        (NoteExpressionType::Brightness, state.brightness),
+                    ] {
+                        push(
+                            converted,
+                            note_expression(event, note_id, channel, key, expression_type, value),
+                        );
+                    }
+                }
+                NOTE_ON | NOTE_OFF => {
+                    let note_id =
+                        std::mem::replace(&mut self.note_ids[channel as usize][key as usize], -1);
+
+                    push(converted, with_note_id(event, midi, note_id));
+                }
+                PITCH_BEND => {
+                    let bend = ((value as i32) << 7 | key as i32) - 0x2000;
+                    let tuning = bend as f64 / 0x2000 as f64 * self.config.pitch_bend_range;
+                    self.channels[channel as usize].tuning = tuning;
+
+                    self.push_channel_expression(
+                        event,
+                        channel,
+                        NoteExpressionType::Tuning,
+                        tuning,
+                        &mut |event| push(converted, event),
+                    );
+                }
+                CHANNEL_PRESSURE => {
+                    let pressure = key as f64 / 127.0;
+                    self.channels[channel as usize].pressure = pressure;
+
+                    self.push_channel_expression(
+                        event,
+                        channel,
+                        NoteExpressionType::Pressure,
+                        pressure,
+                        &mut |event| push(converted, event),
+                    );
+                }
+                CONTROL_CHANGE if key == TIMBRE_CC => {
+                    let brightness = value as f64 / 127.0;
+                    self.channels[channel as usize].brightness = brightness;
+
+                    self.push_channel_expression(
+                        event,
+                        channel,
+                        NoteExpressionType::Brightness,
+                        brightness,
+                        &mut |event| push(converted, event),
+                    );
+                }
+                _ => push(converted, event.clone()),
+            }
+        }
+
+        dropped
+    }
+
+    /// Sends the expression to every note sounding on the member channel.
+    fn push_channel_expression(
+        &self,
+        event: &HostIssuedEvent,
+        channel: u8,
+        expression_type: NoteExpressionType,
+        value: f64,
+        push: &mut impl FnMut(HostIssuedEvent),
+    ) {
+        for (key, &note_id) in self.note_ids[channel as usize].iter().enumerate() {
+            if note_id >= 0 {
+                push(note_expression(
+                    event,
+                    note_id,
+                    channel,
+                    key as u8,
+                    expression_type,
+                    value,
+                ));
+            }
+        }
+    }
+}
+
+fn with_note_id(event: &HostIssuedEvent, midi: &MidiEvent, note_id: i32) -> HostIssuedEvent {
+    HostIssuedEvent {
+        event_type: HostIssuedEventType::Midi(MidiEvent {
+            note_id,
+            ..midi.clone()
+        }),
+        ..event.clone()
+    }
+}
+
+fn note_expression(
+    event: &HostIssuedEvent,
+    note_id: i32,
+    channel: u8,
+    key: u8,
+    expression_type: NoteExpressionType,
+    value: f64,
+) -> HostIssuedEvent {
+    HostIssuedEvent {
+        event_type: HostIssuedEventType::NoteExpression {
+            note_id,
+            channel: channel as i16,
+            key: key as i16,
+            expression_type,
+            value,
+        },
+        ..event.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn midi(bus_index: usize, data: [u8; 3]) -> HostIssuedEvent {
+        HostIssuedEvent {
+            event_type: HostIssuedEventType::Midi(MidiEvent {
+                midi_data: data,
+                ..Default::default()
+            }),
+            bus_index,
+            ..Default::default()
+        }
+    }
+
+    fn convert(converter: &mut MpeConverter, events: &[HostIssuedEvent]) -> Vec<HostIssuedEvent> {
+        let mut converted = Vec::with_capacity(64);
+        assert_eq!(converter.convert(events, &mut converted, |_| true), 0);
+        converted
+    }
+
+    fn note_id(event: &HostIssuedEvent) -> i32 {
+        match event.event_type {
+            HostIssuedEventType::Midi(ref midi) => midi.note_id,
+            _ => panic!("not a MIDI event: {:?}", event),
+        }
+    }
+
+    /// `(note_id, key, type, value)` of every note expression.
+    fn expressions(events: &[HostIssuedEvent]) -> Vec<(i32, i16, NoteExpressionType, f64)> {
+        events
+            .iter()
+            .filter_map(|event| match event.event_type {
+                HostIssuedEventType::NoteExpression {
+                    note_id,
+                    key,
+                    expression_type,
+                    value,
+                    ..
+                } => Some((note_id, key, expression_type, value)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn note_id_lasts_from_note_on_to_note_off() {
+        let mut converter = MpeConverter::new(MpeConfig::default());
+
+        let on = convert(&mut converter, &[midi(0, [0x91, 60, 100])]);
+        let id = note_id(&on[0]);
+        assert!(id > 0);
+        // The channel's expression is sent with the note on.
+        assert_eq!(expressions(&on).len(), 3);
+        assert!(expressions(&on).iter().all(|e| e.0 == id));
+
+        let off = convert(&mut converter, &[midi(0, [0x81, 60, 0])]);
+        assert_eq!(note_id(&off[0]), id);
+
+        // Sounding notes are gone so the expression doesn't go anywhere.
+        let bend = convert(&mut converter, &[midi(0, [0xE1, 0, 0x60])]);
+        assert!(expressions(&bend).is_empty());
+    }
+
+    #[test]
+    fn velocity_zero_note_on_ends_the_note() {
+        let mut converter = MpeConverter::new(MpeConfig::default());
+
+        let on = convert(&mut converter, &[midi(0, [0x91, 60, 100])]);
+        let off = convert(&mut converter, &[midi(0, [0x91, 60, 0])]);
+        assert_eq!(note_id(&off[0]), note_id(&on[0]));
+
+        let again = convert(&mut converter, &[midi(0, [0x91, 60, 100])]);
+        assert_ne!(note_id(&again[0]), note_id(&on[0]));
+    }
+
+    #[test]
+    fn expression_goes_to_every_note_on_the_channel() {
+        let mut converter = MpeConverter::new(MpeConfig::default());
+
+        let on = convert(
+            &mut converter,
+            &[
+                midi(0, [0x92, 60, 100]),
+                midi(0, [0x92, 64, 100]),
+                midi(0, [0x93, 67, 100]),
+            ],
+        );
+        let ids: Vec<i32> = on
+            .iter()
+            .filter(|e| matches!(e.event_type, HostIssuedEventType::Midi(_)))
+            .map(note_id)
+            .collect();
+
+        // Full bend up on channel 3 is the whole 48 semitone range.
+        let bend = expressions(&convert(&mut converter, &[midi(0, [0xE2, 0x7F, 0x7F])]));
+        assert_eq!(bend.len(), 2);
+        assert!(bend.contains(&(ids[0], 60, NoteExpressionType::Tuning, 8191.0 / 8192.0 * 48.0)));
+        assert!(bend.contains(&(ids[1], 64, NoteExpressionType::Tuning, 8191.0 / 8192.0 * 48.0)));
+
+        let pressure = expressions(&convert(&mut converter, &[midi(0, [0xD3, 127, 0])]));
+        assert_eq!(pressure, vec![(ids[2], 67, NoteExpressionType::Pressure, 1.0)]);
+
+        let timbre = expressions(&convert(&mut converter, &[midi(0, [0xB2, 74, 0])]));
+        assert_eq!(timbre.len(), 2);
+        assert!(timbre.iter().all(|e| e.2 == NoteExpressionType::Brightness && e.3 == 0.0));
+    }
+
+    #[test]
+    fn master_channel_and_skipped_buses_pass_through() {
+        let mut converter = MpeConverter::new(MpeConfig::default());
+
+        let master = convert(&mut converter, &[midi(0, [0x90, 60, 100])]);
+        assert_eq!(master.len(), 1);
+        assert_eq!(note_id(&master[0]), 0);
+
+        let mut converted = Vec::with_capacity(8);
+        let events = [midi(1, [0x91, 60, 100]), midi(1, [0xE1, 0, 0x60])];
+        converter.convert(&events, &mut converted, |bus| bus == 0);
+        assert_eq!(converted.len(), 2);
+        assert!(expressions(&converted).is_empty());
+        assert_eq!(note_id(&converted[0]), 0);
+
+        // Converted notes never get the id the notes above were left with.
+        let member = convert(&mut converter, &[midi(0, [0x91, 60, 100])]);
+        assert_ne!(note_id(&member[0]), 0);
+    }
+
+    #[test]
+    fn note_ids_wrap_around_without_zero() {
+        let mut converter = MpeConverter::new(MpeConfig::default());
+        converter.next_note_id = i32::MAX;
+
+        let first = convert(&mut converter, &[midi(0, [0x91, 60, 100])]);
+        let second = convert(&mut converter, &[midi(0, [0x91, 61, 100])]);
+        assert_eq!(note_id(&first[0]), i32::MAX);
+        assert_eq!(note_id(&second[0]), 1);
+    }
+
+    #[test]
+    fn events_that_dont_fit_are_counted() {
+        let mut converter = MpeConverter::new(MpeConfig::default());
+
+        let mut converted = Vec::with_capacity(2);
+        let dropped = converter.convert(&[midi(0, [0x91, 60, 100])], &mut converted, |_| true);
+        assert_eq!(converted.len(), 2);
+        assert_eq!(dropped, 2);
+    }
+}
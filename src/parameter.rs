@@ -3,12 +3,27 @@ use serde::{Deserialize, Serialize};
 
 use crate::heapless_vec::HeaplessString;
 
-#[repr(C)]
-#[derive(Debug, Clone)]
+/// Stable identifier of a parameter: the CLAP `clap_id` or VST3 `ParamID`. These stay the same
+/// between plugin versions (unlike indexes) so automation should be saved against them. VST2 has
+/// no parameter IDs so its IDs are the parameter indexes.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ParamId(pub u32);
+
+impl std::fmt::Display for ParamId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Parameter {
-    pub id: i32,
+    pub id: ParamId,
     pub name: HeaplessString<256>,
+    /// Position in the plugin's parameter list. Can change when the plugin is updated or
+    /// rescans its parameters.
     pub index: i32,
     /// Normalized parameter value in [0.0, 1.0].
     pub value: f32,
@@ -20,13 +35,60 @@ pub struct Parameter {
     pub read_only: bool,
     /// Default normalized value if supported by the format. Not supported by VST2.
     pub default_value: f32,
+    /// Plain value at normalized 0.0. VST2 plugins without an integer range use 0 to 1.
+    pub min_value: f64,
+    /// Plain value at normalized 1.0.
+    pub max_value: f64,
+    /// Number of steps between the minimum and maximum, 0 for continuous parameters. A switch
+    /// has 1 step.
+    pub step_count: u32,
+    /// Unit label, e.g. "dB". Empty if the plugin doesn't give one. Not supported by CLAP.
+    pub unit: String,
+    /// Names of the `step_count + 1` values of a list parameter, from the minimum up. Empty for
+    /// other parameters. Not supported by VST2.
+    pub value_names: Vec<String>,
+    /// Group the parameter is in with parent groups separated by `/`, e.g. "Oscillator 1/Filter".
+    /// Empty for parameters that aren't in a group.
+    pub group: String,
+    /// Whether this is the plugin's bypass parameter. Not supported by VST2.
+    pub is_bypass: bool,
+}
+
+impl Parameter {
+    /// Rounds a normalized value to the nearest step. Continuous parameters are returned as is.
+    pub fn snap(&self, normalized: f32) -> f32 {
+        if self.step_count == 0 {
+            return normalized;
+        }
+
+        let steps = self.step_count as f32;
+        (normalized.clamp(0., 1.) * steps).round() / steps
+    }
+
+    /// Maps linearly between the normalized and plain range. The plugin's own mapping may be
+    /// different, e.g. for frequencies.
+    pub fn to_plain(&self, normalized: f32) -> f64 {
+        self.min_value + (self.max_value - self.min_value) * normalized as f64
+    }
+
+    /// The inverse of `to_plain`.
+    pub fn to_normalized(&self, plain: f64) -> f32 {
+        if self.max_value <= self.min_value {
+            return 0.;
+        }
+
+        ((plain - self.min_value) / (self.max_value - self.min_value)).clamp(0., 1.) as f32
+    }
 }
 
 #[derive(Debug, Clone)]
 #[repr(C)]
 pub struct ParameterUpdate {
-    pub parameter_id: i32,
+    pub parameter_id: ParamId,
+    /// Filled in by `MainHandle` from `parameter_id`, -1 if unknown. Not required to be set
+    /// when sending events to the plugin.
     pub parameter_index: i32,
+    /// Normalized value in [0.0, 1.0].
     pub current_value: f32,
     /// Value at start of edit. For example, the value before the user started dragging a knob
     /// in the plugin editor. Not required to be set when sending events to the plugin; just
@@ -37,7 +99,7 @@ pub struct ParameterUpdate {
 }
 
 impl ParameterUpdate {
-    pub fn new(id: i32, value: f32) -> Self {
+    pub fn new(id: ParamId, value: f32) -> Self {
         ParameterUpdate {
             parameter_id: id,
             parameter_index: -1,
@@ -47,31 +109,3 @@ impl ParameterUpdate {
         }
     }
 }
-
-#[derive(Clone, Copy)]
-#[repr(C)]
-pub union ParameterIdentifier {
-    raw: u64,
-    vst2: i32,
-    vst3: IndexIdPair,
-}
-
-impl ParameterIdentifier {
-    pub fn raw(&self) -> u64 {
-        unsafe { self.raw }
-    }
-
-    pub fn as_vst2(&self) -> i32 {
-        unsafe { self.vst2 }
-    }
-
-    pub fn as_vst3(&self) -> IndexIdPair {
-        unsafe { self.vst3 }
-    }
-}
-
-#[derive(Debug, Clone, Copy)]
-pub struct IndexIdPair {
-    pub index: i32,
-    pub id: i32,
-}
@@ -1,22 +1,26 @@
-// TODO: make safe rust way to call different plugin methods from the correct threads. Probably
-// with separate structures for audio and UI threads with the allowed methods and some raw pointer
-// stuff behind.
-
 use std::{
     any::Any,
+    collections::HashMap,
+    marker::PhantomData,
+    mem::ManuallyDrop,
     path::Path,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
 };
 
-use ringbuf::{traits::*, HeapCons, HeapRb};
+use ringbuf::{traits::*, HeapCons, HeapProd, HeapRb};
 
 use crate::{
     audio_bus::{AudioBus, IOConfigutaion},
-    discovery::PluginDescriptor,
-    error::{err, Error},
-    event::{HostIssuedEvent, PluginIssuedEvent},
-    host::Host,
-    parameter::Parameter,
+    discovery::{Format, PluginDescriptor},
+    error::Error,
+    event::{HostIssuedEvent, HostIssuedEventType, OutputEvents, PluginIssuedEvent, SysEx},
+    heapless_vec::HeaplessString,
+    host::{Host, Limits},
+    mpe::{MpeConfig, MpeConverter},
+    parameter::{ParamId, Parameter},
     track::Track,
     BlockSize, ProcessDetails, SampleRate, Samples, WindowIDType,
 };
@@ -26,7 +30,7 @@ use crate::{
 /// Note that formats such as VST3 allow multiple plugins to be defined in the same file.
 pub fn load(path: impl AsRef<Path>, id: &str, host: &Host) -> Result<PluginInstance, Error> {
     if !path.as_ref().exists() {
-        return err("Path does not exist");
+        return Err(Error::PathNotFound(path.as_ref().to_path_buf()));
     }
 
     let plugin_issued_events: HeapRb<PluginIssuedEvent> = HeapRb::new(512);
@@ -38,140 +42,255 @@ pub fn load(path: impl AsRef<Path>, id: &str, host: &Host) -> Result<PluginInsta
         plugin_issued_events_producer,
     };
 
-    let (mut inner, descriptor) = crate::formats::load_any(path.as_ref(), id, common)?;
-
-    let io_configuration = inner.get_io_configuration();
+    let (inner, descriptor) = crate::formats::load_any(path.as_ref(), id, common)?;
 
-    Ok(PluginInstance {
-        latency: AtomicUsize::new(descriptor.initial_latency),
-        window: Box::new(()),
-        descriptor,
+    Ok(PluginInstance::new(
         inner,
-        plugin_issued_events: plugin_issued_events_consumer,
-        sample_rate: 0,
-        block_size: 0,
-        last_seen_block_size: AtomicUsize::new(0),
-        last_seen_sample_rate: AtomicUsize::new(0),
-        showing_editor: false,
-        io_configuration,
-        resumed: false,
-    })
+        descriptor,
+        plugin_issued_events_consumer,
+        host.limits,
+    ))
 }
 
 /// For wrapping custom implementations of `PluginInner` in a normal `PluginInstance` to use your
-/// custom plugin type the same way you use the others.
+/// custom plugin type the same way you use the others. Uses the default `Limits`.
 pub fn create_plugin_from_custom(mut inner: Box<dyn PluginInner>, descriptor: PluginDescriptor) -> Result<PluginInstance, Error> {
     let plugin_issued_events: HeapRb<PluginIssuedEvent> = HeapRb::new(512);
     let (plugin_issued_events_producer, plugin_issued_events_consumer) =
         plugin_issued_events.split();
 
-    let io_configuration = inner.get_io_configuration();
-
     inner.update_events_producer(plugin_issued_events_producer);
 
-    Ok(PluginInstance {
-        latency: AtomicUsize::new(descriptor.initial_latency),
-        window: Box::new(()),
-        descriptor,
+    Ok(PluginInstance::new(
         inner,
-        plugin_issued_events: plugin_issued_events_consumer,
-        sample_rate: 0,
-        block_size: 0,
-        last_seen_block_size: AtomicUsize::new(0),
-        last_seen_sample_rate: AtomicUsize::new(0),
-        showing_editor: false,
-        io_configuration,
-        resumed: false,
-    })
+        descriptor,
+        plugin_issued_events_consumer,
+        Limits::default(),
+    ))
 }
 
+/// A loaded plugin. Call `split` to get the `MainHandle` which stays on the main (UI) thread and
+/// the `AudioHandle` which is sent to the audio thread. Calling a function from the wrong thread is
+/// then a compile error.
+///
+/// The plugin is unloaded once both handles have been dropped. A dropped `AudioHandle` hands the
+/// plugin back to the `MainHandle` so it's always unloaded on the main thread. Dropping the
+/// `MainHandle` first leaves nothing to unload it on, so the plugin is leaked instead (and debug
+/// builds panic).
 pub struct PluginInstance {
+    // Dropped in this order.
+    pub audio: AudioHandle,
+    pub main: MainHandle,
+}
+
+impl PluginInstance {
+    fn new(
+        inner: Box<dyn PluginInner>,
+        descriptor: PluginDescriptor,
+        plugin_issued_events: HeapCons<PluginIssuedEvent>,
+        limits: Limits,
+    ) -> Self {
+        let io_configuration = inner.get_io_configuration();
+
+        // Replaced configurations are sent back to be dropped on the main thread.
+        let retired_io_configurations: HeapRb<IOConfigutaion> = HeapRb::new(8);
+        let (retired_io_configuration_producer, retired_io_configuration_consumer) =
+            retired_io_configurations.split();
+
+        let queued_events: HeapRb<HostIssuedEvent> = HeapRb::new(512);
+        let (queued_events_producer, queued_events_consumer) = queued_events.split();
+
+        // Payloads of queued SysEx events, in the same order as the events.
+        let queued_sysex: HeapRb<u8> = HeapRb::new(limits.max_sysex_bytes);
+        let (queued_sysex_producer, queued_sysex_consumer) = queued_sysex.split();
+
+        let audio_thread_events: HeapRb<PluginIssuedEvent> = HeapRb::new(32);
+        let (audio_thread_events_producer, audio_thread_events_consumer) =
+            audio_thread_events.split();
+
+        // The `AudioHandle` sends its reference back when it's dropped, see `PluginInstance`.
+        let returned_core: HeapRb<Arc<Core>> = HeapRb::new(1);
+        let (returned_core_producer, returned_core_consumer) = returned_core.split();
+
+        let core = Arc::new(Core {
+            inner,
+            io_configuration_update: Mutex::new(None),
+            latency: AtomicUsize::new(descriptor.initial_latency),
+            last_seen_sample_rate: AtomicUsize::new(0),
+            last_seen_block_size: AtomicUsize::new(0),
+            f64_processing: AtomicBool::new(false),
+            processing: AtomicBool::new(false),
+        });
+
+        let audio = AudioHandle {
+            core: ManuallyDrop::new(core.clone()),
+            returned_core: returned_core_producer,
+            format: descriptor.format.clone(),
+            io_configuration: io_configuration.clone(),
+            retired_io_configurations: retired_io_configuration_producer,
+            queued_events: queued_events_consumer,
+            queued_sysex: queued_sysex_consumer,
+            events: Vec::with_capacity(limits.max_events_per_block),
+            mpe: None,
+            mpe_events: Vec::with_capacity(limits.max_events_per_block),
+            sysex_pool: Vec::with_capacity(limits.max_sysex_bytes),
+            output_events: OutputEvents::new(&limits),
+            audio_thread_events: audio_thread_events_producer,
+            limits,
+            io_mismatch: false,
+            limits_exceeded: false,
+            sample_rate: 0,
+            block_size: 0,
+            resumed: false,
+        };
+
+        let mut main = MainHandle {
+            core,
+            window: Box::new(()),
+            descriptor,
+            plugin_issued_events,
+            retired_io_configurations: retired_io_configuration_consumer,
+            queued_events: queued_events_producer,
+            queued_sysex: queued_sysex_producer,
+            audio_thread_events: audio_thread_events_consumer,
+            returned_core: returned_core_consumer,
+            sample_rate: 0,
+            block_size: 0,
+            showing_editor: false,
+            parameters: Vec::new(),
+            parameter_indices: HashMap::new(),
+            _not_send: PhantomData,
+        };
+
+        main.refresh_parameters();
+
+        PluginInstance { audio, main }
+    }
+
+    pub fn split(self) -> (MainHandle, AudioHandle) {
+        (self.main, self.audio)
+    }
+
+    /// See `AudioHandle::set_mpe`.
+    pub fn set_mpe(&mut self, mpe: Option<MpeConfig>) {
+        self.audio.set_mpe(mpe);
+    }
+}
+
+/// State shared by the `MainHandle` and `AudioHandle` of a plugin.
+struct Core {
+    inner: Box<dyn PluginInner>,
+    /// The latest IO configuration from the main thread that the `AudioHandle` hasn't picked up
+    /// yet. Only the newest one matters so this is a slot rather than a queue. The audio thread
+    /// only ever `try_lock`s it.
+    io_configuration_update: Mutex<Option<IOConfigutaion>>,
+    latency: AtomicUsize,
+    // NOTE: The sample rate and block size is given in the process data is because
+    //       I originally didn't realise that in both VST3 and CLAP you set those in the
+    //       UI thread. So now there's this hacky atomic thing and maybe it should be resdesigned.
+    last_seen_sample_rate: AtomicUsize,
+    last_seen_block_size: AtomicUsize,
+    /// Set by `MainHandle::set_sample_precision`.
+    f64_processing: AtomicBool,
+    /// Held by the `AudioHandle` for the duration of each `process` call and by the
+    /// `MainHandle` while it reconfigures the plugin in ways that can't overlap with processing.
+    /// Neither side waits for it, whoever finds it taken gives up.
+    processing: AtomicBool,
+}
+
+impl Core {
+    fn try_claim(&self) -> bool {
+        self.processing
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn release(&self) {
+        self.processing.store(false, Ordering::Release);
+    }
+}
+
+// SAFETY: All of the plugin formats allow the main thread and audio thread to call into the plugin
+//         at the same time as long as each function is called from the thread the format
+//         specifies. `MainHandle` is `!Send` and only exposes main thread functions and
+//         `AudioHandle` only exposes audio thread functions. `PluginInner` only takes `&self`
+//         so implementations keep any state used by both threads behind atomics or locks.
+unsafe impl Send for Core {}
+unsafe impl Sync for Core {}
+
+/// Main (UI) thread side of a plugin. Handles the editor, state, parameters and configuration.
+/// This is `!Send` so it can't leave the thread it was loaded on.
+pub struct MainHandle {
     pub descriptor: PluginDescriptor,
     /// `Box` to store a window object for convenience. This isn't used by this
     /// crate at all you can use this however you want. Whatever you put in here
     /// will be dropped when the editor is closed.
     pub window: Box<dyn Any>,
-    pub(crate) inner: Box<dyn PluginInner>,
+    core: Arc<Core>,
     plugin_issued_events: HeapCons<PluginIssuedEvent>,
+    retired_io_configurations: HeapCons<IOConfigutaion>,
+    queued_events: HeapProd<HostIssuedEvent>,
+    queued_sysex: HeapProd<u8>,
+    /// Events raised by the `AudioHandle` itself rather than the plugin.
+    audio_thread_events: HeapCons<PluginIssuedEvent>,
+    /// The `AudioHandle`'s reference to `core` once it's dropped. Declared after `core` so that
+    /// if it's the last one it's dropped here, on the main thread.
+    returned_core: HeapCons<Arc<Core>>,
     sample_rate: SampleRate,
     block_size: BlockSize,
-    last_seen_sample_rate: AtomicUsize,
-    last_seen_block_size: AtomicUsize,
     showing_editor: bool,
-    latency: AtomicUsize,
-    io_configuration: IOConfigutaion,
-    resumed: bool,
+    /// Every parameter by index, see `parameters`.
+    parameters: Vec<Parameter>,
+    /// Index of each parameter by ID. Built at load and rebuilt when the plugin rescans its
+    /// parameters.
+    parameter_indices: HashMap<ParamId, usize>,
+    _not_send: PhantomData<*const ()>,
 }
 
-unsafe impl Send for PluginInstance {}
-unsafe impl Sync for PluginInstance {}
-
-impl PluginInstance {
-    /// {Audio thread}
-    pub fn process(
-        &mut self,
-        inputs: &Vec<AudioBus<f32>>,
-        outputs: &mut Vec<AudioBus<f32>>,
-        mut events: Vec<HostIssuedEvent>,
-        process_details: &ProcessDetails,
-    ) {
-        if let Err(e) = self.io_configuration.matches(inputs, outputs) {
-            panic!(
-                "Inputs and outputs do not match the plugin's IO configuration:\n{}",
-                e
-            );
-        }
-
-        events.sort_by_key(|e| e.block_time);
-
-        // FIXME: The abstraction has leaked....
-        //        VST2 wants this stuff in the audio thread other formats do not.
-        //        Maybe just make the libary consumer give these in both threads.
-        if self.descriptor.format == crate::discovery::Format::Vst2 {
-            if self.sample_rate != process_details.sample_rate {
-                self.sample_rate = process_details.sample_rate;
-                self.inner.change_sample_rate(process_details.sample_rate);
-            }
-            if self.block_size != process_details.block_size {
-                self.block_size = process_details.block_size;
-                self.inner.change_block_size(process_details.block_size);
-            }
-        } else {
-            // NOTE: The sample rate and block size is given in the process data is because
-            //       I originally didn't realise that in both VST3 and CLAP you set those in the
-            //       UI thread. So now there's this hacky atomic thing and maybe it should be resdesigned.
-            self.last_seen_block_size
-                .store(process_details.block_size, Ordering::Relaxed);
-            self.last_seen_sample_rate
-                .store(process_details.sample_rate, Ordering::Relaxed);
-        }
-
-        self.resume();
-
-        self.inner.process(inputs, outputs, events, process_details);
+impl MainHandle {
+    fn inner(&self) -> &dyn PluginInner {
+        &*self.core.inner
     }
 
-    /// {UI Thread} Must be called routinely by the UI thread. Consume `PluginIssuedEvent`s
-    /// queued by the plugin. Informs the host of parameter changes in the editor, latency
-    /// changes, etc.
+    /// Must be called routinely. Consume `PluginIssuedEvent`s queued by the plugin. Informs the
+    /// host of parameter changes in the editor, latency changes, etc.
     pub fn get_events(&mut self) -> Vec<PluginIssuedEvent> {
-        self.inner.editor_updates();
+        self.inner().editor_updates();
 
-        // FIXME: see above
-        if self.descriptor.format != crate::discovery::Format::Vst2 {
-            self.fix_configuration();
-        }
+        self.retired_io_configurations.clear();
+        self.returned_core.clear();
 
         let mut events = Vec::new();
-        while let Some(event) = self.plugin_issued_events.try_pop() {
+
+        // FIXME: see `AudioHandle::process`
+        if self.descriptor.format != Format::Vst2 && self.fix_configuration().is_err() {
+            events.push(PluginIssuedEvent::ActivationFailed);
+        }
+
+        while let Some(mut event) = self.plugin_issued_events.try_pop() {
             match event {
+                PluginIssuedEvent::Parameter(ref mut update) => {
+                    update.parameter_index = self.parameter_index_or_unknown(update.parameter_id);
+
+                    if let Some(index) = self.parameter_index(update.parameter_id) {
+                        self.set_cached_value(index, update.current_value);
+                    }
+                }
+                PluginIssuedEvent::ParametersChanged { list: true, .. } => {
+                    self.refresh_parameters();
+                }
+                PluginIssuedEvent::ParametersChanged { info: true, .. } => {
+                    self.refresh_parameter_info();
+                }
+                PluginIssuedEvent::ParametersChanged { values: true, .. } => {
+                    self.refresh_parameter_values();
+                }
                 PluginIssuedEvent::IOChanged => {
-                    self.io_configuration = self.inner.get_io_configuration();
+                    self.send_io_configuration();
 
-                    let latency = self.inner.get_latency();
+                    let latency = self.inner().get_latency();
 
-                    self.latency
-                        .store(latency, std::sync::atomic::Ordering::Relaxed);
+                    self.core.latency.store(latency, Ordering::Relaxed);
 
                     events.push(PluginIssuedEvent::ChangeLatency(latency));
                 }
@@ -181,75 +300,238 @@ impl PluginInstance {
             events.push(event);
         }
 
+        while let Some(event) = self.audio_thread_events.try_pop() {
+            if let PluginIssuedEvent::IOMismatch = event {
+                // Make sure the `AudioHandle` has the latest configuration in case the mismatch
+                // is on its side.
+                self.send_io_configuration();
+            }
+
+            events.push(event);
+        }
+
         events
     }
 
-    /// {Any thread}
     pub fn get_latency(&self) -> usize {
-        self.latency.load(std::sync::atomic::Ordering::Relaxed)
+        self.core.latency.load(Ordering::Relaxed)
+    }
+
+    /// Queues an event to be sent to the plugin in the next `AudioHandle::process` call. For
+    /// things like parameter changes from a generic editor or MIDI from an on-screen keyboard.
+    /// `block_time` is relative to the start of the next block and is clamped to the block size.
+    /// SysEx payloads are copied so they only need to live until this returns.
+    pub fn queue_event(&mut self, mut event: HostIssuedEvent) -> Result<(), Error> {
+        if self.queued_events.is_full() {
+            return Err(Error::CapacityExceeded);
+        }
+
+        if let HostIssuedEventType::Parameter(ref mut update) = event.event_type {
+            update.parameter_index = self.parameter_index_or_unknown(update.parameter_id);
+
+            // The plugin doesn't report values set by the host so update the cache here.
+            if let Some(index) = self.parameter_index(update.parameter_id) {
+                let formatted_value = self
+                    .inner()
+                    .format_parameter_value(update.parameter_id, update.current_value);
+
+                let parameter = &mut self.parameters[index];
+                parameter.value = update.current_value;
+                if let Ok(formatted_value) = formatted_value {
+                    parameter.formatted_value =
+                        HeaplessString::from_str(&formatted_value).unwrap_or_default();
+                }
+            }
+        }
+
+        if let HostIssuedEventType::SysEx(ref sysex) = event.event_type {
+            if self.queued_sysex.vacant_len() < sysex.len() {
+                return Err(Error::CapacityExceeded);
+            }
+
+            // SAFETY: The caller promised the payload outlives this call.
+            self.queued_sysex.push_slice(unsafe { sysex.as_slice() });
+        }
+
+        let _ = self.queued_events.try_push(event);
+
+        Ok(())
     }
 
-    /// {UI thread}
     pub fn get_io_configuration(&mut self) -> IOConfigutaion {
-        let io = self.inner.get_io_configuration();
-        self.io_configuration = io.clone();
+        self.send_io_configuration()
+    }
+
+    /// Gets the current IO configuration from the plugin and passes it on to the `AudioHandle`.
+    fn send_io_configuration(&mut self) -> IOConfigutaion {
+        let io = self.inner().get_io_configuration();
+
+        // Makes room for the configuration the `AudioHandle` replaces with this one.
+        self.retired_io_configurations.clear();
+
+        // Replaces a configuration the `AudioHandle` hasn't picked up yet, dropping it here.
+        *self.core.io_configuration_update.lock().unwrap() = Some(io.clone());
+
         io
     }
 
-    // Note: These two functions are quite specific to VST2 and
-    //       should probably be removed from here.
+    pub fn get_descriptor(&self) -> PluginDescriptor {
+        self.descriptor.clone()
+    }
 
-    pub fn resume(&mut self) {
-        if self.resumed {
-            return;
+    /// Whether the plugin can process 64-bit audio with `AudioHandle::process_f64`.
+    pub fn supports_f64(&mut self) -> bool {
+        self.inner().supports_f64()
+    }
+
+    /// Chooses between `AudioHandle::process` and `AudioHandle::process_f64`. Defaults to
+    /// `SamplePrecision::F32`. Some formats need to reconfigure the plugin for this so call it
+    /// before starting processing, not while the audio thread is running. Returns
+    /// `Error::ProcessingActive` if a `process` call is running at the same time.
+    pub fn set_sample_precision(&mut self, precision: SamplePrecision) -> Result<(), Error> {
+        if precision == SamplePrecision::F64 && !self.supports_f64() {
+            return Err(Error::ExtensionMissing("64-bit processing"));
         }
 
-        self.inner.resume();
-        self.resumed = true;
+        if !self.core.try_claim() {
+            return Err(Error::ProcessingActive);
+        }
+
+        let result = self.inner().set_sample_precision(precision);
+        if result.is_ok() {
+            self.core
+                .f64_processing
+                .store(precision == SamplePrecision::F64, Ordering::Relaxed);
+        }
+
+        self.core.release();
+
+        result
     }
 
-    pub fn suspend(&mut self) {
-        if !self.resumed {
-            return;
+    pub fn get_sample_precision(&self) -> SamplePrecision {
+        if self.core.f64_processing.load(Ordering::Relaxed) {
+            SamplePrecision::F64
+        } else {
+            SamplePrecision::F32
         }
-        self.inner.suspend();
+    }
 
-        self.resumed = false;
+    pub fn get_preset_data(&mut self) -> Result<Vec<u8>, Error> {
+        self.inner().get_preset_data()
     }
 
-    pub fn get_descriptor(&self) -> PluginDescriptor {
-        self.descriptor.clone()
+    pub fn set_preset_data(&mut self, data: Vec<u8>) -> Result<(), Error> {
+        self.inner().set_preset_data(data)
     }
 
-    pub fn get_preset_data(&mut self) -> Result<Vec<u8>, String> {
-        self.inner.get_preset_data()
+    pub fn get_preset_name(&mut self, id: i32) -> Result<String, Error> {
+        self.inner().get_preset_name(id)
     }
 
-    pub fn set_preset_data(&mut self, data: Vec<u8>) -> Result<(), String> {
-        self.inner.set_preset_data(data)
+    pub fn set_preset(&mut self, id: i32) -> Result<(), Error> {
+        self.inner().set_preset(id)
     }
 
-    pub fn get_preset_name(&mut self, id: i32) -> Result<String, String> {
-        self.inner.get_preset_name(id)
+    /// Reads the parameter from the plugin. `None` if the plugin has no parameter with this ID,
+    /// e.g. automation saved with an older version of the plugin that had a parameter that's
+    /// since been removed.
+    pub fn get_parameter(&self, id: ParamId) -> Option<Parameter> {
+        let index = self.parameter_index(id)?;
+        Some(self.inner().get_parameter(index))
     }
 
-    pub fn set_preset(&mut self, id: i32) -> Result<(), String> {
-        self.inner.set_preset(id)
+    /// Current position of the parameter in the plugin's parameter list.
+    pub fn parameter_index(&self, id: ParamId) -> Option<usize> {
+        self.parameter_indices.get(&id).copied()
     }
 
-    pub fn get_parameter(&self, index: i32) -> Parameter {
-        self.inner.get_parameter(index)
+    fn parameter_index_or_unknown(&self, id: ParamId) -> i32 {
+        self.parameter_index(id).map_or(-1, |index| index as i32)
+    }
+
+    /// Every parameter by index, including hidden ones, as of the last `get_events` or
+    /// `queue_event` call. Unlike `get_parameter` this doesn't call into the plugin. Refreshed
+    /// when the plugin reports a parameter change or `PluginIssuedEvent::ParametersChanged`.
+    pub fn parameters(&self) -> &[Parameter] {
+        &self.parameters
+    }
+
+    /// Cached parameter by ID, see `parameters`.
+    pub fn cached_parameter(&self, id: ParamId) -> Option<&Parameter> {
+        self.parameters.get(self.parameter_index(id)?)
+    }
+
+    /// Reads every parameter again and rebuilds the ID to index table.
+    fn refresh_parameters(&mut self) {
+        let inner = &*self.core.inner;
+
+        self.parameter_indices = (0..inner.get_parameter_count())
+            .map(|index| (inner.get_parameter_id(index), index))
+            .collect();
+
+        self.refresh_parameter_info();
+    }
+
+    /// Reads every parameter again. The list itself hasn't changed.
+    fn refresh_parameter_info(&mut self) {
+        let inner = &*self.core.inner;
+
+        self.parameters = (0..inner.get_parameter_count())
+            .map(|index| inner.get_parameter(index))
+            .collect();
+    }
+
+    /// Only reads the values and their formatted text again.
+    fn refresh_parameter_values(&mut self) {
+        for index in 0..self.parameters.len() {
+            if let Ok(value) = self.inner().get_parameter_value(self.parameters[index].id) {
+                self.set_cached_value(index, value);
+            }
+        }
+    }
+
+    fn set_cached_value(&mut self, index: usize, value: f32) {
+        let parameter = &mut self.parameters[index];
+        parameter.value = value;
+
+        if let Ok(text) = self.core.inner.format_parameter_value(parameter.id, value) {
+            parameter.formatted_value = HeaplessString::from_str(&text).unwrap_or_default();
+        }
+    }
+
+    /// Formats a normalized value the way the plugin displays it, e.g. for automation lane
+    /// tooltips. The value doesn't have to be the parameter's current value. VST2 has no API for
+    /// this so other values than the current one are formatted by the host from the plugin's
+    /// range and unit.
+    pub fn format_parameter_value(&self, id: ParamId, value: f32) -> Result<String, Error> {
+        if !self.parameter_indices.contains_key(&id) {
+            return Err(Error::ParameterNotFound(id));
+        }
+
+        self.inner().format_parameter_value(id, value)
+    }
+
+    /// Parses text typed in by the user, e.g. "-6 dB", into a normalized value. Doesn't change
+    /// the parameter. For VST2 only plain numbers and the current formatted value are understood.
+    pub fn parse_parameter_text(&self, id: ParamId, text: &str) -> Result<f32, Error> {
+        if !self.parameter_indices.contains_key(&id) {
+            return Err(Error::ParameterNotFound(id));
+        }
+
+        self.inner().parse_parameter_text(id, text)
     }
 
     pub fn get_all_parameters(&self) -> Vec<Parameter> {
-        (0..self.inner.get_parameter_count())
-            .map(|i| self.inner.get_parameter(i as i32))
+        let inner = self.inner();
+        (0..inner.get_parameter_count())
+            .map(|i| inner.get_parameter(i))
             .filter(|p| !p.hidden)
             .collect()
     }
 
     pub fn get_parameter_count(&self) -> usize {
-        self.inner.get_parameter_count()
+        self.inner().get_parameter_count()
     }
 
     pub fn show_editor(
@@ -258,10 +540,10 @@ impl PluginInstance {
         window_id_type: WindowIDType,
     ) -> Result<(usize, usize), Error> {
         if self.showing_editor {
-            return err("Editor is already open");
+            return Err(Error::EditorAlreadyOpen);
         }
 
-        let size = self.inner.show_editor(window_id, window_id_type)?;
+        let size = self.inner().show_editor(window_id, window_id_type)?;
 
         self.showing_editor = true;
 
@@ -273,7 +555,7 @@ impl PluginInstance {
             return;
         }
 
-        self.inner.hide_editor();
+        self.inner().hide_editor();
         self.window = Box::new(());
 
         self.showing_editor = false;
@@ -283,63 +565,508 @@ impl PluginInstance {
         self.showing_editor
     }
 
-    fn fix_configuration(&mut self) {
-        let last_sample_rate = self.last_seen_sample_rate.load(Ordering::Relaxed);
-        let last_block_size = self.last_seen_block_size.load(Ordering::Relaxed);
+    /// Passes on the sample rate and block size last seen by the `AudioHandle`. Both are tried
+    /// even if the first fails, the plugin is only reconfigured again once they change.
+    fn fix_configuration(&mut self) -> Result<(), Error> {
+        let last_sample_rate = self.core.last_seen_sample_rate.load(Ordering::Relaxed);
+        let last_block_size = self.core.last_seen_block_size.load(Ordering::Relaxed);
+
+        if self.sample_rate == last_sample_rate && self.block_size == last_block_size {
+            return Ok(());
+        }
+
+        // Reconfiguring deactivates the plugin so it can't overlap with `process`. If the audio
+        // thread is processing right now nothing changes and the next `get_events` tries again.
+        if !self.core.try_claim() {
+            return Ok(());
+        }
+
+        let mut result = Ok(());
 
         if self.sample_rate != last_sample_rate {
             self.sample_rate = last_sample_rate;
-            self.inner.change_sample_rate(last_sample_rate);
+            result = self.inner().change_sample_rate(last_sample_rate);
         }
 
         if self.block_size != last_block_size {
             self.block_size = last_block_size;
-            self.inner.change_block_size(last_block_size);
+            result = result.and(self.inner().change_block_size(last_block_size));
         }
+
+        self.core.release();
+
+        result
     }
 
     pub fn set_track_details(&mut self, details: &Track) {
-        self.inner.set_track_details(details);
+        self.inner().set_track_details(details);
+    }
+}
+
+/// Audio thread side of a plugin. Can be sent to the audio thread but only used from one thread
+/// at a time.
+pub struct AudioHandle {
+    /// Only dropped through `returned_core`, see `PluginInstance`.
+    core: ManuallyDrop<Arc<Core>>,
+    returned_core: HeapProd<Arc<Core>>,
+    format: Format,
+    io_configuration: IOConfigutaion,
+    retired_io_configurations: HeapProd<IOConfigutaion>,
+    queued_events: HeapCons<HostIssuedEvent>,
+    queued_sysex: HeapCons<u8>,
+    /// Events for the current block. Allocated up front so `process` doesn't allocate.
+    events: Vec<HostIssuedEvent>,
+    mpe: Option<MpeConverter>,
+    /// Same as `events`, the MPE conversion writes into this and then they're swapped.
+    mpe_events: Vec<HostIssuedEvent>,
+    /// Payloads of queued SysEx events in `events`. Never grown past its capacity so the events
+    /// can point into it.
+    sysex_pool: Vec<u8>,
+    output_events: OutputEvents,
+    audio_thread_events: HeapProd<PluginIssuedEvent>,
+    limits: Limits,
+    /// Set while the buses given to `process` don't match the IO configuration so that the
+    /// mismatch is only reported once.
+    io_mismatch: bool,
+    /// Same as `io_mismatch` for an IO configuration that's bigger than `limits`.
+    limits_exceeded: bool,
+    sample_rate: SampleRate,
+    block_size: BlockSize,
+    resumed: bool,
+}
+
+impl AudioHandle {
+    fn inner(&self) -> &dyn PluginInner {
+        &*self.core.inner
+    }
+
+    /// Processes a block of audio. If the buses don't match the plugin's IO configuration (e.g.
+    /// the plugin changed its layout and the host hasn't caught up yet) the outputs are silenced,
+    /// the plugin isn't called and `PluginIssuedEvent::IOMismatch` is sent to the main thread.
+    /// Events queued with `MainHandle::queue_event` are kept until the next successful call.
+    ///
+    /// Returns `ProcessStatus::Error` without calling the plugin if the sample precision was set
+    /// to `SamplePrecision::F64` or is being changed by `MainHandle::set_sample_precision`.
+    ///
+    /// This doesn't allocate. `events` are copied into a buffer allocated when the plugin was
+    /// loaded. Anything past `Limits::max_events_per_block` is dropped and reported with
+    /// `PluginIssuedEvent::EventsDropped`.
+    pub fn process(
+        &mut self,
+        inputs: &Vec<AudioBus<f32>>,
+        outputs: &mut Vec<AudioBus<f32>>,
+        events: &[HostIssuedEvent],
+        process_details: &ProcessDetails,
+    ) -> ProcessStatus {
+        no_alloc(|| {
+            if !self.core.try_claim() {
+                silence(outputs);
+                return ProcessStatus::Error;
+            }
+
+            let status = if self.core.f64_processing.load(Ordering::Relaxed) {
+                silence(outputs);
+                ProcessStatus::Error
+            } else {
+                self.output_events.clear();
+
+                match self.prepare(inputs, outputs, events, process_details) {
+                    Ok(()) => self.core.inner.process(
+                        inputs,
+                        outputs,
+                        &self.events,
+                        &mut self.output_events,
+                        process_details,
+                    ),
+                    Err(status) => status,
+                }
+            };
+
+            self.core.release();
+
+            status
+        })
+    }
+
+    /// Same as `process` but with 64-bit buffers. `MainHandle::set_sample_precision` must have
+    /// been called with `SamplePrecision::F64` first, otherwise the outputs are silenced and
+    /// `ProcessStatus::Error` is returned.
+    pub fn process_f64(
+        &mut self,
+        inputs: &Vec<AudioBus<f64>>,
+        outputs: &mut Vec<AudioBus<f64>>,
+        events: &[HostIssuedEvent],
+        process_details: &ProcessDetails,
+    ) -> ProcessStatus {
+        no_alloc(|| {
+            if !self.core.try_claim() {
+                silence(outputs);
+                return ProcessStatus::Error;
+            }
+
+            let status = if !self.core.f64_processing.load(Ordering::Relaxed) {
+                silence(outputs);
+                ProcessStatus::Error
+            } else {
+                self.output_events.clear();
+
+                match self.prepare(inputs, outputs, events, process_details) {
+                    Ok(()) => self.core.inner.process_f64(
+                        inputs,
+                        outputs,
+                        &self.events,
+                        &mut self.output_events,
+                        process_details,
+                    ),
+                    Err(status) => status,
+                }
+            };
+
+            self.core.release();
+
+            status
+        })
+    }
+
+    /// Everything `process` and `process_f64` do before calling the plugin. Fills `self.events`
+    /// with the events to send to the plugin or returns the status to return if the plugin
+    /// shouldn't be called.
+    fn prepare<T: Default + Clone>(
+        &mut self,
+        inputs: &[AudioBus<T>],
+        outputs: &mut [AudioBus<T>],
+        events: &[HostIssuedEvent],
+        process_details: &ProcessDetails,
+    ) -> Result<(), ProcessStatus> {
+        // Left for the next block if the main thread is writing it or hasn't dropped the
+        // configurations replaced before yet.
+        if !self.retired_io_configurations.is_full() {
+            if let Ok(mut update) = self.core.io_configuration_update.try_lock() {
+                if let Some(io_configuration) = update.take() {
+                    let old = std::mem::replace(&mut self.io_configuration, io_configuration);
+                    // Dropping it here would deallocate on the audio thread.
+                    let _ = self.retired_io_configurations.try_push(old);
+                }
+            }
+        }
+
+        if !self.io_configuration.within_limits(&self.limits) {
+            silence(outputs);
+
+            if !self.limits_exceeded {
+                self.limits_exceeded = true;
+                let _ = self
+                    .audio_thread_events
+                    .try_push(PluginIssuedEvent::LimitsExceeded);
+            }
+
+            return Err(ProcessStatus::Silenced);
+        }
+
+        self.limits_exceeded = false;
+
+        if !self.io_configuration.fits(inputs, outputs) {
+            silence(outputs);
+
+            if !self.io_mismatch {
+                self.io_mismatch = true;
+                let _ = self
+                    .audio_thread_events
+                    .try_push(PluginIssuedEvent::IOMismatch);
+            }
+
+            return Err(ProcessStatus::Silenced);
+        }
+
+        self.io_mismatch = false;
+
+        // Never grow past the capacity reserved in `PluginInstance::new`. Queued events that
+        // don't fit stay queued for the next block.
+        self.events.clear();
+        let capacity = self.limits.max_events_per_block;
+        for event in events.iter().take(capacity) {
+            self.events.push(event.clone());
+        }
+
+        if events.len() > capacity {
+            let _ = self
+                .audio_thread_events
+                .try_push(PluginIssuedEvent::EventsDropped(events.len() - capacity));
+        }
+
+        let last_sample = process_details.block_size.saturating_sub(1);
+        self.sysex_pool.clear();
+        while self.events.len() < capacity {
+            let Some(mut event) = self.queued_events.try_pop() else {
+                break;
+            };
+            event.block_time = event.block_time.min(last_sample);
+
+            if let HostIssuedEventType::SysEx(ref mut sysex) = event.event_type {
+                let start = self.sysex_pool.len();
+                if sysex.len() > self.sysex_pool.capacity() - start {
+                    // Only possible if more was queued than fits in one block.
+                    self.queued_sysex.skip(sysex.len());
+                    let _ = self
+                        .audio_thread_events
+                        .try_push(PluginIssuedEvent::EventsDropped(1));
+                    continue;
+                }
+
+                self.sysex_pool.resize(start + sysex.len(), 0);
+                self.queued_sysex.pop_slice(&mut self.sysex_pool[start..]);
+                *sysex = unsafe { SysEx::new(&self.sysex_pool[start..]) };
+            }
+
+            self.events.push(event);
+        }
+
+        sort_events(&mut self.events);
+
+        if let Some(mpe) = &mut self.mpe {
+            let inner = &*self.core.inner;
+            let dropped = mpe.convert(&self.events, &mut self.mpe_events, |bus| {
+                inner.takes_note_expressions(bus)
+            });
+            std::mem::swap(&mut self.events, &mut self.mpe_events);
+
+            if dropped > 0 {
+                let _ = self
+                    .audio_thread_events
+                    .try_push(PluginIssuedEvent::EventsDropped(dropped));
+            }
+        }
+
+        // FIXME: The abstraction has leaked....
+        //        VST2 wants this stuff in the audio thread other formats do not.
+        //        Maybe just make the libary consumer give these in both threads.
+        if self.format == Format::Vst2 {
+            let mut result = Ok(());
+            if self.sample_rate != process_details.sample_rate {
+                self.sample_rate = process_details.sample_rate;
+                result = self.inner().change_sample_rate(process_details.sample_rate);
+            }
+            if self.block_size != process_details.block_size {
+                self.block_size = process_details.block_size;
+                result = result.and(self.inner().change_block_size(process_details.block_size));
+            }
+            if result.is_err() {
+                let _ = self
+                    .audio_thread_events
+                    .try_push(PluginIssuedEvent::ActivationFailed);
+            }
+        } else {
+            self.core
+                .last_seen_block_size
+                .store(process_details.block_size, Ordering::Relaxed);
+            self.core
+                .last_seen_sample_rate
+                .store(process_details.sample_rate, Ordering::Relaxed);
+        }
+
+        self.resume();
+
+        Ok(())
+    }
+
+    pub fn get_latency(&self) -> usize {
+        self.core.latency.load(Ordering::Relaxed)
+    }
+
+    /// Events the plugin produced in the last `process` call.
+    pub fn output_events(&self) -> &OutputEvents {
+        &self.output_events
+    }
+
+    /// Turns MPE MIDI into per-note expressions with stable note ids, see `mpe`. Event buses
+    /// without note expressions (VST2, CLAP ports without the CLAP note dialect) still get the
+    /// MPE MIDI. `None` turns it off. Changing it forgets the sounding notes so do it while no notes are held.
+    pub fn set_mpe(&mut self, mpe: Option<MpeConfig>) {
+        self.mpe = mpe.map(MpeConverter::new);
+    }
+
+    /// The IO configuration as of the last `process` call.
+    pub fn io_configuration(&self) -> &IOConfigutaion {
+        &self.io_configuration
+    }
+
+    // Note: These two functions are quite specific to VST2 and
+    //       should probably be removed from here.
+
+    pub fn resume(&mut self) {
+        if self.resumed {
+            return;
+        }
+
+        self.inner().resume();
+        self.resumed = true;
+    }
+
+    pub fn suspend(&mut self) {
+        if !self.resumed {
+            return;
+        }
+        self.inner().suspend();
+
+        self.resumed = false;
+    }
+}
+
+impl Drop for AudioHandle {
+    fn drop(&mut self) {
+        // SAFETY: `core` isn't used again.
+        let core = unsafe { ManuallyDrop::take(&mut self.core) };
+
+        // The `MainHandle` is gone so this is the last reference. Unloading the plugin here would
+        // deactivate and unload it on the audio thread.
+        if Arc::strong_count(&core) == 1 {
+            std::mem::forget(core);
+            if cfg!(debug_assertions) && !std::thread::panicking() {
+                panic!("The MainHandle was dropped before the AudioHandle, the plugin was leaked");
+            }
+            return;
+        }
+
+        let _ = self.returned_core.try_push(core);
     }
 }
 
+/// Result of `AudioHandle::process`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessStatus {
+    /// The plugin processed the block normally.
+    Ok,
+    /// The buses didn't match the plugin's IO configuration. The outputs were silenced and the
+    /// plugin wasn't called.
+    Silenced,
+    /// The plugin reported an error while processing. The contents of the outputs are undefined.
+    Error,
+    /// The plugin is producing silence and doesn't need to be processed until it receives input
+    /// or events.
+    Sleeping,
+}
+
+/// Sample type used when processing. See `MainHandle::set_sample_precision`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SamplePrecision {
+    #[default]
+    F32,
+    F64,
+}
+
+/// A plugin format's implementation. `MainHandle` and `AudioHandle` call into it from their own
+/// threads at the same time so everything takes `&self`. State that's written by one thread and
+/// read by the other has to be behind atomics or locks and the audio thread should never block
+/// on a lock the main thread holds.
 pub trait PluginInner {
     fn process(
-        &mut self,
+        &self,
         inputs: &[AudioBus<f32>],
         outputs: &mut [AudioBus<f32>],
-        events: Vec<HostIssuedEvent>,
+        events: &[HostIssuedEvent],
+        output_events: &mut OutputEvents,
         process_details: &ProcessDetails,
-    );
+    ) -> ProcessStatus;
+
+    /// Only called after `set_sample_precision(SamplePrecision::F64)` succeeded.
+    fn process_f64(
+        &self,
+        _inputs: &[AudioBus<f64>],
+        _outputs: &mut [AudioBus<f64>],
+        _events: &[HostIssuedEvent],
+        _output_events: &mut OutputEvents,
+        _process_details: &ProcessDetails,
+    ) -> ProcessStatus {
+        ProcessStatus::Error
+    }
 
-    fn set_preset_data(&mut self, data: Vec<u8>) -> Result<(), String>;
-    fn get_preset_data(&mut self) -> Result<Vec<u8>, String>;
-    fn get_preset_name(&mut self, id: i32) -> Result<String, String>;
-    fn set_preset(&mut self, id: i32) -> Result<(), String>;
+    fn supports_f64(&self) -> bool {
+        false
+    }
+
+    /// Whether MPE MIDI on the event bus should be turned into note expressions. Buses that
+    /// can't take them get the MPE MIDI as is. Called on the audio thread.
+    fn takes_note_expressions(&self, _bus_index: usize) -> bool {
+        true
+    }
+
+    fn set_sample_precision(&self, _precision: SamplePrecision) -> Result<(), Error> {
+        Ok(())
+    }
 
-    fn get_parameter(&self, index: i32) -> Parameter;
+    fn set_preset_data(&self, data: Vec<u8>) -> Result<(), Error>;
+    fn get_preset_data(&self) -> Result<Vec<u8>, Error>;
+    fn get_preset_name(&self, id: i32) -> Result<String, Error>;
+    fn set_preset(&self, id: i32) -> Result<(), Error>;
+
+    fn get_parameter(&self, index: usize) -> Parameter;
+
+    /// Should be cheaper than `get_parameter`, used to build the ID to index table.
+    fn get_parameter_id(&self, index: usize) -> ParamId;
+
+    /// The current normalized value. Should be cheaper than `get_parameter`.
+    fn get_parameter_value(&self, id: ParamId) -> Result<f32, Error>;
+
+    /// `value` is normalized.
+    fn format_parameter_value(&self, id: ParamId, value: f32) -> Result<String, Error>;
+
+    /// Returns the normalized value.
+    fn parse_parameter_text(&self, id: ParamId, text: &str) -> Result<f32, Error>;
 
     fn show_editor(
-        &mut self,
+        &self,
         window_id: *mut std::ffi::c_void,
         window_id_type: WindowIDType,
     ) -> Result<(usize, usize), Error>;
-    fn hide_editor(&mut self);
+    fn hide_editor(&self);
 
-    fn change_sample_rate(&mut self, _rate: SampleRate);
-    fn change_block_size(&mut self, _size: BlockSize) {}
-    fn suspend(&mut self);
-    fn resume(&mut self);
+    /// Returns `Error::ActivationFailed` if the plugin refused the new configuration.
+    fn change_sample_rate(&self, _rate: SampleRate) -> Result<(), Error>;
+    fn change_block_size(&self, _size: BlockSize) -> Result<(), Error> {
+        Ok(())
+    }
+    fn suspend(&self);
+    fn resume(&self);
 
-    fn get_io_configuration(&mut self) -> IOConfigutaion;
+    fn get_io_configuration(&self) -> IOConfigutaion;
 
-    fn get_latency(&mut self) -> Samples;
+    fn get_latency(&self) -> Samples;
 
-    fn editor_updates(&mut self) {}
+    fn editor_updates(&self) {}
 
     fn get_parameter_count(&self) -> usize;
 
-    fn set_track_details(&mut self, _details: &Track) {}
+    fn set_track_details(&self, _details: &Track) {}
 
     fn update_events_producer(&mut self, _producer: ringbuf::HeapProd<PluginIssuedEvent>) {}
 }
+
+fn silence<T: Default + Clone>(buses: &mut [AudioBus<T>]) {
+    for bus in buses.iter_mut() {
+        for channel in bus.data.iter_mut() {
+            channel.fill(T::default());
+        }
+    }
+}
+
+/// Stable insertion sort by `block_time`. Used instead of `sort_by_key` which allocates. Events
+/// are usually already in order so this is close to linear.
+fn sort_events(events: &mut [HostIssuedEvent]) {
+    for i in 1..events.len() {
+        let mut j = i;
+        while j > 0 && events[j - 1].block_time > events[j].block_time {
+            events.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+}
+
+/// Runs `f` with allocation checking if the `assert_no_alloc` feature is enabled.
+#[inline]
+fn no_alloc<T>(f: impl FnOnce() -> T) -> T {
+    #[cfg(feature = "assert_no_alloc")]
+    return crate::alloc_check::forbid(f);
+
+    #[cfg(not(feature = "assert_no_alloc"))]
+    f()
+}
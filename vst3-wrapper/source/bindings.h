@@ -4,13 +4,30 @@
 #include <ostream>
 #include <new>
 
+/// Set by the C++ side in `load_plugin`.
+enum class LoadStatus {
+  Ok,
+  ModuleLoadFailed,
+  ClassNotFound,
+  InitFailed,
+};
+
+/// Values are in the same units for every format and converted as needed.
 enum class NoteExpressionType {
+  /// Linear gain from 0 to 4, 1 is unchanged.
   Volume,
+  /// 0 is left, 0.5 is center and 1 is right.
   Pan,
+  /// Semitones from -120 to 120.
   Tuning,
+  /// 0 to 1.
   Vibrato,
+  /// 0 to 1.
   Expression,
+  /// 0 to 1. This is the MPE timbre dimension (CC 74).
   Brightness,
+  /// 0 to 1. Sent to VST3 plugins as poly pressure so `key` must be set.
+  Pressure,
 };
 
 enum class PlayingState : uint8_t {
@@ -31,6 +48,9 @@ enum class WindowIDType : uint8_t {
   Other,
 };
 
+/// Input and output configuration for the plugin.
+struct IOConfigutaion;
+
 template<typename T>
 union MaybeUninit {
   T value;
@@ -63,17 +83,21 @@ struct FFIPluginDescriptor {
   const char *version;
   const char *id;
   int initial_latency;
-};
-
-struct AudioBusDescriptor {
-  uintptr_t channels;
-};
-
-/// Input and output configuration for the plugin.
-struct IOConfigutaion {
-  HeaplessVec<AudioBusDescriptor, 16> audio_inputs;
-  HeaplessVec<AudioBusDescriptor, 16> audio_outputs;
-  int32_t event_inputs_count;
+  /// `|` separated, as in `ClassInfo::subCategoriesString`.
+  const char *subcategories;
+  const char *sdk_version;
+  /// From the module's factory info.
+  const char *url;
+  const char *email;
+  /// Whether `has_editor` and the bus counts were filled in by creating the plugin.
+  bool probed;
+  bool has_editor;
+  int32_t audio_inputs;
+  int32_t audio_outputs;
+  int32_t main_input_channels;
+  int32_t main_output_channels;
+  int32_t event_inputs;
+  int32_t event_outputs;
 };
 
 using SampleRate = uintptr_t;
@@ -108,9 +132,17 @@ struct MidiEvent {
   int32_t note_id;
 };
 
+/// Stable identifier of a parameter: the CLAP `clap_id` or VST3 `ParamID`. These stay the same
+/// between plugin versions (unlike indexes) so automation should be saved against them. VST2 has
+/// no parameter IDs so its IDs are the parameter indexes.
+using ParamId = uint32_t;
+
 struct ParameterUpdate {
-  int32_t parameter_id;
+  ParamId parameter_id;
+  /// Filled in by `MainHandle` from `parameter_id`, -1 if unknown. Not required to be set
+  /// when sending events to the plugin.
   int32_t parameter_index;
+  /// Normalized value in [0.0, 1.0].
   float current_value;
   /// Value at start of edit. For example, the value before the user started dragging a knob
   /// in the plugin editor. Not required to be set when sending events to the plugin; just
@@ -120,11 +152,61 @@ struct ParameterUpdate {
   bool end_edit;
 };
 
+/// Transport state from `ProcessDetails` that can change mid-block.
+struct Transport {
+  Tempo tempo;
+  /// Position at the event's `block_time`.
+  PpqTime player_time;
+  uintptr_t time_signature_numerator;
+  uintptr_t time_signature_denominator;
+  bool cycle_enabled;
+  PpqTime cycle_start;
+  PpqTime cycle_end;
+  PlayingState playing_state;
+  PpqTime bar_start_pos;
+};
+
+/// A SysEx message including the leading `0xF0` and trailing `0xF7`. Only points at the bytes so
+/// events stay cheap to clone and never allocate.
+struct SysEx {
+  const uint8_t *data;
+  uintptr_t len;
+};
+
+/// A Universal MIDI Packet. Only MIDI 2.0 channel voice messages (message type 4) are translated
+/// for VST3 and VST2, other packets only reach CLAP plugins.
+///
+/// Note velocities are 16-bit and controller, pressure and pitch bend values are 32-bit.
+struct Ump {
+  uint32_t data[4];
+};
+constexpr static const uint8_t Ump_MIDI2_CHANNEL_VOICE = 4;
+constexpr static const uint8_t Ump_REGISTERED_PER_NOTE_CONTROLLER = 0;
+constexpr static const uint8_t Ump_ASSIGNABLE_PER_NOTE_CONTROLLER = 1;
+constexpr static const uint8_t Ump_PER_NOTE_PITCH_BEND = 6;
+constexpr static const uint8_t Ump_NOTE_OFF = 8;
+constexpr static const uint8_t Ump_NOTE_ON = 9;
+constexpr static const uint8_t Ump_POLY_PRESSURE = 10;
+constexpr static const uint8_t Ump_CONTROL_CHANGE = 11;
+constexpr static const uint8_t Ump_PROGRAM_CHANGE = 12;
+constexpr static const uint8_t Ump_CHANNEL_PRESSURE = 13;
+constexpr static const uint8_t Ump_PITCH_BEND = 14;
+
 struct HostIssuedEventType {
   enum class Tag {
     Midi,
     Parameter,
+    /// Changes one note. Plugins without note ids match on `channel` and `key` instead, which
+    /// are -1 to match any.
     NoteExpression,
+    /// The transport changes at `block_time`. Use this for tempo ramps, loop wrap-arounds and
+    /// time signature changes inside a block. The start of the block uses `ProcessDetails`.
+    Transport,
+    /// Sent on the MIDI port `bus_index`.
+    SysEx,
+    /// A MIDI 2.0 Universal MIDI Packet. Sent as is to CLAP plugins, translated to notes, note
+    /// expressions and `IMidiMapping` parameters for VST3 and down-converted to MIDI 1.0 for VST2.
+    Midi2,
   };
 
   struct Midi_Body {
@@ -137,20 +219,37 @@ struct HostIssuedEventType {
 
   struct NoteExpression_Body {
     int32_t note_id;
+    int16_t channel;
+    int16_t key;
     NoteExpressionType expression_type;
     double value;
   };
 
+  struct Transport_Body {
+    Transport _0;
+  };
+
+  struct SysEx_Body {
+    SysEx _0;
+  };
+
+  struct Midi2_Body {
+    Ump _0;
+  };
+
   Tag tag;
   union {
     Midi_Body midi;
     Parameter_Body parameter;
     NoteExpression_Body note_expression;
+    Transport_Body transport;
+    SysEx_Body sys_ex;
+    Midi2_Body midi2;
   };
 };
 
-/// Events sent to the plugin from the host. Can be passed into the `process` function or queued
-/// for the next process call with `queue_event`.
+/// Events sent to the plugin from the host. Can be passed into `AudioHandle::process` or queued
+/// for the next process call with `MainHandle::queue_event`.
 struct HostIssuedEvent {
   HostIssuedEventType event_type;
   /// Time in samples from start of next block.
@@ -161,20 +260,24 @@ struct HostIssuedEvent {
   bool is_live;
 };
 
-struct Parameter {
-  int32_t id;
+/// `Parameter` as returned by the C++ side. The strings are allocated with `alloc_string`.
+struct FFIParameter {
+  ParamId id;
   HeaplessString<256> name;
   int32_t index;
-  /// Normalized parameter value in [0.0, 1.0].
   float value;
-  /// Value as string formatted by the plugin. E.g. "0 dB", "50 Hz", etc.
   HeaplessString<256> formatted_value;
   bool hidden;
   bool can_automate;
   bool is_wrap_around;
   bool read_only;
-  /// Default normalized value if supported by the format. Not supported by VST2.
   float default_value;
+  double min_value;
+  double max_value;
+  int32_t step_count;
+  const char *unit;
+  const char *group;
+  bool is_bypass;
 };
 
 struct Colour {
@@ -197,13 +300,29 @@ struct PluginIssuedEvent {
     /// Plugin changed its editor window size. 0 is width, 1 is height.
     ResizeWindow,
     Parameter,
-    UpdateDisplay,
+    /// The plugin's parameters changed without a `Parameter` event for each of them, e.g. after
+    /// loading a preset. `MainHandle::parameters` has already been refreshed when this is
+    /// returned by `get_events`.
+    ParametersChanged,
     IOChanged,
+    /// The buses given to `AudioHandle::process` didn't match the plugin's IO configuration so
+    /// its outputs were silenced. Reported once per mismatch. Call
+    /// `MainHandle::get_io_configuration` and rebuild the buses.
+    IOMismatch,
+    /// The plugin's IO configuration needs more buses or channels than `Host::limits` allows so
+    /// its outputs were silenced. Reported once. Reload the plugin with bigger limits.
+    LimitsExceeded,
+    /// More events were given to `AudioHandle::process` than `Limits::max_events_per_block`.
+    /// Contains the number of events that were dropped.
+    EventsDropped,
     RequestEditorOpen,
     RequestEditorClose,
     /// Tail length in samples. This is how long the plugin will continue to produce audio after
     /// the last input sample (i.e. reverb tail).
     TailLengthChanged,
+    /// The plugin refused to activate with the sample rate or block size given to
+    /// `AudioHandle::process`. It's tried again when either changes.
+    ActivationFailed,
   };
 
   struct ChangeLatency_Body {
@@ -219,6 +338,19 @@ struct PluginIssuedEvent {
     ParameterUpdate _0;
   };
 
+  struct ParametersChanged_Body {
+    /// Values (and their formatted text) changed.
+    bool values;
+    /// Names, flags, ranges, value names or groups changed.
+    bool info;
+    /// Parameters were added or removed. Indexes may have changed, IDs may no longer exist.
+    bool list;
+  };
+
+  struct EventsDropped_Body {
+    uintptr_t _0;
+  };
+
   struct TailLengthChanged_Body {
     uintptr_t _0;
   };
@@ -228,40 +360,70 @@ struct PluginIssuedEvent {
     ChangeLatency_Body change_latency;
     ResizeWindow_Body resize_window;
     Parameter_Body parameter;
+    ParametersChanged_Body parameters_changed;
+    EventsDropped_Body events_dropped;
     TailLengthChanged_Body tail_length_changed;
   };
 };
 
 extern "C" {
 
+/// For building an `IOConfigutaion` from C++.
+///
+/// # Safety
+/// `io_config` must be the pointer the C++ side was given.
+void push_audio_bus_to_io_config(IOConfigutaion *io_config, bool is_input, uintptr_t channels);
+
+/// For building an `IOConfigutaion` from C++.
+///
+/// # Safety
+/// `io_config` must be the pointer the C++ side was given.
+void set_event_inputs_in_io_config(IOConfigutaion *io_config, int32_t count);
+
 bool push_c_str_to_heapless_string(HeaplessString<256> *heapless_string, const char *c_str);
 
 void ffi_ensure_main_thread(const char *fn_name);
 
 void ffi_ensure_non_main_thread(const char *fn_name);
 
-extern const void *load_plugin(const char *path, const char *id, const void *vst3_instance);
+extern const void *load_plugin(const char *path,
+                               const char *id,
+                               const void *vst3_instance,
+                               LoadStatus *status);
 
-extern Dims show_gui(const void *app, const void *window_id, WindowIDType window_id_type);
+extern bool show_gui(const void *app,
+                     const void *window_id,
+                     WindowIDType window_id_type,
+                     Dims *dims);
 
 extern void hide_gui(const void *app);
 
 extern FFIPluginDescriptor descriptor(const void *app);
 
-extern IOConfigutaion io_config(const void *app);
+extern void io_config(const void *app, IOConfigutaion *config);
 
 extern uintptr_t parameter_count(const void *app);
 
-extern void process(const void *app,
+extern bool process(const void *app,
                     const ProcessDetails *data,
-                    float ***input,
-                    float ***output,
+                    void ***input,
+                    void ***output,
                     HostIssuedEvent *events,
-                    int32_t events_len);
+                    int32_t events_len,
+                    void *output_events,
+                    int32_t output_offset);
+
+extern void set_param_in_edit_controller(const void *app, ParamId id, float value);
+
+extern FFIParameter get_parameter(const void *app, int32_t index, void *value_names);
 
-extern void set_param_in_edit_controller(const void *app, int32_t id, float value);
+extern ParamId get_parameter_id(const void *app, int32_t index);
 
-extern Parameter get_parameter(const void *app, int32_t id);
+extern double get_parameter_value(const void *app, ParamId id);
+
+extern const char *format_parameter_value(const void *app, ParamId id, double value);
+
+extern bool parse_parameter_text(const void *app, ParamId id, const char *text, double *value);
 
 extern const void *get_data(const void *app, int32_t *data_len, const void **stream);
 
@@ -269,17 +431,21 @@ extern const void *get_controller_data(const void *app, int32_t *data_len, const
 
 extern void free_data_stream(const void *stream);
 
-extern void set_data(const void *app, const void *data, int32_t data_len);
+extern bool set_data(const void *app, const void *data, int32_t data_len);
 
-extern void set_controller_data(const void *app, const void *data, int32_t data_len);
+extern bool set_controller_data(const void *app, const void *data, int32_t data_len);
 
 extern void set_processing(const void *app, bool processing);
 
 extern uint32_t get_latency(const void *app);
 
-extern void get_descriptors(const char *path, HeaplessVec<FFIPluginDescriptor, 10> *plugins);
+extern void get_descriptors(const char *path, void *plugins);
+
+extern bool vst3_set_sample_rate(const void *app, int32_t sample_rate);
 
-extern void vst3_set_sample_rate(const void *app, int32_t sample_rate);
+extern bool supports_f64(const void *app);
+
+extern bool vst3_set_sample_precision(const void *app, bool f64);
 
 extern void set_track_details(const void *app, const Track *details);
 
@@ -289,4 +455,38 @@ extern void free_string(const char *str);
 
 void send_event_to_host(const PluginIssuedEvent *event, const void *vst3_instance);
 
+/// Called by `get_descriptors` for each plugin class in the module. `plugins` is the `Vec`
+/// passed to `get_descriptors`.
+void push_ffi_plugin_descriptor(void *plugins, FFIPluginDescriptor descriptor);
+
+/// Called by `process` for each SysEx message the plugin outputs. `output_events` is the pointer
+/// passed to `process`.
+void push_sysex_output(void *output_events,
+                       int32_t block_time,
+                       int32_t bus_index,
+                       const uint8_t *data,
+                       uint32_t len);
+
+/// Called by `process` for each note on or off the plugin outputs.
+void push_note_output(void *output_events,
+                      int32_t block_time,
+                      int32_t bus_index,
+                      bool note_on,
+                      int16_t channel,
+                      int16_t key,
+                      float velocity,
+                      int32_t note_id);
+
+/// Called by `process` for each legacy MIDI CC output event, converted to a MIDI 1.0 message.
+void push_midi_output(void *output_events,
+                      int32_t block_time,
+                      int32_t bus_index,
+                      uint8_t status,
+                      uint8_t data1,
+                      uint8_t data2);
+
+/// Called by `get_parameter` for each value of a list parameter. `value_names` is the `Vec`
+/// passed to `get_parameter`.
+void push_parameter_value_name(void *value_names, const char *name);
+
 }  // extern "C"
@@ -7,6 +7,8 @@
 #include <public.sdk/source/vst/hosting/parameterchanges.h>
 #include <public.sdk/source/vst/hosting/processdata.h>
 #include <pluginterfaces/vst/ivstchannelcontextinfo.h>
+#include <pluginterfaces/vst/ivstmidicontrollers.h>
+#include <pluginterfaces/vst/ivstunits.h>
 #include "public.sdk/source/vst/hosting/hostclasses.h"
 #include "public.sdk/source/vst/hosting/module.h"
 #include "public.sdk/source/vst/hosting/plugprovider.h"
@@ -14,7 +16,7 @@
 #include "bindings.h"
 
 struct ParameterEditState {
-  int id;
+  ParamId id;
   float initial_value;
   float current_value;
   bool finished;
@@ -26,7 +28,8 @@ struct MidiCC {
   int16_t control_number;
 
   inline uint64_t as_key() {
-    return ((uint64_t)bus_index << 32) | ((uint64_t)bus_index << 16) | (uint64_t)channel;
+    return ((uint64_t)bus_index << 32) | ((uint64_t)(uint16_t)channel << 16) |
+           (uint64_t)(uint16_t)control_number;
   }
 };
 
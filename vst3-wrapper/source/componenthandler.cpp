@@ -58,11 +58,28 @@ Steinberg::tresult ComponentHandler::endEdit(Steinberg::Vst::ParamID id) {
 }
 
 Steinberg::tresult ComponentHandler::restartComponent(Steinberg::int32 flags) {
-  // TODO
+  const Steinberg::int32 parameter_flags =
+      Steinberg::Vst::kParamValuesChanged | Steinberg::Vst::kParamTitlesChanged;
+
+  if ((flags & parameter_flags) != 0) {
+    bool titles_changed = (flags & Steinberg::Vst::kParamTitlesChanged) != 0;
+
+    PluginIssuedEvent event = {};
+    event.tag = PluginIssuedEvent::Tag::ParametersChanged;
+    event.parameters_changed.values =
+        (flags & Steinberg::Vst::kParamValuesChanged) != 0;
+    event.parameters_changed.info = titles_changed;
+    // The parameter count can also change with `kParamTitlesChanged`.
+    event.parameters_changed.list = titles_changed;
+    send_event_to_host(&event, rust_side_vst3_instance_object);
+  }
 
-  PluginIssuedEvent event = {};
-  event.tag = PluginIssuedEvent::Tag::IOChanged;
-  send_event_to_host(&event, rust_side_vst3_instance_object);
+  // TODO: Handle the rest of the flags separately
+  if ((flags & ~parameter_flags) != 0) {
+    PluginIssuedEvent event = {};
+    event.tag = PluginIssuedEvent::Tag::IOChanged;
+    send_event_to_host(&event, rust_side_vst3_instance_object);
+  }
 
   return Steinberg::kResultOk;
 }
@@ -77,7 +94,7 @@ ComponentHandler::queryInterface(const Steinberg::TUID /*_iid*/,
 Steinberg::uint32 ComponentHandler::addRef() { return 1000; }
 Steinberg::uint32 ComponentHandler::release() { return 1000; }
 
-void ComponentHandler::send_param_change_event(int32_t id, float value,
+void ComponentHandler::send_param_change_event(ParamId id, float value,
                                                float initial_value,
                                                bool end_edit) {
   PluginIssuedEvent event = {};
@@ -85,14 +102,9 @@ void ComponentHandler::send_param_change_event(int32_t id, float value,
   event.parameter = {};
   event.parameter._0 = {};
 
-  int32_t param_index = -1;
-  auto it = parameter_indicies.find(id);
-  if (it != parameter_indicies.end()) {
-    param_index = (int32_t)it->second;
-  }
-
+  // The index is filled in by `MainHandle`.
   event.parameter._0.parameter_id = id,
-  event.parameter._0.parameter_index = param_index,
+  event.parameter._0.parameter_index = -1,
   event.parameter._0.current_value = value,
   event.parameter._0.end_edit = end_edit,
   event.parameter._0.initial_value = initial_value,
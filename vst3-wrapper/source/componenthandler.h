@@ -3,7 +3,6 @@
 #include "common.h"
 
 #include <mutex>
-#include <unordered_map>
 
 class ComponentHandler : public Steinberg::Vst::IComponentHandler {
 public:
@@ -21,11 +20,8 @@ public:
     rust_side_vst3_instance_object = _rust_side_vst3_instance_object;
 
     param_edits = {};
-    parameter_indicies = {};
   }
 
-  std::unordered_map<Steinberg::Vst::ParamID, int> parameter_indicies;
-
 private:
   const void *rust_side_vst3_instance_object = nullptr;
   std::vector<ParameterEditState> param_edits;
@@ -38,6 +34,6 @@ private:
   Steinberg::uint32 addRef() override;
   Steinberg::uint32 release() override;
 
-  void send_param_change_event(int32_t id, float value, float initial_value,
+  void send_param_change_event(ParamId id, float value, float initial_value,
                                bool end_edit = false);
 };
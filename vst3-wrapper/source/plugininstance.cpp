@@ -8,7 +8,7 @@ using namespace Steinberg::Vst::ChannelContext;
 
 const int MAX_BLOCK_SIZE = 4096 * 2;
 
-bool PluginInstance::init(const std::string &path, const std::string &id) {
+LoadStatus PluginInstance::init(const std::string &path, const std::string &id) {
   _destroy(false);
 
   ++standard_plugin_context_ref_count;
@@ -17,7 +17,7 @@ bool PluginInstance::init(const std::string &path, const std::string &id) {
     PluginContextFactory::instance().setPluginContext(standard_plugin_context);
   }
 
-  process_setup.symbolicSampleSize = 0;
+  process_setup.symbolicSampleSize = kSample32;
   process_setup.sampleRate = 44100;
   process_setup.maxSamplesPerBlock = MAX_BLOCK_SIZE;
   process_setup.processMode = Steinberg::Vst::kRealtime;
@@ -29,7 +29,7 @@ bool PluginInstance::init(const std::string &path, const std::string &id) {
   _module = VST3::Hosting::Module::create(path, error);
   if (!_module) {
     std::cerr << "Failed to load VST3 module: " << error << std::endl;
-    return false;
+    return LoadStatus::ModuleLoadFailed;
   }
 
   VST3::Hosting::PluginFactory factory = _module->getFactory();
@@ -38,12 +38,15 @@ bool PluginInstance::init(const std::string &path, const std::string &id) {
       if (id != classInfo.ID().toString())
         continue;
 
-      return this->load_plugin_from_class(factory, classInfo);
+      if (!this->load_plugin_from_class(factory, classInfo))
+        return LoadStatus::InitFailed;
+
+      return LoadStatus::Ok;
     }
   }
 
   std::cerr << "No plugin with the provided ID." << std::endl;
-  return false;
+  return LoadStatus::ClassNotFound;
 }
 
 bool PluginInstance::load_plugin_from_class(
@@ -55,6 +58,7 @@ bool PluginInstance::load_plugin_from_class(
 
   if (component->initialize(standard_plugin_context) != kResultOk) {
     std::cout << "Failed to initialize component" << std::endl;
+    return false;
   }
 
   audio_processor = FUnknownPtr<IAudioProcessor>(component);
@@ -74,6 +78,11 @@ bool PluginInstance::load_plugin_from_class(
     }
   }
 
+  if (!edit_controller) {
+    std::cout << "Could not get edit controller from VST" << std::endl;
+    return false;
+  }
+
   if (edit_controller->initialize(standard_plugin_context) != kResultOk) {
     std::cout << "Failed to initialize controller" << std::endl;
   }
@@ -110,6 +119,12 @@ bool PluginInstance::load_plugin_from_class(
   vendor = classInfo.vendor();
   version = classInfo.version();
   id = classInfo.ID().toString();
+  subcategories = classInfo.subCategoriesString();
+  sdk_version = classInfo.sdkVersion();
+
+  auto factory_info = factory.info();
+  url = factory_info.url();
+  email = factory_info.email();
 
   // TODO: Set bus arrangement
 
@@ -179,13 +194,41 @@ bool PluginInstance::load_plugin_from_class(
     std::cout << "Failed to setup VST processing" << std::endl;
   }
 
-  get_io_config();
+  // Allocated here rather than in `process` so processing doesn't allocate.
+  process_data.inputParameterChanges = new ParameterChanges(400);
 
-  look_for_cc_mapping({0, 0, 129});
+  load_cc_mappings();
 
   return true;
 }
 
+void PluginInstance::load_cc_mappings() {
+  ffi_ensure_main_thread("[VST3] load_cc_mappings");
+
+  IMidiMapping *midi_map = nullptr;
+  edit_controller->queryInterface(IMidiMapping::iid, (void **)&midi_map);
+  if (midi_map == nullptr)
+    return;
+
+  // Looked up once so MIDI 2.0 controllers can be mapped on the audio thread.
+  for (int16_t channel = 0; channel < 16; channel++) {
+    for (int16_t control_number = 0; control_number < kCountCtrlNumber;
+         control_number++) {
+      MidiCC cc = {0, channel, control_number};
+      ParamID id = -1;
+
+      // [UI-thread & Connected]
+      if (midi_map->getMidiControllerAssignment(0, channel, control_number,
+                                                id) == kResultOk &&
+          id != -1) {
+        midi_cc_mappings[cc.as_key()] = id;
+      }
+    }
+  }
+
+  midi_map->release();
+}
+
 void PluginInstance::look_for_cc_mapping(MidiCC cc) {
   ffi_ensure_main_thread("[VST3] look_for_cc_mapping");
 
@@ -11,11 +11,10 @@ public:
   PluginInstance();
   ~PluginInstance();
 
-  bool init(const std::string &path, const std::string &id);
+  LoadStatus init(const std::string &path, const std::string &id);
   void destroy();
 
-  IOConfigutaion _io_config;
-  IOConfigutaion get_io_config();
+  void get_io_config(IOConfigutaion *config);
 
   Steinberg::Vst::ProcessContext *processContext();
 
@@ -32,6 +31,7 @@ public:
   std::unordered_map<uint64_t, Steinberg::Vst::ParamID> midi_cc_mappings = {};
 
   void look_for_cc_mapping(MidiCC cc);
+  void load_cc_mappings();
 
   void _destroy(bool decrementRefCount);
 
@@ -52,6 +52,10 @@ public:
   ComponentHandler *component_handler = nullptr;
 
   Steinberg::Vst::ProcessSetup process_setup = {};
+  // Whether the component is active and processing. Used to put the plugin
+  // back the way it was after reconfiguring it.
+  bool active = false;
+  bool processing = false;
   Steinberg::Vst::ProcessContext _processContext = {};
 
   Steinberg::IPtr<Steinberg::IPlugView> _view = nullptr;
@@ -62,6 +66,10 @@ public:
   std::string vendor;
   std::string version;
   std::string id;
+  std::string subcategories;
+  std::string sdk_version;
+  std::string url;
+  std::string email;
 
   Steinberg::Vst::IConnectionPoint *iConnectionPointComponent = nullptr;
   Steinberg::Vst::IConnectionPoint *iConnectionPointController = nullptr;
@@ -28,8 +28,63 @@ PluginInstance::PluginInstance() {}
 
 PluginInstance::~PluginInstance() { destroy(); }
 
-void get_descriptors(const char *path,
-                     HeaplessVec<FFIPluginDescriptor, 10> *plugins) {
+// Creates the plugin to fill in what `ClassInfo` doesn't say: whether it has an
+// editor and its buses. `probed` is left false if it can't be created.
+static void probe_class(VST3::Hosting::PluginFactory &factory,
+                        VST3::Hosting::ClassInfo &classInfo,
+                        FUnknown *context, FFIPluginDescriptor &descriptor) {
+  auto component = factory.createInstance<IComponent>(classInfo.ID());
+  if (!component)
+    return;
+
+  if (component->initialize(context) != kResultOk)
+    return;
+
+  descriptor.audio_inputs = component->getBusCount(kAudio, kInput);
+  descriptor.audio_outputs = component->getBusCount(kAudio, kOutput);
+  descriptor.event_inputs = component->getBusCount(kEvent, kInput);
+  descriptor.event_outputs = component->getBusCount(kEvent, kOutput);
+
+  BusInfo info;
+  if (descriptor.audio_inputs > 0 &&
+      component->getBusInfo(kAudio, kInput, 0, info) == kResultOk)
+    descriptor.main_input_channels = info.channelCount;
+  if (descriptor.audio_outputs > 0 &&
+      component->getBusInfo(kAudio, kOutput, 0, info) == kResultOk)
+    descriptor.main_output_channels = info.channelCount;
+
+  // Same lookup as `PluginInstance::load_plugin_from_class`.
+  IEditController *controller = nullptr;
+  bool separate_controller = false;
+  if (component->queryInterface(IEditController::iid, (void **)&controller) !=
+      kResultOk) {
+    TUID controllerCID;
+    if (component->getControllerClassId(controllerCID) == kResultOk) {
+      factory.get()->createInstance(controllerCID, IEditController::iid,
+                                    (void **)&controller);
+      separate_controller = true;
+    }
+  }
+
+  if (controller) {
+    if (!separate_controller || controller->initialize(context) == kResultOk) {
+      IPlugView *view = controller->createView(ViewType::kEditor);
+      descriptor.has_editor = view != nullptr;
+      if (view)
+        view->release();
+
+      if (separate_controller)
+        controller->terminate();
+    }
+
+    controller->release();
+  }
+
+  component->terminate();
+  descriptor.probed = true;
+}
+
+void get_descriptors(const char *path, void *plugins) {
   auto plugin_ctx = NEW HostApplication();
   PluginContextFactory::instance().setPluginContext(plugin_ctx);
 
@@ -41,23 +96,28 @@ void get_descriptors(const char *path,
   }
 
   VST3::Hosting::PluginFactory factory = module_->getFactory();
+  auto factory_info = factory.info();
   for (auto &classInfo : factory.classInfos()) {
     if (classInfo.category() == kVstAudioEffectClass) {
-      if (plugins->count >= 10)
-        break;
-
       std::string name = classInfo.name();
       std::string vendor = classInfo.vendor();
       std::string version = classInfo.version();
       std::string id = classInfo.ID().toString();
 
-      plugins->data[plugins->count].value.name = alloc_string(name.c_str());
-      plugins->data[plugins->count].value.version =
-          alloc_string(version.c_str());
-      plugins->data[plugins->count].value.vendor = alloc_string(vendor.c_str());
-      plugins->data[plugins->count].value.id = alloc_string(id.c_str());
+      FFIPluginDescriptor descriptor = {};
+      descriptor.name = alloc_string(name.c_str());
+      descriptor.version = alloc_string(version.c_str());
+      descriptor.vendor = alloc_string(vendor.c_str());
+      descriptor.id = alloc_string(id.c_str());
+      descriptor.subcategories =
+          alloc_string(classInfo.subCategoriesString().c_str());
+      descriptor.sdk_version = alloc_string(classInfo.sdkVersion().c_str());
+      descriptor.url = alloc_string(factory_info.url().c_str());
+      descriptor.email = alloc_string(factory_info.email().c_str());
+
+      probe_class(factory, classInfo, plugin_ctx, descriptor);
 
-      plugins->count++;
+      push_ffi_plugin_descriptor(plugins, descriptor);
     }
   }
 }
@@ -101,6 +161,7 @@ void set_processing(const void *app, bool processing) {
 
   // [(UI-thread or processing-thread) & Activated]
   vst->audio_processor->setProcessing(processing);
+  vst->processing = processing;
 }
 
 Steinberg::Vst::ProcessContext *PluginInstance::processContext() {
@@ -134,11 +195,7 @@ PluginInstance::parameterChanges(Steinberg::Vst::BusDirection direction,
   }
 }
 
-IOConfigutaion PluginInstance::get_io_config() {
-  IOConfigutaion io_config = {};
-  io_config.audio_inputs = {};
-  io_config.audio_outputs = {};
-
+void PluginInstance::get_io_config(IOConfigutaion *config) {
   auto audio_inputs =
       component->getBusCount(MediaTypes::kAudio, BusDirections::kInput);
   auto audio_outputs =
@@ -149,34 +206,29 @@ IOConfigutaion PluginInstance::get_io_config() {
   for (int i = 0; i < audio_inputs; i++) {
     BusInfo info;
     component->getBusInfo(MediaTypes::kAudio, BusDirections::kInput, i, info);
-    io_config.audio_inputs.count++;
-    io_config.audio_inputs.data[i] = {};
-    io_config.audio_inputs.data[i].value.channels = info.channelCount;
+    push_audio_bus_to_io_config(config, true, info.channelCount);
   }
 
   for (int i = 0; i < audio_outputs; i++) {
     BusInfo info;
     component->getBusInfo(MediaTypes::kAudio, BusDirections::kOutput, i, info);
-    io_config.audio_outputs.count++;
-    io_config.audio_outputs.data[i] = {};
-    io_config.audio_outputs.data[i].value.channels = info.channelCount;
+    push_audio_bus_to_io_config(config, false, info.channelCount);
   }
 
-  io_config.event_inputs_count = event_inputs;
-
-  // vst->_vstPlug->getBusCount(MediaTypes::kEvent, BusDirections::kInput);
-  // vst->_vstPlug->getBusCount(MediaTypes::kEvent, BusDirections::kOutput);
-
-  _io_config = io_config;
-
-  return io_config;
+  set_event_inputs_in_io_config(config, event_inputs);
 }
 
 const void *load_plugin(const char *s, const char *id,
-                        const void *rust_side_vst3_instance_object) {
+                        const void *rust_side_vst3_instance_object,
+                        LoadStatus *status) {
   PluginInstance *vst = new PluginInstance();
   vst->rust_side_vst3_instance_object = rust_side_vst3_instance_object;
-  vst->init(s, id);
+
+  *status = vst->init(s, id);
+  if (*status != LoadStatus::Ok) {
+    delete vst;
+    return nullptr;
+  }
 
   auto aud_in = vst->component->getBusCount(kAudio, kInput);
   for (int i = 0; i < aud_in; i++) {
@@ -193,33 +245,40 @@ const void *load_plugin(const char *s, const char *id,
     vst->component->activateBus(kEvent, kInput, i, true);
   }
 
+  auto evt_out = vst->component->getBusCount(kEvent, kOutput);
+  for (int i = 0; i < evt_out; i++) {
+    vst->component->activateBus(kEvent, kOutput, i, true);
+  }
+
   if (vst->component->setActive(true) != kResultTrue) {
     std::cout << "Failed to activate VST component" << std::endl;
+  } else {
+    vst->active = true;
   }
 
   if (vst->audio_processor->setProcessing(true)) {
     std::cout << "Failed to being processing" << std::endl;
+  } else {
+    vst->processing = true;
   }
 
-  // NOTE: Output event buses are not supported yet so they are not activated
-
   return vst;
 }
 
-Dims show_gui(const void *app, const void *window_id,
-              WindowIDType window_id_type) {
+bool show_gui(const void *app, const void *window_id,
+              WindowIDType window_id_type, Dims *dims) {
   PluginInstance *vst = (PluginInstance *)app;
 
   if (!vst->edit_controller) {
     std::cerr << "VST does not provide an edit controller" << std::endl;
-    return {};
+    return false;
   }
 
   if (!vst->_view) {
     vst->_view = vst->edit_controller->createView(ViewType::kEditor);
     if (!vst->_view) {
       std::cerr << "EditController does not provide its own view" << std::endl;
-      return {};
+      return false;
     }
 
     vst->_view->setFrame(
@@ -245,25 +304,25 @@ Dims show_gui(const void *app, const void *window_id,
 
   if (vst->_view->isPlatformTypeSupported(platform) != Steinberg::kResultTrue) {
     std::cerr << "Editor view does not support this platform" << std::endl;
-    return {};
+    return false;
   }
 
   if (vst->_view->attached((void *)window_id, platform) !=
       Steinberg::kResultOk) {
     std::cerr << "Failed to attach editor view to view" << std::endl;
-    return {};
+    return false;
   }
 
   ViewRect viewRect = {};
   if (vst->_view->getSize(&viewRect) != kResultOk) {
     std::cout << "Failed to get editor view size" << std::endl;
-    return {};
+    return false;
   }
 
-  return {
-      viewRect.getWidth(),
-      viewRect.getHeight(),
-  };
+  dims->width = viewRect.getWidth();
+  dims->height = viewRect.getHeight();
+
+  return true;
 }
 
 void hide_gui(const void *app) {
@@ -282,11 +341,15 @@ FFIPluginDescriptor descriptor(const void *app) {
   desc.version = alloc_string(vst->version.c_str());
   desc.vendor = alloc_string(vst->vendor.c_str());
   desc.id = alloc_string(vst->id.c_str());
+  desc.subcategories = alloc_string(vst->subcategories.c_str());
+  desc.sdk_version = alloc_string(vst->sdk_version.c_str());
+  desc.url = alloc_string(vst->url.c_str());
+  desc.email = alloc_string(vst->email.c_str());
 
   return desc;
 }
 
-void vst3_set_sample_rate(const void *app, int32_t rate) {
+bool vst3_set_sample_rate(const void *app, int32_t rate) {
   ffi_ensure_main_thread("[VST3] vst3_set_sample_rate");
 
   PluginInstance *vst = (PluginInstance *)app;
@@ -300,15 +363,79 @@ void vst3_set_sample_rate(const void *app, int32_t rate) {
   vst->process_setup.sampleRate = rate;
 
   // [UI-thread & (Initialized | Connected)]]
-  vst->audio_processor->setupProcessing(vst->process_setup);
+  tresult res = vst->audio_processor->setupProcessing(vst->process_setup);
+  if (res != kResultOk) {
+    std::cerr << "Failed to change VST3 sample rate" << std::endl;
+    return false;
+  }
 
   // [UI-thread & Setup Done]
-  vst->component->setActive(true);
+  if (vst->component->setActive(true) != kResultOk) {
+    std::cerr << "Failed to activate VST3 plugin" << std::endl;
+    return false;
+  }
 
   // [(UI-thread or processing-thread) & Activated]
   vst->audio_processor->setProcessing(true);
 
   vst->process_data.processContext->sampleRate = rate;
+
+  return true;
+}
+
+bool supports_f64(const void *app) {
+  ffi_ensure_main_thread("[VST3] supports_f64");
+
+  PluginInstance *vst = (PluginInstance *)app;
+
+  // [UI-thread & (Initialized | Connected)]
+  return vst->audio_processor->canProcessSampleSize(kSample64) == kResultTrue;
+}
+
+bool vst3_set_sample_precision(const void *app, bool f64) {
+  ffi_ensure_main_thread("[VST3] vst3_set_sample_precision");
+
+  PluginInstance *vst = (PluginInstance *)app;
+
+  bool was_active = vst->active;
+  bool was_processing = vst->processing;
+
+  // [(UI-thread or processing-thread) & Activated]
+  if (was_processing) {
+    vst->audio_processor->setProcessing(false);
+  }
+
+  // [UI-thread & Setup Done]
+  if (was_active) {
+    vst->component->setActive(false);
+  }
+
+  int32 previous = vst->process_setup.symbolicSampleSize;
+  vst->process_setup.symbolicSampleSize = f64 ? kSample64 : kSample32;
+
+  // [UI-thread & (Initialized | Connected)]]
+  tresult res = vst->audio_processor->setupProcessing(vst->process_setup);
+  if (res != kResultOk) {
+    std::cerr << "Failed to change VST3 sample size" << std::endl;
+    vst->process_setup.symbolicSampleSize = previous;
+    vst->audio_processor->setupProcessing(vst->process_setup);
+  }
+
+  // The channel buffers are supplied by the host in every process call so only
+  // the sample size needs updating here.
+  vst->process_data.symbolicSampleSize = vst->process_setup.symbolicSampleSize;
+
+  // [UI-thread & Setup Done]
+  if (was_active) {
+    vst->component->setActive(true);
+  }
+
+  // [(UI-thread or processing-thread) & Activated]
+  if (was_processing) {
+    vst->audio_processor->setProcessing(true);
+  }
+
+  return res == kResultOk;
 }
 
 const void *get_data(const void *app, int32_t *data_len, const void **stream) {
@@ -360,11 +487,11 @@ void free_data_stream(const void *stream) {
   delete stream_;
 }
 
-void set_data(const void *app, const void *data, int32_t data_len) {
+bool set_data(const void *app, const void *data, int32_t data_len) {
   ffi_ensure_main_thread("[VST3] set_data");
 
   if (data_len == 0)
-    return;
+    return true;
 
   // https://steinbergmedia.github.io/vst3_dev_portal/pages/Technical+Documentation/API+Documentation/Index.html#persistence
 
@@ -386,6 +513,7 @@ void set_data(const void *app, const void *data, int32_t data_len) {
   // Processing)]
   if (vst->component->setState(&stream) != kResultOk) {
     std::cerr << "Failed to set processor state" << std::endl;
+    return false;
   }
 
   stream.rewind();
@@ -394,13 +522,15 @@ void set_data(const void *app, const void *data, int32_t data_len) {
   if (vst->edit_controller->setComponentState(&stream) != kResultOk) {
     std::cerr << "Failed to set processor state in controller" << std::endl;
   }
+
+  return true;
 }
 
-void set_controller_data(const void *app, const void *data, int32_t data_len) {
+bool set_controller_data(const void *app, const void *data, int32_t data_len) {
   ffi_ensure_main_thread("[VST3] set_controller_data");
 
   if (data_len == 0)
-    return;
+    return true;
 
   // https://steinbergmedia.github.io/vst3_dev_portal/pages/Technical+Documentation/API+Documentation/Index.html#persistence
 
@@ -417,33 +547,173 @@ void set_controller_data(const void *app, const void *data, int32_t data_len) {
   // [UI-thread & Connected]
   if (vst->edit_controller->setState(&stream) != kResultOk) {
     std::cout << "Failed to set controller state" << std::endl;
+    return false;
+  }
+
+  return true;
+}
+
+// Semitones of the MIDI 2.0 per-note pitch bend range.
+static const double kPerNotePitchBendRange = 48.;
+
+static double ump_normalized(uint32_t value) {
+  return (double)value / (double)UINT32_MAX;
+}
+
+// Sets the parameter the plugin maps to the controller through `IMidiMapping`.
+static void add_midi_mapped_point(PluginInstance *vst, MidiCC cc, int32_t time,
+                                  double value) {
+  auto mapping = vst->midi_cc_mappings.find(cc.as_key());
+  if (mapping == vst->midi_cc_mappings.end())
+    return;
+
+  int32 queue_index = 0;
+  auto queue = vst->process_data.inputParameterChanges->addParameterData(
+      mapping->second, queue_index);
+  if (queue == nullptr)
+    return;
+
+  int32 point_index = 0;
+  queue->addPoint(time, value, point_index);
+}
+
+// Translates a MIDI 2.0 channel voice message. UMP has no note ids so notes
+// are identified by channel and key.
+static void add_ump_event(PluginInstance *vst, EventList *eventList,
+                          Steinberg::Vst::Event evt, const Ump &ump) {
+  uint32_t word = ump.data[0];
+  if ((word >> 28) != Ump_MIDI2_CHANNEL_VOICE)
+    return;
+
+  uint8_t status = (word >> 20) & 0xF;
+  int16_t channel = (word >> 16) & 0xF;
+  int16_t key = (word >> 8) & 0x7F;
+  uint8_t per_note_index = word & 0xFF;
+  uint32_t value = ump.data[1];
+  int32_t note_id = (channel << 7) | key;
+
+  evt.type = Steinberg::Vst::Event::EventTypes::kNoteExpressionValueEvent;
+  evt.noteExpressionValue.noteId = note_id;
+  evt.noteExpressionValue.value = ump_normalized(value);
+
+  switch (status) {
+  case Ump_NOTE_ON:
+    evt.type = Steinberg::Vst::Event::EventTypes::kNoteOnEvent;
+    evt.noteOn.channel = channel;
+    evt.noteOn.pitch = key;
+    evt.noteOn.tuning = 0;
+    evt.noteOn.velocity = (float)(value >> 16) / 65535.f;
+    evt.noteOn.length = 0;
+    evt.noteOn.noteId = note_id;
+    eventList->addEvent(evt);
+    break;
+  case Ump_NOTE_OFF:
+    evt.type = Steinberg::Vst::Event::EventTypes::kNoteOffEvent;
+    evt.noteOff.channel = channel;
+    evt.noteOff.pitch = key;
+    evt.noteOff.tuning = 0;
+    evt.noteOff.velocity = (float)(value >> 16) / 65535.f;
+    evt.noteOff.noteId = note_id;
+    eventList->addEvent(evt);
+    break;
+  case Ump_POLY_PRESSURE:
+    evt.type = Steinberg::Vst::Event::EventTypes::kPolyPressureEvent;
+    evt.polyPressure.channel = channel;
+    evt.polyPressure.pitch = key;
+    evt.polyPressure.pressure = (float)ump_normalized(value);
+    evt.polyPressure.noteId = note_id;
+    eventList->addEvent(evt);
+    break;
+  case Ump_PER_NOTE_PITCH_BEND:
+    // The tuning expression covers +-120 semitones.
+    evt.noteExpressionValue.typeId =
+        Steinberg::Vst::NoteExpressionTypeIDs::kTuningTypeID;
+    evt.noteExpressionValue.value =
+        0.5 + (ump_normalized(value) - 0.5) * kPerNotePitchBendRange / 120.;
+    eventList->addEvent(evt);
+    break;
+  case Ump_REGISTERED_PER_NOTE_CONTROLLER:
+    switch (per_note_index) {
+    case 1:
+      evt.noteExpressionValue.typeId =
+          Steinberg::Vst::NoteExpressionTypeIDs::kVibratoTypeID;
+      break;
+    case 7:
+      evt.noteExpressionValue.typeId =
+          Steinberg::Vst::NoteExpressionTypeIDs::kVolumeTypeID;
+      break;
+    case 10:
+      evt.noteExpressionValue.typeId =
+          Steinberg::Vst::NoteExpressionTypeIDs::kPanTypeID;
+      break;
+    case 11:
+      evt.noteExpressionValue.typeId =
+          Steinberg::Vst::NoteExpressionTypeIDs::kExpressionTypeID;
+      break;
+    case 74:
+      evt.noteExpressionValue.typeId =
+          Steinberg::Vst::NoteExpressionTypeIDs::kBrightnessTypeID;
+      break;
+    default:
+      return;
+    }
+    eventList->addEvent(evt);
+    break;
+  case Ump_ASSIGNABLE_PER_NOTE_CONTROLLER:
+    evt.noteExpressionValue.typeId =
+        Steinberg::Vst::NoteExpressionTypeIDs::kCustomStart + per_note_index;
+    eventList->addEvent(evt);
+    break;
+  case Ump_CONTROL_CHANGE:
+    add_midi_mapped_point(vst, {0, channel, key}, evt.sampleOffset,
+                          ump_normalized(value));
+    break;
+  case Ump_CHANNEL_PRESSURE:
+    add_midi_mapped_point(vst, {0, channel, Steinberg::Vst::kAfterTouch},
+                          evt.sampleOffset, ump_normalized(value));
+    break;
+  case Ump_PITCH_BEND:
+    add_midi_mapped_point(vst, {0, channel, Steinberg::Vst::kPitchBend},
+                          evt.sampleOffset, ump_normalized(value));
+    break;
+  default:
+    break;
   }
 }
 
-void process(const void *app, const ProcessDetails *data, float ***input,
-             float ***output, HostIssuedEvent *events, int32_t events_len) {
+bool process(const void *app, const ProcessDetails *data, void ***input,
+             void ***output, HostIssuedEvent *events, int32_t events_len,
+             void *output_events, int32_t output_offset) {
   ffi_ensure_non_main_thread("[VST3] process");
   PluginInstance *vst = (PluginInstance *)app;
 
-  auto audio_inputs = vst->_io_config.audio_inputs.count;
-  auto audio_outputs = vst->_io_config.audio_outputs.count;
+  auto audio_inputs = vst->_numInAudioBuses;
+  auto audio_outputs = vst->_numOutAudioBuses;
 
   vst->process_data.numSamples = data->block_size;
 
   for (int i = 0; i < audio_inputs; i++) {
     vst->process_data.inputs[i].numChannels =
-        vst->_io_config.audio_inputs.data[i].value.channels;
+        vst->_inAudioBusInfos[i].channelCount;
     vst->process_data.inputs[i].silenceFlags = 0;
-    vst->process_data.inputs[i].channelBuffers32 = input[i];
+    if (vst->process_setup.symbolicSampleSize == kSample64) {
+      vst->process_data.inputs[i].channelBuffers64 = (Sample64 **)input[i];
+    } else {
+      vst->process_data.inputs[i].channelBuffers32 = (Sample32 **)input[i];
+    }
   }
 
   vst->process_data.numInputs = audio_inputs;
 
   for (int i = 0; i < audio_outputs; i++) {
     vst->process_data.outputs[i].numChannels =
-        vst->_io_config.audio_outputs.data[i].value.channels;
+        vst->_outAudioBusInfos[i].channelCount;
     vst->process_data.outputs[i].silenceFlags = 0;
-    vst->process_data.outputs[i].channelBuffers32 = output[i];
+    if (vst->process_setup.symbolicSampleSize == kSample64) {
+      vst->process_data.outputs[i].channelBuffers64 = (Sample64 **)output[i];
+    } else {
+      vst->process_data.outputs[i].channelBuffers32 = (Sample32 **)output[i];
+    }
   }
 
   vst->process_data.numOutputs = audio_outputs;
@@ -503,17 +773,15 @@ void process(const void *app, const ProcessDetails *data, float ***input,
   int midi_bus = 0;
   Steinberg::Vst::EventList *eventList = nullptr;
 
-  if (!vst->process_data.inputParameterChanges) {
-    vst->process_data.inputParameterChanges = new ParameterChanges(400);
-  }
-
-  if (vst->_io_config.event_inputs_count > 0) {
+  if (vst->_numInEventBuses > 0) {
     eventList = vst->eventList(Steinberg::Vst::kInput, midi_bus);
 
     for (int i = 0; i < events_len; i++) {
       auto tag = events[i].event_type.tag;
       if (tag != HostIssuedEventType::Tag::Midi &&
-          tag != HostIssuedEventType::Tag::NoteExpression)
+          tag != HostIssuedEventType::Tag::NoteExpression &&
+          tag != HostIssuedEventType::Tag::SysEx &&
+          tag != HostIssuedEventType::Tag::Midi2)
         continue;
 
       Steinberg::Vst::Event evt = {};
@@ -526,17 +794,31 @@ void process(const void *app, const ProcessDetails *data, float ***input,
       }
 
       if (tag == HostIssuedEventType::Tag::NoteExpression) {
+        const auto &expression = events[i].event_type.note_expression;
+
+        // VST3 has no pressure expression, poly pressure is the closest.
+        if (expression.expression_type == NoteExpressionType::Pressure) {
+          if (expression.key >= 0) {
+            evt.type = Steinberg::Vst::Event::EventTypes::kPolyPressureEvent;
+            evt.polyPressure.channel = expression.channel < 0 ? 0 : expression.channel;
+            evt.polyPressure.pitch = expression.key;
+            evt.polyPressure.pressure = (float)expression.value;
+            evt.polyPressure.noteId = expression.note_id;
+            eventList->addEvent(evt);
+          }
+          continue;
+        }
+
         evt.type = Steinberg::Vst::Event::EventTypes::kNoteExpressionValueEvent;
-        evt.noteExpressionValue.value =
-            (Steinberg::Vst::NoteExpressionValue)events[i]
-                .event_type.note_expression.value;
-        evt.noteExpressionValue.noteId =
-            (int32_t)events[i].event_type.note_expression.note_id;
+        evt.noteExpressionValue.noteId = expression.note_id;
+        evt.noteExpressionValue.value = expression.value;
 
-        switch (events[i].event_type.note_expression.expression_type) {
+        // Converts from the units in `NoteExpressionType` to normalized values.
+        switch (expression.expression_type) {
         case NoteExpressionType::Volume:
           evt.noteExpressionValue.typeId =
               Steinberg::Vst::NoteExpressionTypeIDs::kVolumeTypeID;
+          evt.noteExpressionValue.value = expression.value / 4.;
           break;
         case NoteExpressionType::Pan:
           evt.noteExpressionValue.typeId =
@@ -545,6 +827,7 @@ void process(const void *app, const ProcessDetails *data, float ***input,
         case NoteExpressionType::Tuning:
           evt.noteExpressionValue.typeId =
               Steinberg::Vst::NoteExpressionTypeIDs::kTuningTypeID;
+          evt.noteExpressionValue.value = 0.5 + expression.value / 240.;
           break;
         case NoteExpressionType::Vibrato:
           evt.noteExpressionValue.typeId =
@@ -558,19 +841,39 @@ void process(const void *app, const ProcessDetails *data, float ***input,
           evt.noteExpressionValue.typeId =
               Steinberg::Vst::NoteExpressionTypeIDs::kExpressionTypeID;
           break;
+        case NoteExpressionType::Pressure:
+          break;
         }
 
         eventList->addEvent(evt);
       }
 
+      if (tag == HostIssuedEventType::Tag::SysEx) {
+        // Points at memory owned by the Rust side which outlives this call.
+        evt.type = Steinberg::Vst::Event::EventTypes::kDataEvent;
+        evt.data.size = (uint32)events[i].event_type.sys_ex._0.len;
+        evt.data.type = Steinberg::Vst::DataEvent::DataTypes::kMidiSysEx;
+        evt.data.bytes = events[i].event_type.sys_ex._0.data;
+        eventList->addEvent(evt);
+      }
+
+      if (tag == HostIssuedEventType::Tag::Midi2) {
+        add_ump_event(vst, eventList, evt, events[i].event_type.midi2._0);
+      }
+
       if (tag == HostIssuedEventType::Tag::Midi) {
-        bool is_note_on = events[i].event_type.midi._0.midi_data[0] == 0x90;
-        bool is_note_off = events[i].event_type.midi._0.midi_data[0] == 0x80;
-        bool is_pitch_bend = events[i].event_type.midi._0.midi_data[0] == 0xE0;
+        uint8_t status = events[i].event_type.midi._0.midi_data[0] & 0xF0;
+        int16_t channel = events[i].event_type.midi._0.midi_data[0] & 0x0F;
+        uint8_t velocity = events[i].event_type.midi._0.midi_data[2];
+
+        // A note on with velocity 0 is a note off.
+        bool is_note_on = status == 0x90 && velocity > 0;
+        bool is_note_off = status == 0x80 || (status == 0x90 && velocity == 0);
+        bool is_pitch_bend = status == 0xE0;
 
         if (is_note_on) {
           evt.type = Steinberg::Vst::Event::EventTypes::kNoteOnEvent;
-          evt.noteOn.channel = 0;
+          evt.noteOn.channel = channel;
           evt.noteOn.pitch = events[i].event_type.midi._0.midi_data[1];
           evt.noteOn.tuning = events[i].event_type.midi._0.detune;
           evt.noteOn.velocity =
@@ -580,7 +883,7 @@ void process(const void *app, const ProcessDetails *data, float ***input,
           eventList->addEvent(evt);
         } else if (is_note_off) {
           evt.type = Steinberg::Vst::Event::EventTypes::kNoteOffEvent;
-          evt.noteOff.channel = 0;
+          evt.noteOff.channel = channel;
           evt.noteOff.pitch = events[i].event_type.midi._0.midi_data[1];
           evt.noteOff.tuning = events[i].event_type.midi._0.detune;
           evt.noteOff.velocity =
@@ -588,7 +891,7 @@ void process(const void *app, const ProcessDetails *data, float ***input,
           evt.noteOff.noteId = events[i].event_type.midi._0.note_id;
           eventList->addEvent(evt);
         } else if (is_pitch_bend) {
-          MidiCC cc = {0, 0, 129};
+          MidiCC cc = {0, channel, Steinberg::Vst::kPitchBend};
           if (vst->midi_cc_mappings.find(cc.as_key()) !=
               vst->midi_cc_mappings.end()) {
             ParamID id = vst->midi_cc_mappings[cc.as_key()];
@@ -617,9 +920,6 @@ void process(const void *app, const ProcessDetails *data, float ***input,
           evt.data.size = 3;
           evt.data.type = Steinberg::Vst::DataEvent::DataTypes::kMidiSysEx;
           evt.data.bytes = events[i].event_type.midi._0.midi_data;
-          std::cout << (int)events[i].event_type.midi._0.midi_data[1] << " "
-                    << (int)events[i].event_type.midi._0.midi_data[2]
-                    << std::endl;
           eventList->addEvent(evt);
         }
       }
@@ -650,13 +950,80 @@ void process(const void *app, const ProcessDetails *data, float ***input,
 
   // [processing-thread & Processing]
   tresult result = vst->audio_processor->process(vst->process_data);
-  if (result != kResultOk) {
-    std::cout << "Failed to process" << std::endl;
-  }
-
   if (eventList) {
     eventList->clear();
   }
+
+  for (int bus = 0; bus < vst->_numOutEventBuses; bus++) {
+    Steinberg::Vst::EventList *outputList =
+        vst->eventList(Steinberg::Vst::kOutput, bus);
+
+    for (int i = 0; i < outputList->getEventCount(); i++) {
+      Steinberg::Vst::Event evt = {};
+      if (outputList->getEvent(i, evt) != kResultOk)
+        continue;
+
+      int32_t time = output_offset + evt.sampleOffset;
+
+      switch (evt.type) {
+      case Steinberg::Vst::Event::EventTypes::kNoteOnEvent:
+        push_note_output(output_events, time, bus, true, evt.noteOn.channel,
+                         evt.noteOn.pitch, evt.noteOn.velocity,
+                         evt.noteOn.noteId);
+        break;
+      case Steinberg::Vst::Event::EventTypes::kNoteOffEvent:
+        push_note_output(output_events, time, bus, false, evt.noteOff.channel,
+                         evt.noteOff.pitch, evt.noteOff.velocity,
+                         evt.noteOff.noteId);
+        break;
+      case Steinberg::Vst::Event::EventTypes::kDataEvent:
+        if (evt.data.type == Steinberg::Vst::DataEvent::DataTypes::kMidiSysEx) {
+          push_sysex_output(output_events, time, bus, evt.data.bytes,
+                            evt.data.size);
+        }
+        break;
+      case Steinberg::Vst::Event::EventTypes::kLegacyMIDICCOutEvent: {
+        const auto &cc = evt.midiCCOut;
+        uint8_t channel = cc.channel & 0x0F;
+        uint8_t value = cc.value & 0x7F;
+        uint8_t value2 = cc.value2 & 0x7F;
+
+        switch (cc.controlNumber) {
+        case Steinberg::Vst::kAfterTouch:
+          push_midi_output(output_events, time, bus, 0xD0 | channel, value, 0);
+          break;
+        case Steinberg::Vst::kPitchBend:
+          push_midi_output(output_events, time, bus, 0xE0 | channel, value,
+                           value2);
+          break;
+        case Steinberg::Vst::kCtrlProgramChange:
+          push_midi_output(output_events, time, bus, 0xC0 | channel, value, 0);
+          break;
+        case Steinberg::Vst::kCtrlPolyPressure:
+          push_midi_output(output_events, time, bus, 0xA0 | channel, value,
+                           value2);
+          break;
+        default:
+          if (cc.controlNumber < 128) {
+            push_midi_output(output_events, time, bus, 0xB0 | channel,
+                             cc.controlNumber, value);
+          }
+          break;
+        }
+        break;
+      }
+      default:
+        break;
+      }
+    }
+
+    outputList->clear();
+  }
+
+  static_cast<ParameterChanges *>(vst->process_data.inputParameterChanges)
+      ->clearQueue();
+
+  return result == kResultOk;
 }
 
 void set_track_details(const void *app, const Track *details) {
@@ -694,7 +1061,7 @@ void set_track_details(const void *app, const Track *details) {
   track_info_listener->setChannelContextInfos(list);
 }
 
-void set_param_in_edit_controller(const void *app, int32_t id, float value) {
+void set_param_in_edit_controller(const void *app, ParamId id, float value) {
   PluginInstance *vst = (PluginInstance *)app;
 
   // Takes param id
@@ -705,9 +1072,47 @@ void set_param_in_edit_controller(const void *app, int32_t id, float value) {
 
 void free_string(const char *str) { delete[] str; }
 
-Parameter get_parameter(const void *app, int32_t index) {
-  // TODO: sort out naming confusion with id and index
+static std::string tchar_to_string(const TChar *str) {
+  std::string result = {};
+  for (int i = 0; str[i] != '\0'; i++) {
+    result += (char)str[i];
+  }
+  return result;
+}
+
+// Names of the unit and its parents separated by '/', empty for the root unit.
+static std::string unit_path(PluginInstance *vst, UnitID unit_id) {
+  IUnitInfo *unit_info = nullptr;
+  vst->edit_controller->queryInterface(IUnitInfo::iid, (void **)&unit_info);
+  if (unit_info == nullptr)
+    return {};
+
+  std::string path = {};
+  int32 count = unit_info->getUnitCount();
+
+  // Bounded by the unit count in case the plugin's tree has a cycle.
+  for (int32 depth = 0; depth < count && unit_id != kRootUnitId &&
+                        unit_id != kNoParentUnitId;
+       depth++) {
+    UnitInfo info = {};
+    bool found = false;
+    for (int32 i = 0; i < count && !found; i++) {
+      found = unit_info->getUnitInfo(i, info) == kResultOk && info.id == unit_id;
+    }
+    if (!found)
+      break;
 
+    std::string name = tchar_to_string(info.name);
+    path = path.empty() ? name : name + "/" + path;
+    unit_id = info.parentUnitId;
+  }
+
+  unit_info->release();
+  return path;
+}
+
+FFIParameter get_parameter(const void *app, int32_t index,
+                           void *value_names) {
   ffi_ensure_main_thread("[VST3] get_parameter");
 
   PluginInstance *vst = (PluginInstance *)app;
@@ -716,16 +1121,9 @@ Parameter get_parameter(const void *app, int32_t index) {
   // Takes index
   vst->edit_controller->getParameterInfo(index, param_info);
 
-  vst->component_handler->parameter_indicies[param_info.id] = index;
-
   // TODO: Make real-time safe with stack buffers
 
-  std::string name = {};
-  for (TChar c : param_info.title) {
-    if (c != '\0') {
-      name += c;
-    }
-  }
+  std::string name = tchar_to_string(param_info.title);
 
   Steinberg::Vst::ParamValue value =
       vst->edit_controller->getParamNormalized(param_info.id);
@@ -736,14 +1134,9 @@ Parameter get_parameter(const void *app, int32_t index) {
     std::cout << "Failed to get parameter value by string" << std::endl;
   }
 
-  std::string formatted_value_c_str = {};
-  for (TChar c : formatted_value) {
-    if (c != '\0') {
-      formatted_value_c_str += c;
-    }
-  }
+  std::string formatted_value_c_str = tchar_to_string(formatted_value);
 
-  Parameter param = {};
+  FFIParameter param = {};
   param.id = param_info.id;
   param.index = index;
   param.value = (float)value;
@@ -757,16 +1150,95 @@ Parameter get_parameter(const void *app, int32_t index) {
   param.hidden = (param_info.flags & ParameterInfo::kIsHidden) != 0;
   param.can_automate = (param_info.flags & ParameterInfo::kCanAutomate) != 0;
   param.read_only = (param_info.flags & ParameterInfo::kIsReadOnly) != 0;
+  param.is_bypass = (param_info.flags & ParameterInfo::kIsBypass) != 0;
 
   param.default_value = (float)param_info.defaultNormalizedValue;
 
+  param.min_value =
+      vst->edit_controller->normalizedParamToPlain(param_info.id, 0.);
+  param.max_value =
+      vst->edit_controller->normalizedParamToPlain(param_info.id, 1.);
+  param.step_count = param_info.stepCount;
+  param.unit = alloc_string(tchar_to_string(param_info.units).c_str());
+  param.group = alloc_string(unit_path(vst, param_info.unitId).c_str());
+
+  if ((param_info.flags & ParameterInfo::kIsList) != 0 &&
+      param_info.stepCount > 0) {
+    for (int32 step = 0; step <= param_info.stepCount; step++) {
+      TChar step_name[128] = {};
+      vst->edit_controller->getParamStringByValue(
+          param_info.id, (ParamValue)step / param_info.stepCount, step_name);
+      push_parameter_value_name(value_names,
+                                tchar_to_string(step_name).c_str());
+    }
+  }
+
   return param;
 }
 
-IOConfigutaion io_config(const void *app) {
+ParamId get_parameter_id(const void *app, int32_t index) {
+  ffi_ensure_main_thread("[VST3] get_parameter_id");
+
+  PluginInstance *vst = (PluginInstance *)app;
+
+  ParameterInfo param_info = {};
+  // Takes index
+  vst->edit_controller->getParameterInfo(index, param_info);
+
+  return param_info.id;
+}
+
+double get_parameter_value(const void *app, ParamId id) {
+  ffi_ensure_main_thread("[VST3] get_parameter_value");
+
+  PluginInstance *vst = (PluginInstance *)app;
+
+  // Takes param id
+  return vst->edit_controller->getParamNormalized(id);
+}
+
+const char *format_parameter_value(const void *app, ParamId id,
+                                   double value) {
+  ffi_ensure_main_thread("[VST3] format_parameter_value");
+
+  PluginInstance *vst = (PluginInstance *)app;
+
+  // Takes param id
+  TChar formatted_value[128] = {};
+  if (vst->edit_controller->getParamStringByValue(id, value, formatted_value) !=
+      kResultOk) {
+    return nullptr;
+  }
+
+  return alloc_string(tchar_to_string(formatted_value).c_str());
+}
+
+bool parse_parameter_text(const void *app, ParamId id, const char *text,
+                          double *value) {
+  ffi_ensure_main_thread("[VST3] parse_parameter_text");
+
+  PluginInstance *vst = (PluginInstance *)app;
+
+  TChar tchar_text[128] = {};
+  for (int i = 0; i < 127 && text[i] != '\0'; i++) {
+    tchar_text[i] = (TChar)text[i];
+  }
+
+  // Takes param id
+  ParamValue normalized = 0.;
+  if (vst->edit_controller->getParamValueByString(id, tchar_text,
+                                                  normalized) != kResultOk) {
+    return false;
+  }
+
+  *value = normalized;
+  return true;
+}
+
+void io_config(const void *app, IOConfigutaion *config) {
   PluginInstance *vst = (PluginInstance *)app;
 
-  return vst->get_io_config();
+  vst->get_io_config(config);
 }
 
 uintptr_t parameter_count(const void *app) {
@@ -785,6 +1257,7 @@ void unload(const void *app) {
   auto vst = (PluginInstance *)app;
 
   vst->component->setActive(false);
+  vst->active = false;
 
   if (vst->iConnectionPointComponent && vst->iConnectionPointController) {
     vst->iConnectionPointComponent->disconnect(vst->iConnectionPointController);